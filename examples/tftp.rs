@@ -23,7 +23,7 @@ use smolapps::{
     net::socket::{SocketSet, UdpPacketMetadata, UdpSocketBuffer},
     net::time::Instant,
     net::wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address},
-    tftp::{Context, Handle, Server},
+    tftp::{self, Context, Handle, Server},
 };
 use std::{
     collections::BTreeMap,
@@ -61,6 +61,10 @@ impl Handle for File {
     fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
         self.0.write(buf).map_err(|_| ())
     }
+
+    fn size(&self) -> Option<u64> {
+        self.0.metadata().ok().map(|metadata| metadata.len())
+    }
 }
 
 fn main() {
@@ -89,8 +93,14 @@ fn main() {
 
     let mut tftp = Server::new(
         &mut sockets,
-        UdpSocketBuffer::new([UdpPacketMetadata::EMPTY; 2], vec![0; 1032]),
-        UdpSocketBuffer::new([UdpPacketMetadata::EMPTY; 2], vec![0; 1032]),
+        UdpSocketBuffer::new(
+            vec![UdpPacketMetadata::EMPTY; tftp::required_rx_packets()],
+            vec![0; tftp::required_rx_size(512)],
+        ),
+        UdpSocketBuffer::new(
+            vec![UdpPacketMetadata::EMPTY; tftp::required_tx_packets(1)],
+            vec![0; tftp::required_tx_size(512, 1)],
+        ),
         Instant::now(),
     );
 
@@ -0,0 +1,271 @@
+/*! Example of a TFTP server running over a SLIP serial link.
+
+This shows the bring-up path of a microcontroller attached to a PC through a UART:
+IP packets are framed with SLIP (RFC 1055), and the server is driven with raw datagrams
+through `Server::process_datagram()` and `Server::process_timeouts()`, without any
+smoltcp interface or socket.
+
+On Linux, attach the other end of the serial line to a SLIP interface:
+
+```no_rust
+sudo slattach -L -p slip -s 115200 /dev/ttyUSB1 &
+sudo ip addr add 192.168.70.100 peer 192.168.70.1 dev sl0
+sudo ip link set sl0 up
+```
+
+Without any hardware, a pair of virtual serial ports can be created with
+`socat -d -d pty,raw,echo=0 pty,raw,echo=0`, attaching `slattach` to one of them.
+Finally, run the example on the other one, previously set to raw mode with `stty -F <tty> raw`:
+
+```no_rust
+cargo run --example tftp_slip --features "std log" -- /dev/ttyUSB0
+```
+
+You should now be able to connect to `192.168.70.1:69` using a TFTP client
+and read/write files from/to your filesystem.
+*/
+
+#[macro_use]
+extern crate log;
+
+use env_logger::Env;
+use smolapps::{
+    net::phy::ChecksumCapabilities,
+    net::time::Instant,
+    net::wire::{IpAddress, IpEndpoint, IpProtocol, Ipv4Address, Ipv4Packet, Ipv4Repr},
+    net::wire::{UdpPacket, UdpRepr},
+    net::{self, Error},
+    tftp::{Context, Handle, Server, Transfers, DEFAULT_PORT},
+    transport::Transmit,
+};
+use std::{
+    env, fs,
+    io::{Read, Write},
+    sync::mpsc,
+    thread,
+};
+
+/// Address of the server on the serial link.
+const LOCAL_ADDR: Ipv4Address = Ipv4Address([192, 168, 70, 1]);
+
+/// SLIP special characters.
+const END: u8 = 0xc0;
+const ESC: u8 = 0xdb;
+const ESC_END: u8 = 0xdc;
+const ESC_ESC: u8 = 0xdd;
+
+struct RootFilesystem;
+
+impl Context for RootFilesystem {
+    type Handle = File;
+
+    fn open(&mut self, filename: &str, write_mode: bool) -> Result<Self::Handle, ()> {
+        fs::OpenOptions::new()
+            .read(true)
+            .write(write_mode)
+            .open(filename)
+            .map(File)
+            .map_err(|_| ())
+    }
+
+    fn close(&mut self, mut handle: Self::Handle) {
+        handle.0.flush().ok();
+    }
+}
+
+struct File(fs::File);
+
+impl Handle for File {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        self.0.read(buf).map_err(|_| ())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        self.0.write(buf).map_err(|_| ())
+    }
+
+    fn size(&self) -> Option<u64> {
+        self.0.metadata().ok().map(|metadata| metadata.len())
+    }
+}
+
+/// Reassembles the SLIP frames received over the serial line.
+#[derive(Default)]
+struct SlipDecoder {
+    frame: Vec<u8>,
+    escaped: bool,
+}
+
+impl SlipDecoder {
+    /// Feeds a received byte, returning the frame it completes, if any.
+    fn push(&mut self, byte: u8) -> Option<Vec<u8>> {
+        match (self.escaped, byte) {
+            (false, END) if self.frame.is_empty() => return None,
+            (false, END) => return Some(self.frame.split_off(0)),
+            (false, ESC) => {
+                self.escaped = true;
+                return None;
+            }
+            (true, ESC_END) => self.frame.push(END),
+            (true, ESC_ESC) => self.frame.push(ESC),
+            (_, byte) => self.frame.push(byte),
+        }
+        self.escaped = false;
+        None
+    }
+}
+
+/// Frames `packet` with SLIP, escaping the special characters it contains.
+fn slip_encode(packet: &[u8]) -> Vec<u8> {
+    let mut frame = vec![END];
+    for byte in packet {
+        match *byte {
+            END => frame.extend_from_slice(&[ESC, ESC_END]),
+            ESC => frame.extend_from_slice(&[ESC, ESC_ESC]),
+            byte => frame.push(byte),
+        }
+    }
+    frame.push(END);
+    frame
+}
+
+/// The UDP datagrams sent by the server, until they are framed and written to the line.
+#[derive(Default)]
+struct Outbox(Vec<(IpEndpoint, Vec<u8>)>);
+
+impl Transmit for Outbox {
+    fn send(&mut self, size: usize, endpoint: IpEndpoint) -> net::Result<&mut [u8]> {
+        self.0.push((endpoint, vec![0; size]));
+        Ok(&mut self.0.last_mut().unwrap().1)
+    }
+}
+
+/// Extracts the payload and source of a UDP datagram sent to the TFTP port.
+fn parse_datagram(frame: &[u8]) -> net::Result<(&[u8], IpEndpoint)> {
+    let caps = ChecksumCapabilities::default();
+
+    let ip_packet = Ipv4Packet::new_checked(frame)?;
+    let ip_repr = Ipv4Repr::parse(&ip_packet, &caps)?;
+    if ip_repr.dst_addr != LOCAL_ADDR || ip_repr.protocol != IpProtocol::Udp {
+        return Err(Error::Dropped);
+    }
+
+    let src_addr = IpAddress::Ipv4(ip_repr.src_addr);
+    let dst_addr = IpAddress::Ipv4(ip_repr.dst_addr);
+    let udp_packet = UdpPacket::new_checked(ip_packet.payload())?;
+    let udp_repr = UdpRepr::parse(&udp_packet, &src_addr, &dst_addr, &caps)?;
+    if udp_repr.dst_port != DEFAULT_PORT {
+        return Err(Error::Dropped);
+    }
+
+    Ok((
+        udp_repr.payload,
+        IpEndpoint::new(src_addr, udp_repr.src_port),
+    ))
+}
+
+/// Wraps a payload sent by the server into an IPv4 packet for `endpoint`.
+fn emit_datagram(payload: &[u8], endpoint: IpEndpoint) -> net::Result<Vec<u8>> {
+    let caps = ChecksumCapabilities::default();
+    let dst_addr = match endpoint.addr {
+        IpAddress::Ipv4(addr) => addr,
+        _ => return Err(Error::Unaddressable),
+    };
+
+    let udp_repr = UdpRepr {
+        src_port: DEFAULT_PORT,
+        dst_port: endpoint.port,
+        payload,
+    };
+    let ip_repr = Ipv4Repr {
+        src_addr: LOCAL_ADDR,
+        dst_addr,
+        protocol: IpProtocol::Udp,
+        payload_len: udp_repr.buffer_len(),
+        hop_limit: 64,
+    };
+
+    let mut bytes = vec![0; ip_repr.buffer_len() + udp_repr.buffer_len()];
+    let mut ip_packet = Ipv4Packet::new_unchecked(&mut bytes);
+    ip_repr.emit(&mut ip_packet, &caps);
+    udp_repr.emit(
+        &mut UdpPacket::new_unchecked(ip_packet.payload_mut()),
+        &LOCAL_ADDR.into(),
+        &endpoint.addr,
+        &caps,
+    );
+    Ok(bytes)
+}
+
+fn main() {
+    env_logger::from_env(Env::default().default_filter_or("trace")).init();
+
+    let path = env::args().nth(1).unwrap_or_else(|| "/dev/ttyUSB0".into());
+    let mut line = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+
+    // Read the line from a thread of its own, so that timeouts can be serviced meanwhile
+    let (rx_bytes, bytes) = mpsc::channel();
+    let mut reader = line.try_clone().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0; 256];
+        while let Ok(n) = reader.read(&mut buf) {
+            if n == 0 || rx_bytes.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut tftp = Server::detached(Instant::now());
+    let mut transfers: Transfers<_> = vec![].into();
+    let mut decoder = SlipDecoder::default();
+    let mut outbox = Outbox::default();
+
+    loop {
+        let timeout = tftp.next_poll(Instant::now());
+        let received = match bytes.recv_timeout(timeout.into()) {
+            Ok(received) => received,
+            Err(mpsc::RecvTimeoutError::Timeout) => vec![],
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        let timestamp = Instant::now();
+
+        for frame in received.into_iter().filter_map(|byte| decoder.push(byte)) {
+            let (payload, endpoint) = match parse_datagram(&frame) {
+                Ok(datagram) => datagram,
+                Err(e) => {
+                    debug!("Dropping frame: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = tftp.process_datagram(
+                &mut RootFilesystem,
+                &mut transfers,
+                payload,
+                endpoint,
+                timestamp,
+                &mut outbox,
+            ) {
+                error!("TFTP error: {}", e);
+            }
+        }
+
+        if let Err(e) =
+            tftp.process_timeouts(&mut RootFilesystem, &mut transfers, timestamp, &mut outbox)
+        {
+            error!("TFTP error: {}", e);
+        }
+
+        for (endpoint, payload) in outbox.0.drain(..) {
+            match emit_datagram(&payload, endpoint) {
+                Ok(packet) => line.write_all(&slip_encode(&packet)).unwrap(),
+                Err(e) => error!("Cannot send to {}: {}", endpoint, e),
+            }
+        }
+    }
+
+    error!("Serial line {} closed", path);
+}
@@ -0,0 +1,85 @@
+//! Civil time formatting, without depending on a date and time library.
+
+use core::fmt;
+
+const SECS_PER_DAY: i64 = 86_400;
+
+/// Writes the Unix time `unix_secs` + `nanos` as an RFC 3339 date and time in UTC,
+/// e.g. `2020-05-17T22:11:29.416Z`.
+///
+/// The fractional part is omitted if zero, otherwise it is written with millisecond,
+/// microsecond or nanosecond precision, whichever is the shortest exact one.
+/// Years are only guaranteed to be well-formed between 0000 and 9999.
+pub(crate) fn format_rfc3339<W: fmt::Write>(w: &mut W, unix_secs: i64, nanos: u32) -> fmt::Result {
+    let days = unix_secs.div_euclid(SECS_PER_DAY);
+    let secs = unix_secs.rem_euclid(SECS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+
+    write!(
+        w,
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )?;
+
+    match (nanos, nanos % 1_000_000, nanos % 1_000) {
+        (0, _, _) => (),
+        (_, 0, _) => write!(w, ".{:03}", nanos / 1_000_000)?,
+        (_, _, 0) => write!(w, ".{:06}", nanos / 1_000)?,
+        _ => write!(w, ".{:09}", nanos)?,
+    }
+    w.write_char('Z')
+}
+
+/// Converts a number of days since the Unix epoch to a (year, month, day) date
+/// of the proleptic Gregorian calendar.
+///
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Shift the epoch to 0000-03-01, so that leap days end each 400-year era
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::string::String;
+
+    fn rfc3339(unix_secs: i64, nanos: u32) -> String {
+        let mut s = String::new();
+        format_rfc3339(&mut s, unix_secs, nanos).unwrap();
+        s
+    }
+
+    #[test]
+    fn test_format_rfc3339() {
+        assert_eq!(rfc3339(0, 0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(-1, 0), "1969-12-31T23:59:59Z");
+        assert_eq!(rfc3339(951_782_400, 0), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1_709_251_199, 0), "2024-02-29T23:59:59Z");
+        assert_eq!(rfc3339(4_294_967_295, 0), "2106-02-07T06:28:15Z");
+    }
+
+    #[test]
+    fn test_format_rfc3339_fraction() {
+        assert_eq!(rfc3339(0, 500_000_000), "1970-01-01T00:00:00.500Z");
+        assert_eq!(rfc3339(0, 1_000), "1970-01-01T00:00:00.000001Z");
+        assert_eq!(
+            rfc3339(1_589_753_489, 416_228_804),
+            "2020-05-17T22:11:29.416228804Z"
+        );
+    }
+}
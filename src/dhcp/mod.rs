@@ -0,0 +1,503 @@
+//! DHCP information requests (DHCPINFORM), for devices with a static IP address.
+//!
+//! See https://tools.ietf.org/html/rfc2131#section-3.4 for the DHCPINFORM message.
+//! Unlike the full DHCP client shipped with smoltcp, no lease is ever requested:
+//! the server is only asked for the location of the NTP and TFTP servers.
+
+use crate::net::{
+    socket::{SocketHandle, SocketSet, UdpSocket, UdpSocketBuffer},
+    time::{Duration, Instant},
+    wire::{
+        DhcpMessageType, DhcpPacket, DhcpRepr, EthernetAddress, IpAddress, IpEndpoint, Ipv4Address,
+    },
+    {Error, Result},
+};
+use core::str;
+
+/// Interval before the first retransmission of a request, as per RFC 2131.
+const MIN_REQUEST_INTERVAL: Duration = Duration { millis: 4 * 1_000 };
+
+/// Maximum interval between retransmissions, as per RFC 2131.
+const MAX_REQUEST_INTERVAL: Duration = Duration { millis: 64 * 1_000 };
+
+/// IANA port for DHCP servers.
+const DHCP_SERVER_PORT: u16 = 67;
+
+/// IANA port for DHCP clients.
+const DHCP_CLIENT_PORT: u16 = 68;
+
+/// Pad option, see RFC 2132.
+const OPT_PAD: u8 = 0;
+
+/// NTP servers option, see RFC 2132.
+const OPT_NTP_SERVERS: u8 = 42;
+
+/// TFTP server name option, see RFC 2132.
+const OPT_TFTP_SERVER_NAME: u8 = 66;
+
+/// TFTP server address option, see RFC 5859.
+const OPT_TFTP_SERVERS: u8 = 150;
+
+/// End option, see RFC 2132.
+const OPT_END: u8 = 255;
+
+/// Options requested from the DHCP server.
+const PARAMETER_REQUEST_LIST: [u8; 3] = [OPT_NTP_SERVERS, OPT_TFTP_SERVER_NAME, OPT_TFTP_SERVERS];
+
+/// The addresses of the servers advertised by a DHCP server.
+///
+/// Only the first three addresses of each kind are retained.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// NTP servers (option 42), to be used by the SNTP client.
+    pub ntp_servers: [Option<Ipv4Address>; 3],
+    /// TFTP servers (option 150), or the TFTP server name (option 66)
+    /// if the former is missing and the latter is a dotted-decimal address.
+    pub tftp_servers: [Option<Ipv4Address>; 3],
+}
+
+impl ServerInfo {
+    /// Extracts the server addresses from the options of a DHCP packet.
+    ///
+    /// Returns `Err(Error::Truncated)` if an option extends past the end of the packet.
+    pub fn parse<T>(packet: &DhcpPacket<&T>) -> Result<ServerInfo>
+    where
+        T: AsRef<[u8]> + ?Sized,
+    {
+        let mut info = ServerInfo::default();
+        let mut tftp_server_name = None;
+
+        let mut options = packet.options()?;
+        while let Some((&kind, rest)) = options.split_first() {
+            match kind {
+                OPT_PAD => {
+                    options = rest;
+                    continue;
+                }
+                OPT_END => break,
+                _ => (),
+            }
+
+            let (&len, rest) = rest.split_first().ok_or(Error::Truncated)?;
+            let data = rest.get(..usize::from(len)).ok_or(Error::Truncated)?;
+            match kind {
+                OPT_NTP_SERVERS => fill_addresses(&mut info.ntp_servers, data),
+                OPT_TFTP_SERVERS => fill_addresses(&mut info.tftp_servers, data),
+                OPT_TFTP_SERVER_NAME => tftp_server_name = parse_address(data),
+                _ => (),
+            }
+            options = &rest[usize::from(len)..];
+        }
+
+        if info.tftp_servers[0].is_none() {
+            info.tftp_servers[0] = tftp_server_name;
+        }
+
+        Ok(info)
+    }
+}
+
+/// Fills `addrs` with the addresses contained in the data of an option.
+fn fill_addresses(addrs: &mut [Option<Ipv4Address>], data: &[u8]) {
+    for (addr, bytes) in addrs.iter_mut().zip(data.chunks_exact(4)) {
+        *addr = Some(Ipv4Address::from_bytes(bytes));
+    }
+}
+
+/// Parses a dotted-decimal address, optionally NULL-terminated.
+fn parse_address(data: &[u8]) -> Option<Ipv4Address> {
+    let len = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    str::from_utf8(&data[..len]).ok()?.parse().ok()
+}
+
+/// DHCP client sending information requests (DHCPINFORM).
+///
+/// You must call `InformClient::poll()` after `Interface::poll()` to send
+/// and receive DHCP packets. Requests are retransmitted with exponential backoff
+/// until a reply is received, after which the client stays idle until `InformClient::reset()`.
+pub struct InformClient {
+    udp_handle: SocketHandle,
+    client_ip: Ipv4Address,
+    hardware_addr: EthernetAddress,
+    server: Ipv4Address,
+    transaction_id: u32,
+    /// When to send next request, if a reply is still expected.
+    next_request: Option<Instant>,
+    /// Current retransmission interval.
+    curr_interval: Duration,
+}
+
+impl InformClient {
+    /// Creates a client for the interface configured with `client_ip` and `hardware_addr`.
+    ///
+    /// Requests are broadcast, unless a server is selected with `InformClient::set_server()`.
+    /// The `transaction_id` identifies the replies to this client, and should be random.
+    pub fn new<'a, 'b, 'c>(
+        sockets: &mut SocketSet<'a, 'b, 'c>,
+        rx_buffer: UdpSocketBuffer<'b, 'c>,
+        tx_buffer: UdpSocketBuffer<'b, 'c>,
+        client_ip: Ipv4Address,
+        hardware_addr: EthernetAddress,
+        transaction_id: u32,
+        now: Instant,
+    ) -> Self
+    where
+        'b: 'c,
+    {
+        let socket = UdpSocket::new(rx_buffer, tx_buffer);
+        let udp_handle = sockets.add(socket);
+
+        net_trace!("DHCP inform initialised");
+
+        InformClient {
+            udp_handle,
+            client_ip,
+            hardware_addr,
+            server: Ipv4Address::BROADCAST,
+            transaction_id,
+            next_request: Some(now),
+            curr_interval: MIN_REQUEST_INTERVAL,
+        }
+    }
+
+    /// Sets the address of the DHCP server to query, instead of broadcasting requests.
+    pub fn set_server(&mut self, server: Ipv4Address) {
+        self.server = server;
+    }
+
+    /// Returns the duration until the next packet request, if a reply is still expected.
+    ///
+    /// Useful for suspending execution after polling.
+    #[must_use]
+    pub fn next_poll(&self, now: Instant) -> Option<Duration> {
+        self.next_request.map(|next_request| {
+            if next_request > now {
+                next_request - now
+            } else {
+                Duration::from_millis(0)
+            }
+        })
+    }
+
+    /// Requests the server information again, e.g. after the network configuration has changed.
+    pub fn reset(&mut self, transaction_id: u32, now: Instant) {
+        self.transaction_id = transaction_id;
+        self.next_request = Some(now);
+        self.curr_interval = MIN_REQUEST_INTERVAL;
+    }
+
+    /// Processes incoming packets, and sends requests when timeouts expire.
+    ///
+    /// If a reply to the last request is received, the advertised servers are returned.
+    #[must_use = "the advertised servers are lost if the result is discarded"]
+    pub fn poll(&mut self, sockets: &mut SocketSet, now: Instant) -> Result<Option<ServerInfo>> {
+        let mut socket = sockets.get::<UdpSocket>(self.udp_handle);
+
+        // Bind the socket if necessary
+        if !socket.is_open() {
+            socket.bind(IpEndpoint {
+                addr: IpAddress::Unspecified,
+                port: DHCP_CLIENT_PORT,
+            })?;
+        }
+
+        // Process incoming packets
+        let info = match socket.recv() {
+            // Links looping back broadcasts could return our own packets
+            Ok((_, ep)) if ep.addr == IpAddress::from(self.client_ip) => {
+                net_debug!("DHCP pkt from own address ignored");
+                None
+            }
+            Ok((payload, _)) => {
+                net_trace_bytes!("DHCP recv", payload);
+                self.receive(payload)
+            }
+            Err(Error::Exhausted) => None,
+            Err(e) => return Err(e),
+        };
+
+        if info.is_some() {
+            self.next_request = None;
+            return Ok(info);
+        }
+
+        match self.next_request {
+            Some(next_request) if socket.can_send() && now >= next_request => {
+                self.request(&mut *socket)?;
+                self.next_request = Some(now + self.curr_interval);
+                self.curr_interval = MAX_REQUEST_INTERVAL.min(self.curr_interval * 2);
+            }
+            _ => (),
+        }
+
+        Ok(None)
+    }
+
+    /// Parses a reply, returning the advertised servers if it is meant for this client.
+    fn receive(&mut self, data: &[u8]) -> Option<ServerInfo> {
+        if self.next_request.is_none() {
+            net_debug!("DHCP unexpected reply");
+            return None;
+        }
+
+        let packet = match DhcpPacket::new_checked(data) {
+            Ok(packet) => packet,
+            Err(e) => {
+                net_debug!("DHCP invalid pkt: {:?}", e);
+                return None;
+            }
+        };
+        let repr = match DhcpRepr::parse(&packet) {
+            Ok(repr) => repr,
+            Err(e) => {
+                net_debug!("DHCP invalid pkt: {:?}", e);
+                return None;
+            }
+        };
+
+        if repr.message_type != DhcpMessageType::Ack || repr.transaction_id != self.transaction_id {
+            net_debug!("DHCP reply not meant for this client");
+            return None;
+        }
+
+        match ServerInfo::parse(&packet) {
+            Ok(info) => {
+                net_trace!("DHCP server info: {:?}", info);
+                Some(info)
+            }
+            Err(e) => {
+                net_debug!("DHCP invalid options: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// Returns the DHCPINFORM message sent by this client.
+    fn inform_repr(&self) -> DhcpRepr<'static> {
+        DhcpRepr {
+            message_type: DhcpMessageType::Inform,
+            transaction_id: self.transaction_id,
+            client_hardware_address: self.hardware_addr,
+            client_ip: self.client_ip,
+            your_ip: Ipv4Address::UNSPECIFIED,
+            server_ip: Ipv4Address::UNSPECIFIED,
+            router: None,
+            subnet_mask: None,
+            relay_agent_ip: Ipv4Address::UNSPECIFIED,
+            broadcast: false,
+            requested_ip: None,
+            client_identifier: Some(self.hardware_addr),
+            server_identifier: None,
+            parameter_request_list: Some(&PARAMETER_REQUEST_LIST),
+            dns_servers: None,
+            max_size: None,
+        }
+    }
+
+    /// Sends a DHCPINFORM request to the configured server.
+    fn request(&mut self, socket: &mut UdpSocket) -> Result<()> {
+        let repr = self.inform_repr();
+        let endpoint = IpEndpoint {
+            addr: self.server.into(),
+            port: DHCP_SERVER_PORT,
+        };
+
+        net_trace!("DHCP send inform to {}: {:?}", endpoint, repr);
+
+        let payload = socket.send(repr.buffer_len(), endpoint)?;
+        let mut packet = DhcpPacket::new_unchecked(payload);
+        repr.emit(&mut packet)?;
+        net_trace_bytes!("DHCP send", packet.into_inner());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::{self, Datagram, Network};
+    use std::vec::Vec;
+
+    const HW_ADDR: EthernetAddress = EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+
+    const XID: u32 = 0x1234_5678;
+
+    /// Builds a DHCPACK reply to an INFORM, carrying the given options.
+    fn ack_bytes(xid: u32, options: &[u8]) -> Vec<u8> {
+        let mut bytes = std::vec![0; 236];
+        bytes[..4].copy_from_slice(&[2, 1, 6, 0]); // BOOTREPLY over Ethernet
+        bytes[4..8].copy_from_slice(&xid.to_be_bytes());
+        bytes[12..16].copy_from_slice(&mock::LOCAL_ADDR.0); // ciaddr
+        bytes[28..34].copy_from_slice(&HW_ADDR.0);
+        bytes.extend_from_slice(&[0x63, 0x82, 0x53, 0x63]);
+        bytes.extend_from_slice(&[53, 1, 5]); // DHCPACK
+        bytes.extend_from_slice(&[54, 4, 10, 0, 0, 1]); // server identifier
+        bytes.extend_from_slice(options);
+        bytes.push(OPT_END);
+        bytes
+    }
+
+    fn client(sockets: &mut SocketSet<'static, 'static, 'static>) -> InformClient {
+        InformClient::new(
+            sockets,
+            mock::udp_buffer(1, 1024),
+            mock::udp_buffer(1, 1024),
+            mock::LOCAL_ADDR,
+            HW_ADDR,
+            XID,
+            Instant::from_millis(0),
+        )
+    }
+
+    #[test]
+    fn test_inform_packet() {
+        let mut sockets = mock::socket_set(1);
+        let client = client(&mut sockets);
+
+        let repr = client.inform_repr();
+        let mut bytes = std::vec![0xa5; repr.buffer_len()];
+        repr.emit(&mut DhcpPacket::new_unchecked(&mut bytes))
+            .unwrap();
+
+        // BOOTREQUEST with our address and no lease-related fields
+        assert_eq!(&bytes[..4], &[1, 1, 6, 0]);
+        assert_eq!(&bytes[4..8], &XID.to_be_bytes());
+        assert_eq!(&bytes[12..16], &mock::LOCAL_ADDR.0);
+        assert!(bytes[16..28].iter().all(|b| *b == 0));
+        assert_eq!(&bytes[28..34], &HW_ADDR.0);
+
+        let packet = DhcpPacket::new_checked(&bytes[..]).unwrap();
+        let parsed = DhcpRepr::parse(&packet).unwrap();
+        assert_eq!(parsed.message_type, DhcpMessageType::Inform);
+        assert_eq!(parsed.requested_ip, None);
+        assert_eq!(parsed.parameter_request_list, Some(&[42, 66, 150][..]));
+    }
+
+    #[test]
+    fn test_server_info() {
+        let bytes = ack_bytes(
+            XID,
+            &[
+                OPT_PAD, 42, 8, 10, 0, 0, 2, 10, 0, 0, 3, // NTP servers
+                66, 9, b'1', b'0', b'.', b'0', b'.', b'0', b'.', b'4', 0, // TFTP server name
+                150, 4, 10, 0, 0, 5, // TFTP servers
+            ],
+        );
+        let packet = DhcpPacket::new_checked(&bytes[..]).unwrap();
+        let info = ServerInfo::parse(&packet).unwrap();
+        assert_eq!(
+            info.ntp_servers,
+            [
+                Some(Ipv4Address::new(10, 0, 0, 2)),
+                Some(Ipv4Address::new(10, 0, 0, 3)),
+                None
+            ]
+        );
+        assert_eq!(
+            info.tftp_servers,
+            [Some(Ipv4Address::new(10, 0, 0, 5)), None, None]
+        );
+
+        // The TFTP server name is only used as a fallback, and only if it's an address
+        let bytes = ack_bytes(
+            XID,
+            &[66, 8, b'1', b'0', b'.', b'0', b'.', b'0', b'.', b'4'],
+        );
+        let packet = DhcpPacket::new_checked(&bytes[..]).unwrap();
+        let info = ServerInfo::parse(&packet).unwrap();
+        assert_eq!(
+            info.tftp_servers,
+            [Some(Ipv4Address::new(10, 0, 0, 4)), None, None]
+        );
+
+        let bytes = ack_bytes(XID, &[66, 4, b't', b'f', b't', b'p']);
+        let packet = DhcpPacket::new_checked(&bytes[..]).unwrap();
+        assert_eq!(ServerInfo::parse(&packet), Ok(ServerInfo::default()));
+
+        // Options running past the end of the packet
+        let mut bytes = ack_bytes(XID, &[42, 8, 10, 0, 0, 2]);
+        bytes.pop();
+        let packet = DhcpPacket::new_checked(&bytes[..]).unwrap();
+        assert_eq!(ServerInfo::parse(&packet), Err(Error::Truncated));
+    }
+
+    #[test]
+    fn test_poll() {
+        let mut net = Network::new();
+        let mut sockets = mock::socket_set(1);
+        let mut client = client(&mut sockets);
+        let server = mock::remote_ep(1, DHCP_SERVER_PORT);
+        client.set_server(Ipv4Address::new(10, 0, 0, 1));
+
+        let mut poll = |now: Instant, reply: Option<Vec<u8>>| {
+            if let Some(reply) = reply {
+                net.inject(server, mock::local_ep(DHCP_CLIENT_PORT), &reply);
+            }
+            net.poll(&mut sockets, now);
+            let info = client.poll(&mut sockets, now).unwrap();
+            net.poll(&mut sockets, now);
+            (info, net.transmitted())
+        };
+
+        let (info, sent) = poll(Instant::from_millis(0), None);
+        assert_eq!(info, None);
+        assert_eq!(sent.len(), 1);
+        let Datagram { dst, payload, .. } = &sent[0];
+        assert_eq!(*dst, server);
+        let packet = DhcpPacket::new_checked(&payload[..]).unwrap();
+        assert_eq!(DhcpRepr::parse(&packet).unwrap().transaction_id, XID);
+
+        // Replies to other transactions are ignored
+        let other = ack_bytes(XID + 1, &[42, 4, 10, 0, 0, 2]);
+        let (info, sent) = poll(Instant::from_millis(10), Some(other));
+        assert_eq!(info, None);
+        assert!(sent.is_empty());
+
+        let reply = ack_bytes(XID, &[42, 4, 10, 0, 0, 2]);
+        let (info, _) = poll(Instant::from_millis(20), Some(reply));
+        assert_eq!(
+            info.unwrap().ntp_servers[0],
+            Some(Ipv4Address::new(10, 0, 0, 2))
+        );
+
+        // No further requests once answered
+        let (_, sent) = poll(Instant::from_secs(60), None);
+        assert!(sent.is_empty());
+    }
+
+    #[test]
+    fn test_next_poll() {
+        let mut sockets = mock::socket_set(1);
+        let client = client(&mut sockets);
+
+        // A request is due since creation, however late the first poll
+        let zero = Duration::from_millis(0);
+        assert_eq!(client.next_poll(Instant::from_millis(0)), Some(zero));
+        assert_eq!(client.next_poll(Instant::from_secs(10)), Some(zero));
+    }
+
+    #[test]
+    fn test_own_packets_ignored() {
+        let mut net = Network::new();
+        let mut sockets = mock::socket_set(1);
+        let mut client = client(&mut sockets);
+        let now = Instant::from_millis(0);
+
+        assert_eq!(client.poll(&mut sockets, now).unwrap(), None);
+        net.poll(&mut sockets, now);
+        assert_eq!(net.transmitted().len(), 1);
+
+        // A valid reply that appears to come from ourselves is not trusted
+        let reply = ack_bytes(XID, &[42, 4, 10, 0, 0, 2]);
+        let own = mock::local_ep(DHCP_SERVER_PORT);
+        net.inject(own, mock::local_ep(DHCP_CLIENT_PORT), &reply);
+        net.poll(&mut sockets, now);
+        assert_eq!(client.poll(&mut sockets, now).unwrap(), None);
+
+        let server = mock::remote_ep(1, DHCP_SERVER_PORT);
+        net.inject(server, mock::local_ep(DHCP_CLIENT_PORT), &reply);
+        net.poll(&mut sockets, now);
+        assert!(client.poll(&mut sockets, now).unwrap().is_some());
+    }
+}
@@ -48,11 +48,17 @@ The following features can be enabled at the crate level:
 
 ## `sntp`
 
-Compiles the SNTP protocol and client implementation. It has a dependency on `socket-udp`. Enabled by default.
+Compiles the SNTP protocol, client and server implementations. It has a dependency on `socket-udp`. Enabled by default.
 
 ## `tftp`
 
-Compiles the TFTP protocol and server implementation. It has a dependency on `socket-udp`. Enabled by default.
+Compiles the TFTP protocol, server and client implementations. It has a dependency on `socket-udp`. Enabled by default.
+
+## `dhcp`
+
+Compiles a DHCP client that only sends information requests (DHCPINFORM), to learn the NTP
+and TFTP servers of the network without taking a lease. It has a dependency on `ethernet`,
+`proto-dhcpv4` and `socket-udp`.
 */
 
 #![deny(warnings)]
@@ -72,10 +78,25 @@ pub use smoltcp as net;
 
 #[macro_use]
 mod macros;
-mod wire;
+pub mod wire;
+
+#[cfg(feature = "sntp")]
+mod datetime;
+
+#[cfg(any(test, feature = "trace-bytes"))]
+mod util;
+
+#[cfg(test)]
+mod mock;
+
+#[cfg(any(feature = "sntp", feature = "tftp"))]
+pub mod transport;
 
 #[cfg(feature = "sntp")]
 pub mod sntp;
 
 #[cfg(feature = "tftp")]
 pub mod tftp;
+
+#[cfg(feature = "dhcp")]
+pub mod dhcp;
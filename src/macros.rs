@@ -8,6 +8,8 @@ mod log {
     macro_rules! net_log {
         (trace, $($arg:expr),*) => { trace!($($arg),*); };
         (debug, $($arg:expr),*) => { debug!($($arg),*); };
+        // Only used by some protocols: called by path, so that importing it can't go unused
+        (warn, $($arg:expr),*) => { ::log::warn!($($arg),*); };
     }
 }
 
@@ -27,6 +29,48 @@ macro_rules! net_debug {
     ($($arg:expr),*) => (net_log!(debug, $($arg),*));
 }
 
+macro_rules! net_warn {
+    ($($arg:expr),*) => (net_log!(warn, $($arg),*));
+}
+
+/// Maximum number of bytes dumped by `net_trace_bytes!` for each packet.
+#[cfg(feature = "trace-bytes")]
+pub(crate) const TRACE_BYTES_MAX: usize = 32;
+
+/// Formats the length of a buffer followed by the hex dump of its first `TRACE_BYTES_MAX` bytes.
+#[cfg(feature = "trace-bytes")]
+pub(crate) struct HexDump<'a>(pub &'a [u8]);
+
+#[cfg(feature = "trace-bytes")]
+impl<'a> ::core::fmt::Display for HexDump<'a> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "[{}]", self.0.len())?;
+        if !self.0.is_empty() {
+            let len = self.0.len().min(TRACE_BYTES_MAX);
+            write!(f, " ")?;
+            crate::util::hex::write_hex(f, &self.0[..len], 1)?;
+        }
+        if self.0.len() > TRACE_BYTES_MAX {
+            write!(f, " ...")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "trace-bytes")]
+macro_rules! net_trace_bytes {
+    ($what:expr, $bytes:expr) => {
+        net_trace!("{}: {}", $what, crate::macros::HexDump($bytes))
+    };
+}
+
+#[cfg(not(feature = "trace-bytes"))]
+macro_rules! net_trace_bytes {
+    ($what:expr, $bytes:expr) => {{
+        let _ = (&$what, &$bytes);
+    }};
+}
+
 /// Macro to automatically derive `From<{integer}>` and `Into<{integer}>` traits for an enum.
 /// Unused codes will automatically be grouped in a catch-all `Unknown({integer})` variant.
 ///
@@ -63,6 +107,7 @@ macro_rules! enum_with_unknown {
               $( #[$variant_attr] )*
               $variant
             ),*,
+            /// Any other value.
             Unknown($ty)
         }
 
@@ -83,5 +128,30 @@ macro_rules! enum_with_unknown {
                 }
             }
         }
+
+        impl $name {
+            /// Returns all the known variants, i.e. all but `Unknown`, in the order they are defined.
+            pub fn all() -> &'static [$name] {
+                &[$( $name::$variant ),*]
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "trace-bytes"))]
+mod test {
+    use super::*;
+    use std::format;
+
+    #[test]
+    fn test_hex_dump() {
+        assert_eq!(format!("{}", HexDump(&[])), "[0]");
+        assert_eq!(format!("{}", HexDump(&[0x00, 0x01, 0xab])), "[3] 00 01 ab");
+
+        let long = [0xffu8; TRACE_BYTES_MAX + 1];
+        let dump = format!("{}", HexDump(&long));
+        assert!(dump.starts_with(&format!("[{}] ff ff", TRACE_BYTES_MAX + 1)));
+        assert!(dump.ends_with(" ff ..."));
+        assert_eq!(dump.matches("ff").count(), TRACE_BYTES_MAX);
     }
 }
@@ -0,0 +1,292 @@
+//! Mock network used to exercise the protocol implementations in tests.
+//!
+//! The [`Network`] wraps an `EthernetInterface` on top of a fake device. Tests can inject
+//! UDP datagrams as if they were received from a remote peer, and collect the datagrams
+//! transmitted by the interface.
+
+#![allow(dead_code)]
+
+use crate::net::{
+    iface::{EthernetInterface, EthernetInterfaceBuilder, NeighborCache, Routes},
+    phy::{self, ChecksumCapabilities, Device, DeviceCapabilities},
+    socket::{SocketSet, UdpPacketMetadata, UdpSocketBuffer},
+    time::Instant,
+    wire::{
+        EthernetAddress, EthernetFrame, EthernetProtocol, IpAddress, IpCidr, IpEndpoint,
+        IpProtocol, Ipv4Address, Ipv4Packet, Ipv4Repr, UdpPacket, UdpRepr,
+    },
+    Result,
+};
+use std::{boxed::Box, collections::VecDeque, vec, vec::Vec};
+
+/// Address of the interface under test.
+pub const LOCAL_ADDR: Ipv4Address = Ipv4Address([192, 168, 69, 1]);
+
+/// Hardware address of the interface under test.
+const LOCAL_HWADDR: EthernetAddress = EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+
+/// Address of the default gateway, through which all remote peers are reached.
+const GATEWAY_ADDR: Ipv4Address = Ipv4Address([192, 168, 69, 100]);
+
+/// Hardware address of the default gateway.
+const GATEWAY_HWADDR: EthernetAddress = EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, 0x64]);
+
+/// Returns an endpoint of the interface under test.
+pub fn local_ep(port: u16) -> IpEndpoint {
+    IpEndpoint::new(LOCAL_ADDR.into(), port)
+}
+
+/// Returns an endpoint of a remote peer, reachable through the default gateway.
+pub fn remote_ep(host: u8, port: u16) -> IpEndpoint {
+    IpEndpoint::new(IpAddress::v4(10, 0, 0, host), port)
+}
+
+/// Allocates a UDP socket buffer with static lifetime.
+pub fn udp_buffer(packets: usize, size: usize) -> UdpSocketBuffer<'static, 'static> {
+    UdpSocketBuffer::new(
+        Box::leak(vec![UdpPacketMetadata::EMPTY; packets].into_boxed_slice()),
+        Box::leak(vec![0; size].into_boxed_slice()),
+    )
+}
+
+/// Allocates an empty socket set with static lifetime.
+pub fn socket_set(capacity: usize) -> SocketSet<'static, 'static, 'static> {
+    let mut entries = Vec::new();
+    entries.resize_with(capacity, || None);
+    SocketSet::new(Box::leak(entries.into_boxed_slice()))
+}
+
+/// A UDP datagram travelling on the mock network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    pub src: IpEndpoint,
+    pub dst: IpEndpoint,
+    pub hop_limit: u8,
+    pub payload: Vec<u8>,
+}
+
+/// A fake Ethernet device, backed by a pair of frame queues.
+pub struct Wire {
+    rx: VecDeque<Vec<u8>>,
+    tx: VecDeque<Vec<u8>>,
+}
+
+impl<'a> Device<'a> for Wire {
+    type RxToken = RxToken;
+    type TxToken = TxToken<'a>;
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let tx = &mut self.tx;
+        self.rx
+            .pop_front()
+            .map(move |buffer| (RxToken { buffer }, TxToken { queue: tx }))
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        Some(TxToken {
+            queue: &mut self.tx,
+        })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = 1514;
+        caps
+    }
+}
+
+pub struct RxToken {
+    buffer: Vec<u8>,
+}
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> Result<R>,
+    {
+        f(&mut self.buffer)
+    }
+}
+
+pub struct TxToken<'a> {
+    queue: &'a mut VecDeque<Vec<u8>>,
+}
+
+impl<'a> phy::TxToken for TxToken<'a> {
+    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> Result<R>,
+    {
+        let mut buffer = vec![0; len];
+        let result = f(&mut buffer);
+        self.queue.push_back(buffer);
+        result
+    }
+}
+
+/// An Ethernet interface attached to the mock network.
+pub struct Network {
+    iface: EthernetInterface<'static, 'static, 'static, Wire>,
+}
+
+impl Network {
+    /// Creates a new interface with address `LOCAL_ADDR`.
+    pub fn new() -> Network {
+        let mut neighbor_cache =
+            NeighborCache::new(Box::leak(Box::new([None; 4])) as &'static mut [_]);
+        // Make sure the gateway entry never expires, regardless of the test timestamps
+        neighbor_cache.fill(
+            GATEWAY_ADDR.into(),
+            GATEWAY_HWADDR,
+            Instant::from_millis(i64::MAX / 2),
+        );
+
+        let mut routes = Routes::new(Box::leak(Box::new([None; 1])) as &'static mut [_]);
+        routes.add_default_ipv4_route(GATEWAY_ADDR).unwrap();
+
+        let ip_addrs = Box::leak(Box::new([IpCidr::new(LOCAL_ADDR.into(), 24)]));
+
+        let iface = EthernetInterfaceBuilder::new(Wire {
+            rx: VecDeque::new(),
+            tx: VecDeque::new(),
+        })
+        .ethernet_addr(LOCAL_HWADDR)
+        .neighbor_cache(neighbor_cache)
+        .ip_addrs(&mut ip_addrs[..])
+        .routes(routes)
+        .finalize();
+
+        Network { iface }
+    }
+
+    /// Polls the interface, moving datagrams between the device and the sockets.
+    pub fn poll(&mut self, sockets: &mut SocketSet, now: Instant) {
+        while self.iface.poll(sockets, now).unwrap_or(false) {}
+    }
+
+    /// Queues a datagram for reception by the interface.
+    pub fn inject(&mut self, src: IpEndpoint, dst: IpEndpoint, payload: &[u8]) {
+        let (src_addr, dst_addr) = match (src.addr, dst.addr) {
+            (IpAddress::Ipv4(src), IpAddress::Ipv4(dst)) => (src, dst),
+            _ => panic!("only IPv4 endpoints are supported"),
+        };
+        let checksum = ChecksumCapabilities::default();
+
+        let udp_repr = UdpRepr {
+            src_port: src.port,
+            dst_port: dst.port,
+            payload,
+        };
+        let ip_repr = Ipv4Repr {
+            src_addr,
+            dst_addr,
+            protocol: IpProtocol::Udp,
+            payload_len: udp_repr.buffer_len(),
+            hop_limit: 64,
+        };
+
+        let ip_len = ip_repr.buffer_len() + ip_repr.payload_len;
+        let mut buffer = vec![0; EthernetFrame::<&[u8]>::buffer_len(ip_len)];
+        let mut frame = EthernetFrame::new_unchecked(&mut buffer[..]);
+        frame.set_src_addr(GATEWAY_HWADDR);
+        frame.set_dst_addr(if dst_addr.is_broadcast() {
+            EthernetAddress::BROADCAST
+        } else {
+            LOCAL_HWADDR
+        });
+        frame.set_ethertype(EthernetProtocol::Ipv4);
+
+        let mut ip_packet = Ipv4Packet::new_unchecked(frame.payload_mut());
+        ip_repr.emit(&mut ip_packet, &checksum);
+
+        let mut udp_packet = UdpPacket::new_unchecked(ip_packet.payload_mut());
+        udp_repr.emit(&mut udp_packet, &src.addr, &dst.addr, &checksum);
+
+        self.iface.device_mut().rx.push_back(buffer);
+    }
+
+    /// Returns all the datagrams transmitted by the interface since the last call.
+    pub fn transmitted(&mut self) -> Vec<Datagram> {
+        self.iface
+            .device_mut()
+            .tx
+            .drain(..)
+            .filter_map(|buffer| {
+                let frame = EthernetFrame::new_checked(&buffer[..]).ok()?;
+                if frame.ethertype() != EthernetProtocol::Ipv4 {
+                    return None;
+                }
+                let ip_packet = Ipv4Packet::new_checked(frame.payload()).ok()?;
+                if ip_packet.protocol() != IpProtocol::Udp {
+                    return None;
+                }
+                let udp_packet = UdpPacket::new_checked(ip_packet.payload()).ok()?;
+                Some(Datagram {
+                    src: IpEndpoint::new(ip_packet.src_addr().into(), udp_packet.src_port()),
+                    dst: IpEndpoint::new(ip_packet.dst_addr().into(), udp_packet.dst_port()),
+                    hop_limit: ip_packet.hop_limit(),
+                    payload: udp_packet.payload().to_vec(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Transmit queue collecting the datagrams sent through it, for tests not involving sockets.
+#[cfg(any(feature = "sntp", feature = "tftp"))]
+#[derive(Default)]
+pub struct Outbox {
+    /// The datagrams sent so far, along with their destination.
+    pub sent: Vec<(IpEndpoint, Vec<u8>)>,
+}
+
+#[cfg(any(feature = "sntp", feature = "tftp"))]
+impl crate::transport::Transmit for Outbox {
+    fn send(&mut self, size: usize, endpoint: IpEndpoint) -> Result<&mut [u8]> {
+        self.sent.push((endpoint, vec![0; size]));
+        Ok(&mut self.sent.last_mut().unwrap().1[..])
+    }
+}
+
+/// Logger capturing the records emitted on the current thread, so that tests can inspect them.
+#[cfg(feature = "log")]
+pub mod logger {
+    use core::cell::RefCell;
+    use log::{LevelFilter, Log, Metadata, Record};
+    use std::{string::String, string::ToString, sync::Once, thread_local, vec::Vec};
+
+    struct Capture;
+
+    static LOGGER: Capture = Capture;
+    static INIT: Once = Once::new();
+
+    thread_local! {
+        static LINES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    impl Log for Capture {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            LINES.with(|lines| lines.borrow_mut().push(record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Installs the capturing logger, discarding the records captured so far.
+    pub fn install() {
+        INIT.call_once(|| {
+            log::set_logger(&LOGGER).unwrap();
+            log::set_max_level(LevelFilter::Trace);
+        });
+        take();
+    }
+
+    /// Returns the records captured since the last call.
+    pub fn take() -> Vec<String> {
+        LINES.with(|lines| lines.borrow_mut().drain(..).collect())
+    }
+}
@@ -1,12 +1,25 @@
-//! Simple Network Time Protocol client implementation.
+//! Simple Network Time Protocol client and server implementation.
 
+#[cfg(feature = "ipv4")]
+use crate::net::wire::Ipv4Address;
 use crate::net::{
     socket::{SocketHandle, SocketSet, UdpSocket, UdpSocketBuffer},
     time::{Duration, Instant},
     wire::{IpAddress, IpEndpoint},
     {Error, Result},
 };
-use crate::wire::sntp::{LeapIndicator, Packet, ProtocolMode, Repr, Stratum, Timestamp};
+use crate::transport::{Activity, EndpointFilter, Transmit};
+use crate::wire::sntp::{
+    LeapIndicator, Packet, ProtocolMode, Repr, Stratum, Timestamp, DIFF_SEC_1970_2036,
+};
+use core::cmp::Ordering;
+use managed::ManagedSlice;
+
+mod pool;
+mod server;
+
+pub use pool::{PoolEntry, ServerPool};
+pub use server::{Clock, Server};
 
 /// Minimum interval between requests (defaults to one minute)
 const MIN_REQUEST_INTERVAL: Duration = Duration { millis: 60 * 1_000 };
@@ -16,24 +29,105 @@ const MAX_REQUEST_INTERVAL: Duration = Duration {
     millis: 24 * 60 * 60 * 1_000,
 };
 
-/// Number of seconds between 1970 and Feb 7, 2036 06:28:16 UTC (epoch 1).
-/// Used for NTP to Unix timestamp conversion.
-const DIFF_SEC_1970_2036: u32 = 2_085_978_496;
+/// Maximum number of requests sent in a burst.
+const MAX_BURST: usize = 8;
+
+/// Interval between the requests of a burst.
+const BURST_INTERVAL: Duration = Duration { millis: 250 };
+
+/// Protocol version used by default.
+const DEFAULT_VERSION: u8 = 4;
+
+/// Maximum number of servers kept by `Client::from_dhcp()`, as many as `dhcp::ServerInfo` holds.
+const MAX_DHCP_SERVERS: usize = 3;
+
+/// Precision advertised in requests by default, as a power of two number of seconds:
+/// 2^-10 s is about 1 ms, the resolution of `Instant`.
+const DEFAULT_PRECISION: i8 = -10;
+
+/// Precision of the local clock, in microseconds, since `Instant` has millisecond resolution.
+const LOCAL_PRECISION_US: u64 = 1_000;
+
+/// Frequency tolerance of the local clock, in parts per million (RFC 5905's PHI).
+const FREQ_TOLERANCE_PPM: u64 = 15;
+
+/// Number of recent round-trip delays from which the jitter is estimated (RFC 5905's NSTAGE).
+const JITTER_SAMPLES: usize = 8;
 
 /// IANA port for SNTP servers.
 const SNTP_PORT: u16 = 123;
 
+/// First port of the dynamic range (RFC 6335), from which ephemeral source ports are picked.
+const EPHEMERAL_PORT_BASE: u16 = 49152;
+
+/// Length of the largest reply accepted: a 48-byte header, optionally followed by
+/// a 4-byte key identifier and a message digest of up to 20 bytes (RFC 5905).
+const MAX_REPLY_LEN: usize = 48 + 4 + 20;
+
 /// SNTPv4 client.
 ///
 /// You must call `Client::poll()` after `Interface::poll()` to send
 /// and receive SNTP packets.
 pub struct Client {
-    udp_handle: SocketHandle,
+    udp_handle: Option<SocketHandle>,
     ntp_server: IpAddress,
+    /// Local port the socket is bound to, or zero for an ephemeral one.
+    local_port: u16,
     /// When to send next request.
     next_request: Instant,
     /// Current timeout interval.
     curr_interval: Duration,
+    /// Constant interval between requests replacing the backoff, if any.
+    fixed_interval: Option<Duration>,
+    /// Whether a request has been sent and no valid reply has been received yet.
+    pending: bool,
+    /// Whether the server of the pending request has been reported unreachable.
+    unreachable: bool,
+    /// Whether requests are stopped until the next `Client::reset()`.
+    cancelled: bool,
+    /// Hop limit (TTL) of the requests, or `None` for the default of the interface.
+    hop_limit: Option<u8>,
+    /// Peers whose datagrams are processed, or `None` for all of them.
+    endpoint_filter: Option<EndpointFilter>,
+    /// When the last request was sent.
+    last_request: Instant,
+    /// Stratum of the last valid reply.
+    last_stratum: u8,
+    /// Maximum error of the last valid reply.
+    max_error: Option<Duration>,
+    /// Round-trip delays of the most recent valid replies, in milliseconds, as a ring buffer.
+    delays: [u32; JITTER_SAMPLES],
+    /// Number of valid entries in `delays`.
+    delays_len: usize,
+    /// Index of the entry of `delays` to be overwritten next.
+    delays_next: usize,
+    /// Protocol version of the requests, which replies must match.
+    version: u8,
+    /// Protocol version of the last valid reply.
+    reply_version: Option<u8>,
+    /// Precision of the local clock advertised in requests, in log2 seconds.
+    precision: i8,
+    /// Protocol modes of the accepted replies, one bit per mode value.
+    accepted_modes: u8,
+    /// Maximum accepted distance between a reply and the time estimate.
+    sanity_window: Option<Duration>,
+    /// Unix timestamp believed to be current at the given instant.
+    time_estimate: Option<(u32, Instant)>,
+    /// Whether the time estimate is a last known good timestamp, only bounding replies from below.
+    estimate_seeded: bool,
+    /// Number of requests sent at each synchronization.
+    burst: u8,
+    /// Number of requests sent so far in the current burst.
+    burst_sent: u8,
+    /// Offsets of the server clock from the monotonic clock collected during the current burst,
+    /// in milliseconds.
+    burst_offsets: [i64; MAX_BURST],
+    /// Number of valid entries in `burst_offsets`.
+    burst_len: usize,
+    /// Servers handed out by DHCP, among which `Client::poll()` fails over.
+    dhcp_servers: [PoolEntry; MAX_DHCP_SERVERS],
+    /// Number of valid entries in `dhcp_servers`.
+    dhcp_servers_len: usize,
 }
 
 impl Client {
@@ -88,63 +182,676 @@ impl Client {
         net_trace!("SNTP initialised");
 
         Client {
-            udp_handle,
+            udp_handle: Some(udp_handle),
+            ..Client::detached(ntp_server, now)
+        }
+    }
+
+    /// Creates a client querying the NTP servers handed out by DHCP (option 42), or `None`
+    /// if there are none.
+    ///
+    /// `Client::poll()` then fails over among the first `MAX_DHCP_SERVERS` of them, just like
+    /// `Client::poll_pool()` with a pool made of these servers: the first one is queried first,
+    /// and a request left unanswered is retried with the next best one.
+    ///
+    /// ```rust
+    /// use smolapps::sntp::Client;
+    /// use smolapps::net::socket::{SocketSet, UdpSocketBuffer, UdpPacketMetadata};
+    /// use smolapps::net::time::Instant;
+    /// use smolapps::net::wire::Ipv4Address;
+    ///
+    /// # let mut sockets_entries: [_; 1] = Default::default();
+    /// # let mut sockets = SocketSet::new(&mut sockets_entries[..]);
+    /// # let mut rx_storage = [0; 128];
+    /// # let mut rx_metadata = [UdpPacketMetadata::EMPTY; 1];
+    /// # let mut tx_storage = [0; 128];
+    /// # let mut tx_metadata = [UdpPacketMetadata::EMPTY; 1];
+    /// # let rx_buffer = UdpSocketBuffer::new(&mut rx_metadata[..], &mut rx_storage[..]);
+    /// # let tx_buffer = UdpSocketBuffer::new(&mut tx_metadata[..], &mut tx_storage[..]);
+    /// // As found in `dhcp::ServerInfo::ntp_servers`
+    /// let ntp_servers = [
+    ///     Some(Ipv4Address::new(192, 168, 69, 2)),
+    ///     Some(Ipv4Address::new(192, 168, 69, 3)),
+    ///     None,
+    /// ];
+    ///
+    /// let mut servers = [Ipv4Address::UNSPECIFIED; 3];
+    /// let mut count = 0;
+    /// for addr in ntp_servers.iter().flatten() {
+    ///     servers[count] = *addr;
+    ///     count += 1;
+    /// }
+    ///
+    /// let sntp = Client::from_dhcp(
+    ///     &mut sockets,
+    ///     rx_buffer,
+    ///     tx_buffer,
+    ///     &servers[..count],
+    ///     Instant::from_secs(0),
+    /// )
+    /// .unwrap();
+    /// assert_eq!(sntp.server(), Ipv4Address::new(192, 168, 69, 2).into());
+    /// ```
+    #[cfg(feature = "ipv4")]
+    pub fn from_dhcp<'a, 'b, 'c>(
+        sockets: &mut SocketSet<'a, 'b, 'c>,
+        rx_buffer: UdpSocketBuffer<'b, 'c>,
+        tx_buffer: UdpSocketBuffer<'b, 'c>,
+        dhcp_ntp_servers: &[Ipv4Address],
+        now: Instant,
+    ) -> Option<Self>
+    where
+        'b: 'c,
+    {
+        let first = *dhcp_ntp_servers.first()?;
+        let mut client = Client::new(sockets, rx_buffer, tx_buffer, first.into(), now);
+        for (entry, addr) in client.dhcp_servers.iter_mut().zip(dhcp_ntp_servers) {
+            *entry = PoolEntry::new((*addr).into(), 1);
+            client.dhcp_servers_len += 1;
+        }
+        Some(client)
+    }
+
+    /// Returns the handle of the UDP socket used by the client, or `None` if it is detached.
+    ///
+    /// This gives access to the underlying `UdpSocket` through the `SocketSet`, e.g. to
+    /// inspect its buffers. The socket must not be closed, rebound or read from,
+    /// or the client stops working.
+    ///
+    /// ```rust
+    /// # use smolapps::sntp::Client;
+    /// # use smolapps::net::socket::{SocketSet, UdpSocketBuffer, UdpPacketMetadata};
+    /// # use smolapps::net::time::Instant;
+    /// # use smolapps::net::wire::IpAddress;
+    /// use smolapps::net::socket::UdpSocket;
+    ///
+    /// # let mut sockets_entries: [_; 1] = Default::default();
+    /// # let mut sockets = SocketSet::new(&mut sockets_entries[..]);
+    /// # let mut rx_storage = [0; 128];
+    /// # let mut rx_metadata = [UdpPacketMetadata::EMPTY; 1];
+    /// # let mut tx_storage = [0; 128];
+    /// # let mut tx_metadata = [UdpPacketMetadata::EMPTY; 1];
+    /// # let rx_buffer = UdpSocketBuffer::new(&mut rx_metadata[..], &mut rx_storage[..]);
+    /// # let tx_buffer = UdpSocketBuffer::new(&mut tx_metadata[..], &mut tx_storage[..]);
+    /// let sntp = Client::new(
+    ///     &mut sockets,
+    ///     rx_buffer,
+    ///     tx_buffer,
+    ///     IpAddress::v4(62, 112, 134, 4),
+    ///     Instant::from_secs(0),
+    /// );
+    ///
+    /// let socket = sockets.get::<UdpSocket>(sntp.socket_handle().unwrap());
+    /// assert_eq!(socket.payload_recv_capacity(), 128);
+    /// assert!(!socket.can_recv());
+    /// ```
+    pub fn socket_handle(&self) -> Option<SocketHandle> {
+        self.udp_handle
+    }
+
+    /// Creates an SNTPv4 client that is not attached to any socket.
+    ///
+    /// The client must then be driven through `Client::process_datagram()` and
+    /// `Client::process_timeouts()`, while `Client::poll()` fails with `Err(Error::Illegal)`.
+    pub fn detached(ntp_server: IpAddress, now: Instant) -> Self {
+        Client {
+            udp_handle: None,
             ntp_server,
+            local_port: 0,
             next_request: now,
             curr_interval: MIN_REQUEST_INTERVAL,
+            fixed_interval: None,
+            pending: false,
+            unreachable: false,
+            cancelled: false,
+            hop_limit: None,
+            endpoint_filter: None,
+            last_request: now,
+            last_stratum: 0,
+            max_error: None,
+            delays: [0; JITTER_SAMPLES],
+            delays_len: 0,
+            delays_next: 0,
+            version: DEFAULT_VERSION,
+            reply_version: None,
+            precision: DEFAULT_PRECISION,
+            accepted_modes: mode_bit(ProtocolMode::Server),
+            sanity_window: None,
+            time_estimate: None,
+            estimate_seeded: false,
+            burst: 1,
+            burst_sent: 0,
+            burst_offsets: [0; MAX_BURST],
+            burst_len: 0,
+            dhcp_servers: [PoolEntry::new(IpAddress::Unspecified, 1); MAX_DHCP_SERVERS],
+            dhcp_servers_len: 0,
         }
     }
 
+    /// Sets the maximum distance allowed between a reply and the current time estimate.
+    ///
+    /// Replies further away than `window` from the estimate provided through
+    /// `Client::set_time_estimate()` are discarded. The check is disabled (the default)
+    /// if either the window or the estimate is missing.
+    pub fn set_sanity_window(&mut self, window: Option<Duration>) {
+        self.sanity_window = window;
+    }
+
+    /// Sends a burst of `count` requests at each synchronization, instead of a single one.
+    ///
+    /// Requests are sent `BURST_INTERVAL` apart, and the timestamp is only returned once
+    /// a reply is received after the last one. It is then computed from the median offset
+    /// of the replies, so that outliers due to a jittery link are discarded.
+    /// The count is capped to `MAX_BURST`, and bursts are disabled (the default) below two.
+    pub fn set_burst(&mut self, count: u8) {
+        self.burst = count.max(1).min(MAX_BURST as u8);
+    }
+
+    /// Sets the protocol version of the requests (4 by default).
+    ///
+    /// Servers reply with the version of the request, so replies with a different version
+    /// are discarded. Use version 3 for compatibility with NTPv3-only servers.
+    pub fn set_version(&mut self, version: u8) {
+        self.version = version;
+    }
+
+    /// Returns the protocol version of the requests.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Sets the precision of the local clock advertised in requests, as a power of two
+    /// number of seconds (RFC 5905).
+    ///
+    /// Defaults to -10, i.e. about 1 ms, which is the resolution of `Instant`. Use a lower
+    /// value if timestamps are taken from a finer clock. Servers don't rely on it to answer,
+    /// but it shows up in their logs and monitoring tools.
+    pub fn set_precision(&mut self, precision: i8) {
+        self.precision = precision;
+    }
+
+    /// Returns the precision of the local clock advertised in requests.
+    pub fn precision(&self) -> i8 {
+        self.precision
+    }
+
+    /// Sets whether replies in protocol `mode` are accepted.
+    ///
+    /// Only `ProtocolMode::Server` replies are accepted by default. Peers replying in
+    /// `ProtocolMode::SymmetricPassive` or `ProtocolMode::Broadcast` mode can be accepted too,
+    /// or server replies rejected. `ProtocolMode::Client` is never accepted, so that requests
+    /// looped back by the link can't be mistaken for replies.
+    pub fn set_accept_mode(&mut self, mode: ProtocolMode, accept: bool) {
+        match accept {
+            true => self.accepted_modes |= mode_bit(mode),
+            false => self.accepted_modes &= !mode_bit(mode),
+        }
+    }
+
+    /// Returns whether replies in protocol `mode` are accepted.
+    pub fn accepts_mode(&self, mode: ProtocolMode) -> bool {
+        mode != ProtocolMode::Client && self.accepted_modes & mode_bit(mode) != 0
+    }
+
+    /// Returns the protocol version of the last valid reply, if any.
+    pub fn reply_version(&self) -> Option<u8> {
+        self.reply_version
+    }
+
+    /// Returns the maximum error of the timestamp of the last valid reply, if any.
+    ///
+    /// This is RFC 5905's root synchronization distance: half the round-trip delay to the
+    /// primary reference (the root delay plus the measured round trip), plus the root dispersion
+    /// and the dispersion accumulated by this sample, from the precision of both clocks and
+    /// the frequency tolerance over the round trip. It is rounded up to the next millisecond.
+    ///
+    /// `None` is also returned if the server reported an unsynchronized stratum (16 or more),
+    /// whose error is unbounded.
+    pub fn max_error(&self) -> Option<Duration> {
+        self.max_error
+    }
+
+    /// Returns an estimate of the jitter of the round-trip delay to the server, if known.
+    ///
+    /// This follows RFC 5905's peer jitter: the root mean square of the differences between
+    /// the delays of the last 8 valid replies and the shortest of them, in whole milliseconds.
+    /// Clock disciplines can use it to weight samples. `None` is returned until at least two
+    /// replies have been received.
+    pub fn jitter(&self) -> Option<Duration> {
+        jitter(&self.delays[..self.delays_len])
+    }
+
+    /// Provides a rough estimate of the current Unix timestamp, valid at instant `now`.
+    ///
+    /// The estimate is advanced according to the monotonic clock and is replaced
+    /// by the timestamp of every accepted reply.
+    pub fn set_time_estimate(&mut self, timestamp: u32, now: Instant) {
+        self.time_estimate = Some((timestamp, now));
+        self.estimate_seeded = false;
+    }
+
+    /// Seeds the time estimate with the last known good Unix timestamp, e.g. one persisted
+    /// in non-volatile memory before a reboot, valid at instant `now`.
+    ///
+    /// Unlike `Client::set_time_estimate()`, the actual time may be arbitrarily later than
+    /// the seed, since it is unknown how long the device has been off: until the first reply
+    /// is accepted, the sanity window only rejects replies earlier than the seed.
+    pub fn seed_estimate(&mut self, timestamp: u32, now: Instant) {
+        self.time_estimate = Some((timestamp, now));
+        self.estimate_seeded = true;
+    }
+
     /// Returns the duration until the next packet request.
     ///
     /// Useful for suspending execution after polling.
+    ///
+    /// A cancelled client has nothing to do, and `MAX_REQUEST_INTERVAL` is returned.
+    #[must_use]
     pub fn next_poll(&self, now: Instant) -> Duration {
-        self.next_request - now
+        if self.cancelled {
+            return MAX_REQUEST_INTERVAL;
+        }
+        // Late callers must poll right away, instead of waiting for as long as they're late
+        if self.next_request > now {
+            self.next_request - now
+        } else {
+            Duration::from_millis(0)
+        }
+    }
+
+    /// Resets the request schedule, e.g. after the link has gone down and back up.
+    ///
+    /// A new request is sent at the next poll, and the backoff restarts from the minimum interval.
+    pub fn reset(&mut self, now: Instant) {
+        self.next_request = now;
+        self.curr_interval = MIN_REQUEST_INTERVAL;
+        self.pending = false;
+        self.unreachable = false;
+        self.cancelled = false;
+        self.burst_sent = 0;
+        self.burst_len = 0;
+    }
+
+    /// Abandons the pending request, if any, and stops sending requests until
+    /// the next `Client::reset()`.
+    ///
+    /// This lets the client go idle once the time is no longer needed, e.g. because it was
+    /// obtained from another source. Replies received in the meantime are discarded.
+    pub fn cancel(&mut self) {
+        net_debug!("sntp: requests cancelled");
+        self.cancelled = true;
+        self.pending = false;
+        self.unreachable = false;
+        self.burst_sent = 0;
+        self.burst_len = 0;
+    }
+
+    /// Returns `true` if requests are stopped through `Client::cancel()`.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// Reports that the server at `addr` is unreachable, returning whether a request
+    /// to it was pending.
+    ///
+    /// `Client::poll_pool()` then gives up on the pending request right away and queries
+    /// the next best server of the pool, instead of waiting for the request to time out.
+    /// `Client::poll()` keeps its schedule, since retrying the same server early would not help.
+    ///
+    /// smoltcp does not report ICMP errors to UDP sockets. Destination Unreachable messages
+    /// can be received by an `IcmpSocket` bound to `IcmpEndpoint::Udp` with the endpoint of the
+    /// client instead: the destination of the UDP packet quoted in the message is `addr`.
+    pub fn peer_unreachable(&mut self, addr: IpAddress) -> bool {
+        let is_pending = self.pending && addr == self.ntp_server;
+        if is_pending {
+            net_debug!("sntp: {} unreachable", addr);
+            self.unreachable = true;
+        }
+        is_pending
+    }
+
+    /// Returns the address of the server queried by this client.
+    pub fn server(&self) -> IpAddress {
+        self.ntp_server
+    }
+
+    /// Sets the address of the server queried by this client, starting from the next request.
+    pub fn set_server(&mut self, ntp_server: IpAddress) {
+        self.ntp_server = ntp_server;
+    }
+
+    /// Sets the local port from which requests are sent.
+    ///
+    /// By default (or if `port` is zero), an ephemeral port from the dynamic range is picked
+    /// when the socket is bound, so that the client doesn't conflict with an NTP server
+    /// listening on port 123 of the same host. Requests are always sent to port 123.
+    ///
+    /// The socket is bound during the first call to `Client::poll()`, so this has no effect
+    /// afterwards.
+    pub fn set_local_port(&mut self, port: u16) {
+        self.local_port = port;
+    }
+
+    /// Returns the configured local port, or zero if an ephemeral one is used.
+    pub fn local_port(&self) -> u16 {
+        self.local_port
+    }
+
+    /// Sets the hop limit (TTL) of the requests, or `None` (the default) to use the one
+    /// of the interface, which is 64 for smoltcp.
+    ///
+    /// This bounds how far requests can travel, e.g. to keep requests sent to the NTP
+    /// multicast group (224.0.1.1) or to a link-local server within the local link with
+    /// `Some(1)`. RFC 5905 leaves the scope of multicast NTP to configuration, so there is
+    /// no default for it. The hop limit is applied to the socket at every `Client::poll()`.
+    pub fn set_hop_limit(&mut self, hop_limit: Option<u8>) {
+        self.hop_limit = hop_limit;
+    }
+
+    /// Returns the hop limit (TTL) of the requests, if set.
+    pub fn hop_limit(&self) -> Option<u8> {
+        self.hop_limit
+    }
+
+    /// Sets a constant interval between requests, or `None` (the default) to back off.
+    ///
+    /// By default, unanswered requests are retried at intervals doubling from one minute
+    /// up to a day, and the time is requested again a day after each valid reply.
+    /// With a fixed interval, requests are sent at that constant rate instead, answered or not,
+    /// which is simpler to reason about on gateways with an always-on link.
+    pub fn set_fixed_interval(&mut self, interval: Option<Duration>) {
+        self.fixed_interval = interval;
+    }
+
+    /// Returns the constant interval between requests, if backoff is disabled.
+    pub fn fixed_interval(&self) -> Option<Duration> {
+        self.fixed_interval
+    }
+
+    /// Sets the filter deciding which peers may answer the client, or `None` (the default)
+    /// to process datagrams from anyone.
+    ///
+    /// Datagrams received by `Client::poll()` from endpoints rejected by the filter are dropped
+    /// before being parsed. `Client::process_datagram()` is not affected, since it is not told
+    /// where datagrams come from.
+    pub fn set_endpoint_filter(&mut self, filter: Option<EndpointFilter>) {
+        self.endpoint_filter = filter;
+    }
+
+    /// Returns the filter deciding which peers may answer the client, if any.
+    pub fn endpoint_filter(&self) -> Option<EndpointFilter> {
+        self.endpoint_filter
+    }
+
+    fn allows(&self, ep: IpEndpoint) -> bool {
+        match self.endpoint_filter {
+            Some(filter) => filter(ep),
+            None => true,
+        }
+    }
+
+    /// Returns the instant at which the next request is scheduled.
+    ///
+    /// While a request is pending, this is when it will be retried.
+    #[must_use]
+    pub fn next_poll_at(&self) -> Instant {
+        self.next_request
+    }
+
+    /// Returns `true` if no request is awaiting a reply.
+    ///
+    /// An idle client doesn't need to be polled until `Client::next_poll_at()`.
+    pub fn is_idle(&self) -> bool {
+        !self.pending
     }
 
     /// Processes incoming packets, and sends SNTP requests when timeouts expire.
     ///
     /// If a valid response is received, the Unix timestamp (ie. seconds since
     /// epoch) corresponding to the received NTP timestamp is returned.
+    /// All the queued packets are processed, and the last valid response takes precedence.
+    ///
+    /// Clients created through `Client::from_dhcp()` fail over among their servers.
+    #[must_use = "a received timestamp is lost if the result is discarded"]
     pub fn poll(&mut self, sockets: &mut SocketSet, now: Instant) -> Result<Option<u32>> {
-        let mut socket = sockets.get::<UdpSocket>(self.udp_handle);
+        self.poll_counted(sockets, now)
+            .map(|(timestamp, _)| timestamp)
+    }
+
+    /// Same as `Client::poll()`, also returning how many datagrams were received and sent.
+    #[must_use = "a received timestamp is lost if the result is discarded"]
+    pub fn poll_counted(
+        &mut self,
+        sockets: &mut SocketSet,
+        now: Instant,
+    ) -> Result<(Option<u32>, Activity)> {
+        if self.dhcp_servers_len > 0 {
+            // Work on a copy, as the pool can't borrow the entries from the client it drives
+            let mut entries = self.dhcp_servers;
+            let mut pool = ServerPool::new(&mut entries[..self.dhcp_servers_len]);
+            let res = self.poll_pool_counted(sockets, &mut pool, now);
+            self.dhcp_servers = entries;
+            return res;
+        }
+
+        self.poll_with(sockets, now, true)
+    }
+
+    /// Same as `Client::poll()`, but the server to query is picked from a `ServerPool`.
+    ///
+    /// Replies are recorded in the pool along with their round-trip delay, and requests
+    /// that are retried are recorded as failures of the server that left them unanswered.
+    #[must_use = "a received timestamp is lost if the result is discarded"]
+    pub fn poll_pool(
+        &mut self,
+        sockets: &mut SocketSet,
+        pool: &mut ServerPool,
+        now: Instant,
+    ) -> Result<Option<u32>> {
+        self.poll_pool_counted(sockets, pool, now)
+            .map(|(timestamp, _)| timestamp)
+    }
+
+    fn poll_pool_counted(
+        &mut self,
+        sockets: &mut SocketSet,
+        pool: &mut ServerPool,
+        now: Instant,
+    ) -> Result<(Option<u32>, Activity)> {
+        if self.unreachable {
+            // Abandon the pending request, and fail over right away if possible
+            self.unreachable = false;
+            self.pending = false;
+            self.burst_sent = 0;
+            self.burst_len = 0;
+            pool.record_failure(self.ntp_server);
+            if pool
+                .select()
+                .filter(|addr| *addr != self.ntp_server)
+                .is_some()
+            {
+                self.next_request = now;
+            }
+        }
+
+        if now >= self.next_request && !self.mid_burst() {
+            if self.pending {
+                pool.record_failure(self.ntp_server);
+            }
+            if let Some(addr) = pool.select() {
+                self.ntp_server = addr;
+            }
+        }
+
+        let (timestamp, activity) = self.poll_with(sockets, now, true)?;
+        if timestamp.is_some() {
+            let delay = if now > self.last_request {
+                now - self.last_request
+            } else {
+                Duration::from_millis(0)
+            };
+            pool.record_reply(self.ntp_server, self.last_stratum, delay);
+        }
+
+        Ok((timestamp, activity))
+    }
 
-        // Bind the socket if necessary
+    /// Same as `Client::poll_counted()`, but a request is sent only if `may_request` is `true`.
+    fn poll_with(
+        &mut self,
+        sockets: &mut SocketSet,
+        now: Instant,
+        may_request: bool,
+    ) -> Result<(Option<u32>, Activity)> {
+        let mut socket = match self.udp_handle {
+            Some(udp_handle) => sockets.get::<UdpSocket>(udp_handle),
+            None => return Err(Error::Illegal),
+        };
+
+        // Bind the socket if necessary, deriving an ephemeral port from the clock
         if !socket.is_open() {
+            let port = match self.local_port {
+                0 => EPHEMERAL_PORT_BASE + (now.total_millis() as u64 % 16384) as u16,
+                port => port,
+            };
             socket.bind(IpEndpoint {
                 addr: IpAddress::Unspecified,
-                port: SNTP_PORT,
+                port,
             })?;
         }
+        socket.set_hop_limit(self.hop_limit);
 
-        // Process incoming packets
-        let timestamp = match socket.recv() {
-            Ok((payload, _)) => self.receive(payload),
-            Err(Error::Exhausted) => None,
-            Err(e) => return Err(e),
-        };
+        // Process all the incoming packets, keeping the most recent valid reply
+        let mut timestamp = None;
+        let mut activity = Activity::default();
+        loop {
+            match socket.recv() {
+                Ok((_, ep)) if !self.allows(ep) => {
+                    net_debug!("sntp: dropping packet from filtered endpoint {}", ep);
+                }
+                Ok((payload, _)) => {
+                    timestamp = self.process_datagram(payload, now).or(timestamp);
+                    activity.received += 1;
+                }
+                Err(Error::Exhausted) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        if timestamp.is_none() && may_request && self.process_timeouts(&mut *socket, now)? {
+            activity.sent = 1;
+        }
+        Ok((timestamp, activity))
+    }
+
+    /// Processes a datagram received from the SNTP server.
+    ///
+    /// This is the socket-less equivalent of `Client::poll()` receiving a packet,
+    /// to be used along with `Client::process_timeouts()`. If the datagram is a valid
+    /// response, the corresponding Unix timestamp is returned.
+    ///
+    /// Responses are 48 to 72 bytes long, to account for an optional key identifier
+    /// and message digest. Longer datagrams are dropped without being parsed.
+    pub fn process_datagram(&mut self, data: &[u8], now: Instant) -> Option<u32> {
+        net_trace_bytes!("SNTP recv", data);
+
+        if self.cancelled {
+            net_debug!("SNTP reply received after cancellation, ignoring");
+            return None;
+        }
+
+        let xmit_timestamp = self.receive(data, now)?;
+        let mut ts = xmit_timestamp.sec.wrapping_add(DIFF_SEC_1970_2036);
 
-        match timestamp {
-            Some(ts) => {
-                // A valid timestamp was received.
-                // Increase the request interval to its maximum and return the timestamp.
-                self.next_request = now + MAX_REQUEST_INTERVAL;
-                Ok(Some(ts))
+        if self.burst > 1 {
+            let offset = (xmit_timestamp.to_unix_nanos() / 1_000_000) as i64 - now.total_millis();
+            if self.burst_len < MAX_BURST {
+                self.burst_offsets[self.burst_len] = offset;
+                self.burst_len += 1;
             }
-            None if socket.can_send() && now >= self.next_request => {
-                // The timeout has expired.
-                // Send a request, set the timeout and increment interval using exponential backoff.
-                self.request(&mut *socket)?;
-                self.next_request = now + self.curr_interval;
-                self.curr_interval = MAX_REQUEST_INTERVAL.min(self.curr_interval * 2);
-                Ok(None)
+            // Wait for the rest of the burst
+            if self.burst_sent < self.burst {
+                return None;
             }
-            None => Ok(None),
+            ts = (now.total_millis() + self.burst_median()).div_euclid(1_000) as u32;
+            self.burst_sent = 0;
+            self.burst_len = 0;
+        }
+
+        // A valid timestamp was received.
+        // Increase the request interval to its maximum and return the timestamp.
+        self.next_request = now + self.fixed_interval.unwrap_or(MAX_REQUEST_INTERVAL);
+        self.pending = false;
+        self.unreachable = false;
+        if self.time_estimate.is_some() {
+            self.set_time_estimate(ts, now);
         }
+        Some(ts)
     }
 
-    /// Processes a response from the SNTP server.
-    fn receive(&mut self, data: &[u8]) -> Option<u32> {
+    /// Sends a request through `tx` if the timeout has expired, returning whether it was sent.
+    ///
+    /// This is the socket-less equivalent of `Client::poll()` with no packet to receive.
+    /// It should be called at least as often as `Client::next_poll()` indicates.
+    pub fn process_timeouts<T>(&mut self, tx: &mut T, now: Instant) -> Result<bool>
+    where
+        T: Transmit,
+    {
+        if self.cancelled || !tx.can_send() || now < self.next_request {
+            return Ok(false);
+        }
+
+        // Start a new burst, unless one is in progress
+        if self.burst > 1 && !self.mid_burst() {
+            self.burst_sent = 0;
+            self.burst_len = 0;
+        }
+
+        // The timeout has expired.
+        // Send a request, set the timeout and increment interval using exponential backoff.
+        self.request(tx)?;
+        self.pending = true;
+        self.unreachable = false;
+        self.last_request = now;
+
+        if self.burst > 1 {
+            self.burst_sent += 1;
+            if self.mid_burst() {
+                self.next_request = now + BURST_INTERVAL;
+                return Ok(true);
+            }
+        }
+
+        if let Some(interval) = self.fixed_interval {
+            self.next_request = now + interval;
+            return Ok(true);
+        }
+        self.next_request = now + self.curr_interval;
+        self.curr_interval = MAX_REQUEST_INTERVAL.min(self.curr_interval * 2);
+        Ok(true)
+    }
+
+    /// Returns `true` if the requests of the current burst have not all been sent yet.
+    fn mid_burst(&self) -> bool {
+        self.burst_sent > 0 && self.burst_sent < self.burst
+    }
+
+    /// Returns the median of the offsets collected during the current burst.
+    fn burst_median(&mut self) -> i64 {
+        let offsets = &mut self.burst_offsets[..self.burst_len];
+        offsets.sort_unstable();
+
+        // Both indices point to the middle entry if the count is odd
+        let len = offsets.len();
+        (offsets[(len - 1) / 2] + offsets[len / 2]) / 2
+    }
+
+    /// Processes a response from the SNTP server, returning its transmit timestamp.
+    fn receive(&mut self, data: &[u8], now: Instant) -> Option<Timestamp> {
+        if data.len() > MAX_REPLY_LEN {
+            net_debug!("SNTP oversized pkt: {} bytes", data.len());
+            return None;
+        }
         let sntp_packet = match Packet::new_checked(data) {
             Ok(sntp_packet) => sntp_packet,
             Err(e) => {
@@ -160,36 +867,99 @@ impl Client {
             }
         };
 
-        if sntp_repr.protocol_mode != ProtocolMode::Server {
+        // This also discards our own requests, should the link loop them back
+        if !self.accepts_mode(sntp_repr.protocol_mode) {
             net_debug!(
                 "Invalid mode in SNTP response: {:?}",
                 sntp_repr.protocol_mode
             );
             return None;
         }
+        if sntp_repr.version != self.version {
+            net_debug!(
+                "Invalid version in SNTP response: {} (expected {})",
+                sntp_repr.version,
+                self.version
+            );
+            return None;
+        }
         if sntp_repr.stratum == Stratum::KissOfDeath {
             net_debug!("SNTP kiss o' death received, doing nothing");
             return None;
         }
 
+        // A zero timestamp means the server doesn't know the time (or never synchronized)
+        if sntp_repr.xmit_timestamp == Timestamp::default() {
+            net_debug!("SNTP response without transmit timestamp");
+            return None;
+        }
+        if sntp_repr.ref_timestamp == Timestamp::default() {
+            net_debug!("SNTP response from an unsynchronized server");
+            return None;
+        }
+
         // Perform conversion from NTP timestamp to Unix timestamp
         let timestamp = sntp_repr
             .xmit_timestamp
             .sec
             .wrapping_add(DIFF_SEC_1970_2036);
 
-        Some(timestamp)
+        if !self.is_sane(timestamp, now) {
+            net_debug!(
+                "SNTP timestamp {} too far from the current estimate",
+                timestamp
+            );
+            return None;
+        }
+
+        let rtt = if now > self.last_request {
+            now - self.last_request
+        } else {
+            Duration::from_millis(0)
+        };
+
+        self.last_stratum = sntp_repr.stratum.into();
+        self.reply_version = Some(sntp_repr.version);
+        self.max_error = max_error(&sntp_repr, rtt);
+        self.record_delay(rtt);
+
+        Some(sntp_repr.xmit_timestamp)
+    }
+
+    /// Records the round-trip delay of a valid reply, evicting the oldest one if necessary.
+    fn record_delay(&mut self, rtt: Duration) {
+        self.delays[self.delays_next] = rtt.total_millis().min(u64::from(u32::MAX)) as u32;
+        self.delays_next = (self.delays_next + 1) % JITTER_SAMPLES;
+        self.delays_len = JITTER_SAMPLES.min(self.delays_len + 1);
+    }
+
+    /// Checks whether `timestamp` falls within the sanity window around the time estimate.
+    fn is_sane(&self, timestamp: u32, now: Instant) -> bool {
+        let (window, (estimate, at)) = match (self.sanity_window, self.time_estimate) {
+            (Some(window), Some(estimate)) => (window, estimate),
+            _ => return true,
+        };
+
+        let elapsed = if now > at { (now - at).secs() } else { 0 };
+        let estimate = u64::from(estimate) + elapsed;
+        let timestamp = u64::from(timestamp);
+        let distance = match timestamp.cmp(&estimate) {
+            Ordering::Greater if self.estimate_seeded => 0,
+            _ => timestamp.max(estimate) - timestamp.min(estimate),
+        };
+
+        distance * 1_000 <= window.total_millis()
     }
 
     /// Sends a request to the configured SNTP ntp_server.
-    fn request(&mut self, socket: &mut UdpSocket) -> Result<()> {
+    fn request(&mut self, socket: &mut impl Transmit) -> Result<()> {
         let sntp_repr = Repr {
             leap_indicator: LeapIndicator::NoWarning,
-            version: 4,
+            version: self.version,
             protocol_mode: ProtocolMode::Client,
             stratum: Stratum::KissOfDeath,
             poll_interval: 0,
-            precision: 0,
+            precision: self.precision,
             root_delay: 0,
             root_dispersion: 0,
             ref_identifier: [0, 0, 0, 0],
@@ -209,7 +979,995 @@ impl Client {
         let mut packet = socket.send(sntp_repr.buffer_len(), endpoint)?;
         let mut sntp_packet = Packet::new_unchecked(&mut packet);
         sntp_repr.emit(&mut sntp_packet)?;
+        net_trace_bytes!("SNTP send", sntp_packet.into_inner());
 
         Ok(())
     }
 }
+
+/// Returns the bit of `mode` in a set of accepted modes, or zero for unknown values.
+fn mode_bit(mode: ProtocolMode) -> u8 {
+    match u8::from(mode) {
+        value @ 0..=7 => 1 << value,
+        _ => 0,
+    }
+}
+
+/// Computes the root synchronization distance of a reply received `rtt` after its request.
+fn max_error(repr: &Repr, rtt: Duration) -> Option<Duration> {
+    if let Stratum::Reserved(_) = repr.stratum {
+        return None;
+    }
+
+    // Divides rounding up, as `u64::div_ceil()` is too recent for the supported toolchains
+    fn div_ceil(n: u64, d: u64) -> u64 {
+        match n % d {
+            0 => n / d,
+            _ => n / d + 1,
+        }
+    }
+
+    // Converts an unsigned 16.16 fixed-point number of seconds to microseconds, rounding up
+    fn fixed_to_us(value: u32) -> u64 {
+        (u64::from(value) * 1_000_000 + 0xffff) >> 16
+    }
+
+    // Converts a power of two number of seconds to microseconds, rounding up
+    fn log2_to_us(exp: i8) -> u64 {
+        if exp >= 0 {
+            1_000_000 << exp.min(20)
+        } else {
+            let shift = (-i32::from(exp)).min(63);
+            (1_000_000 + (1 << shift) - 1) >> shift
+        }
+    }
+
+    let rtt_us = rtt.total_millis() * 1_000;
+    let root_delay_us = fixed_to_us(repr.root_delay.max(0) as u32);
+    let dispersion_us = log2_to_us(repr.precision)
+        + LOCAL_PRECISION_US
+        + div_ceil(rtt_us * FREQ_TOLERANCE_PPM, 1_000_000);
+
+    let distance_us =
+        (root_delay_us + rtt_us) / 2 + fixed_to_us(repr.root_dispersion) + dispersion_us;
+    Some(Duration::from_millis(div_ceil(distance_us, 1_000)))
+}
+
+/// Computes the jitter of the round-trip `delays`, in milliseconds, see `Client::jitter()`.
+fn jitter(delays: &[u32]) -> Option<Duration> {
+    // Integer square root, rounding down
+    fn isqrt(n: u128) -> u128 {
+        if n == 0 {
+            return 0;
+        }
+        let mut x = n;
+        let mut y = (x + n / x) / 2;
+        while y < x {
+            x = y;
+            y = (x + n / x) / 2;
+        }
+        x
+    }
+
+    if delays.len() < 2 {
+        return None;
+    }
+    let min = delays.iter().min().copied().unwrap_or(0);
+    let sum: u128 = delays
+        .iter()
+        .map(|delay| u128::from(delay - min).pow(2))
+        .sum();
+    let variance = sum / (delays.len() as u128 - 1);
+    Some(Duration::from_millis(isqrt(variance) as u64))
+}
+
+/// Coordinates several SNTP clients, e.g. querying a primary and a backup server.
+///
+/// Each client retains its own request schedule, but outgoing requests are staggered
+/// so that no two of them are sent less than `min_spacing` apart. A client whose request
+/// is due while the spacing has not elapsed yet simply sends it at a later poll.
+pub struct Coordinator<'a> {
+    clients: ManagedSlice<'a, Client>,
+    min_spacing: Duration,
+    last_request: Option<Instant>,
+}
+
+impl<'a> Coordinator<'a> {
+    /// Creates a coordinator for the given clients.
+    pub fn new<C>(clients: C, min_spacing: Duration) -> Self
+    where
+        C: Into<ManagedSlice<'a, Client>>,
+    {
+        Coordinator {
+            clients: clients.into(),
+            min_spacing,
+            last_request: None,
+        }
+    }
+
+    /// Returns the coordinated clients.
+    pub fn clients(&self) -> &[Client] {
+        &self.clients
+    }
+
+    /// Returns the coordinated clients, e.g. to update their configuration.
+    pub fn clients_mut(&mut self) -> &mut [Client] {
+        &mut self.clients
+    }
+
+    /// Returns the duration until the next packet request.
+    ///
+    /// Useful for suspending execution after polling.
+    #[must_use]
+    pub fn next_poll(&self, now: Instant) -> Duration {
+        let next_request = self.clients.iter().map(|c| c.next_request).min();
+        let next_allowed = self.last_request.map(|t| t + self.min_spacing);
+
+        match (next_request, next_allowed) {
+            (Some(a), Some(b)) if a.max(b) > now => a.max(b) - now,
+            (Some(a), None) if a > now => a - now,
+            _ => Duration::from_millis(0),
+        }
+    }
+
+    /// Polls all the clients, sending at most one request.
+    ///
+    /// If any of the clients receives a valid response, the corresponding Unix timestamp
+    /// is returned. When several responses are received in the same poll,
+    /// the one from the client coming first takes precedence.
+    #[must_use = "a received timestamp is lost if the result is discarded"]
+    pub fn poll(&mut self, sockets: &mut SocketSet, now: Instant) -> Result<Option<u32>> {
+        let mut timestamp = None;
+
+        for client in self.clients.iter_mut() {
+            let may_request = match self.last_request {
+                Some(last) => now >= last + self.min_spacing,
+                None => true,
+            };
+
+            let (ts, activity) = client.poll_with(sockets, now, may_request)?;
+            if activity.sent > 0 {
+                self.last_request = Some(now);
+            }
+            timestamp = timestamp.or(ts);
+        }
+
+        Ok(timestamp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::{self, Datagram, Network};
+    use crate::net::wire::IpEndpoint;
+    use std::{vec, vec::Vec};
+
+    /// Unix timestamp of the replies sent by the test server.
+    const SERVER_TIME: u32 = 1_589_753_489;
+
+    /// An SNTP client attached to a mock network.
+    struct Harness {
+        net: Network,
+        sockets: SocketSet<'static, 'static, 'static>,
+        client: Client,
+        server: IpEndpoint,
+        now: Instant,
+    }
+
+    impl Harness {
+        fn new() -> Harness {
+            let mut sockets = mock::socket_set(1);
+            let now = Instant::from_millis(0);
+            let server = mock::remote_ep(1, SNTP_PORT);
+            let client = Client::new(
+                &mut sockets,
+                mock::udp_buffer(2, 256),
+                mock::udp_buffer(2, 256),
+                server.addr,
+                now,
+            );
+
+            Harness {
+                net: Network::new(),
+                sockets,
+                client,
+                server,
+                now,
+            }
+        }
+
+        fn poll(&mut self) -> (Option<u32>, Vec<Datagram>) {
+            self.net.poll(&mut self.sockets, self.now);
+            let res = self.client.poll(&mut self.sockets, self.now).unwrap();
+            self.net.poll(&mut self.sockets, self.now);
+            (res, self.net.transmitted())
+        }
+
+        /// Lets the client send a request and answers it with `timestamp`.
+        fn exchange(&mut self, timestamp: u32) -> Option<u32> {
+            let (_, sent) = self.poll();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(sent[0].dst, self.server);
+            self.respond(timestamp)
+        }
+
+        /// Sends a reply with `timestamp` to the client.
+        fn respond(&mut self, timestamp: u32) -> Option<u32> {
+            let bytes = response(timestamp);
+            let to = self.local_ep();
+            self.net.inject(self.server, to, &bytes);
+
+            self.poll().0
+        }
+
+        /// Returns the endpoint the client socket is bound to.
+        fn local_ep(&mut self) -> IpEndpoint {
+            let handle = self.client.socket_handle().unwrap();
+            mock::local_ep(self.sockets.get::<UdpSocket>(handle).endpoint().port)
+        }
+    }
+
+    /// Builds a server response carrying `timestamp`.
+    fn response(timestamp: u32) -> Vec<u8> {
+        let repr = Repr {
+            leap_indicator: LeapIndicator::NoWarning,
+            version: 4,
+            protocol_mode: ProtocolMode::Server,
+            stratum: Stratum::Secondary(2),
+            poll_interval: 0,
+            precision: 0,
+            root_delay: 0,
+            root_dispersion: 0,
+            ref_identifier: [0, 0, 0, 0],
+            ref_timestamp: Timestamp {
+                sec: timestamp.wrapping_sub(DIFF_SEC_1970_2036 + 60),
+                frac: 0,
+            },
+            orig_timestamp: Timestamp::default(),
+            recv_timestamp: Timestamp::default(),
+            xmit_timestamp: Timestamp {
+                sec: timestamp.wrapping_sub(DIFF_SEC_1970_2036),
+                frac: 0,
+            },
+        };
+        let mut bytes = vec![0; repr.buffer_len()];
+        repr.emit(&mut Packet::new_unchecked(&mut bytes)).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_version() {
+        let server = mock::remote_ep(1, SNTP_PORT);
+        let mut reply = response(SERVER_TIME);
+        Packet::new_unchecked(&mut reply).set_version(3);
+
+        // Pinned to v4: NTPv3 replies are discarded
+        let mut client = Client::detached(server.addr, Instant::from_millis(0));
+        assert_eq!(
+            client.process_datagram(&reply, Instant::from_millis(0)),
+            None
+        );
+        assert_eq!(client.reply_version(), None);
+
+        // Compatible with NTPv3 servers
+        let mut tx = mock::Outbox::default();
+        client.set_version(3);
+        client
+            .process_timeouts(&mut tx, Instant::from_millis(0))
+            .unwrap();
+        let request = Packet::new_checked(&tx.sent[0].1[..]).unwrap();
+        assert_eq!(request.version(), 3);
+
+        let now = Instant::from_millis(20);
+        assert_eq!(client.process_datagram(&reply, now), Some(SERVER_TIME));
+        assert_eq!(client.reply_version(), Some(3));
+    }
+
+    #[test]
+    fn test_hop_limit() {
+        let mut h = Harness::new();
+        let (_, sent) = h.poll();
+        assert_eq!(sent[0].hop_limit, 64);
+
+        // Keep requests on the local link
+        h.client.set_hop_limit(Some(1));
+        assert_eq!(h.client.hop_limit(), Some(1));
+        h.client.reset(h.now);
+        let (_, sent) = h.poll();
+        assert_eq!(sent[0].hop_limit, 1);
+        let handle = h.client.socket_handle().unwrap();
+        assert_eq!(h.sockets.get::<UdpSocket>(handle).hop_limit(), Some(1));
+    }
+
+    #[test]
+    fn test_endpoint_filter() {
+        let mut h = Harness::new();
+        h.client
+            .set_endpoint_filter(Some(|ep| ep.addr == mock::remote_ep(1, SNTP_PORT).addr));
+        assert!(h.client.endpoint_filter().is_some());
+        let (_, sent) = h.poll();
+        assert_eq!(sent.len(), 1);
+
+        // Replies from other hosts are ignored
+        let to = h.local_ep();
+        h.net
+            .inject(mock::remote_ep(2, SNTP_PORT), to, &response(SERVER_TIME));
+        assert_eq!(h.poll().0, None);
+
+        assert_eq!(h.respond(SERVER_TIME), Some(SERVER_TIME));
+    }
+
+    #[test]
+    fn test_precision() {
+        let mut client = Client::detached(IpAddress::v4(10, 0, 0, 1), Instant::from_millis(0));
+        let mut tx = mock::Outbox::default();
+        let mut request = |client: &mut Client| {
+            client.reset(Instant::from_millis(0));
+            client
+                .process_timeouts(&mut tx, Instant::from_millis(0))
+                .unwrap();
+            Packet::new_checked(&tx.sent.last().unwrap().1[..])
+                .unwrap()
+                .precision()
+        };
+
+        assert_eq!(client.precision(), DEFAULT_PRECISION);
+        assert_eq!(request(&mut client), -10);
+
+        client.set_precision(-20);
+        assert_eq!(client.precision(), -20);
+        assert_eq!(request(&mut client), -20);
+    }
+
+    #[test]
+    fn test_jitter() {
+        assert_eq!(jitter(&[]), None);
+        assert_eq!(jitter(&[100]), None);
+        assert_eq!(jitter(&[100, 100]), Some(Duration::from_millis(0)));
+        // Deviations of 0, 10, 30 and 0 ms from the shortest delay: sqrt(1000 / 3)
+        assert_eq!(
+            jitter(&[100, 110, 130, 100]),
+            Some(Duration::from_millis(18))
+        );
+        assert_eq!(
+            jitter(&[u32::MAX, 0]),
+            Some(Duration::from_millis(u64::from(u32::MAX)))
+        );
+
+        let mut h = Harness::new();
+        assert_eq!(h.client.jitter(), None);
+        for delay in [100, 110, 130, 100].iter() {
+            h.client.reset(h.now);
+            assert_eq!(h.poll().1.len(), 1);
+            h.now += Duration::from_millis(*delay);
+            assert_eq!(h.respond(SERVER_TIME), Some(SERVER_TIME));
+        }
+        assert_eq!(h.client.jitter(), Some(Duration::from_millis(18)));
+
+        // Only the most recent delays are kept
+        for _ in 0..JITTER_SAMPLES {
+            h.client.reset(h.now);
+            h.poll();
+            h.now += Duration::from_millis(50);
+            h.respond(SERVER_TIME);
+        }
+        assert_eq!(h.client.jitter(), Some(Duration::from_millis(0)));
+    }
+
+    #[test]
+    fn test_max_error() {
+        let mut repr =
+            Repr::parse(&Packet::new_checked(&response(SERVER_TIME)[..]).unwrap()).unwrap();
+
+        // 1 µs precision and no delay, which leaves the local clock resolution
+        repr.precision = -20;
+        assert_eq!(
+            max_error(&repr, Duration::from_millis(0)),
+            Some(Duration::from_millis(2))
+        );
+
+        // 500 ms root delay, 250 ms root dispersion and 100 ms round trip
+        repr.root_delay = 0x8000;
+        repr.root_dispersion = 0x4000;
+        assert_eq!(
+            max_error(&repr, Duration::from_millis(100)),
+            Some(Duration::from_millis(552))
+        );
+
+        // A negative root delay doesn't reduce the error
+        repr.root_delay = -0x8000;
+        assert_eq!(
+            max_error(&repr, Duration::from_millis(100)),
+            Some(Duration::from_millis(302))
+        );
+
+        // A server with 1 s precision
+        repr.precision = 0;
+        assert_eq!(
+            max_error(&repr, Duration::from_millis(100)),
+            Some(Duration::from_millis(1_302))
+        );
+
+        // The error of an unsynchronized server is unbounded
+        repr.stratum = Stratum::Reserved(16);
+        assert_eq!(max_error(&repr, Duration::from_millis(100)), None);
+    }
+
+    #[test]
+    fn test_max_error_of_reply() {
+        let mut h = Harness::new();
+        assert_eq!(h.client.max_error(), None);
+
+        assert_eq!(h.poll().1.len(), 1);
+        h.now += Duration::from_millis(40);
+        assert_eq!(h.respond(SERVER_TIME), Some(SERVER_TIME));
+        // The server precision of 1 s dominates the 40 ms round trip
+        assert_eq!(h.client.max_error(), Some(Duration::from_millis(1_022)));
+    }
+
+    #[test]
+    fn test_own_request_ignored() {
+        let mut h = Harness::new();
+        let (_, sent) = h.poll();
+        assert_eq!(sent.len(), 1);
+
+        // A copy of the request looped back by the link is not mistaken for a reply
+        let to = h.local_ep();
+        h.net.inject(to, to, &sent[0].payload);
+        assert_eq!(h.poll(), (None, vec![]));
+        assert!(!h.client.is_idle());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut h = Harness::new();
+        assert_eq!(h.poll().1.len(), 1);
+
+        // The pending request is abandoned, and its reply discarded
+        h.client.cancel();
+        assert!(h.client.is_cancelled() && h.client.is_idle());
+        assert_eq!(h.respond(SERVER_TIME), None);
+
+        // No more requests, however long we wait
+        for _ in 0..4 {
+            h.now += MAX_REQUEST_INTERVAL;
+            assert_eq!(h.poll(), (None, vec![]));
+            assert_eq!(h.client.next_poll(h.now), MAX_REQUEST_INTERVAL);
+        }
+
+        // Until re-armed
+        h.client.reset(h.now);
+        assert!(!h.client.is_cancelled());
+        assert_eq!(h.exchange(SERVER_TIME), Some(SERVER_TIME));
+    }
+
+    #[test]
+    fn test_next_poll_late() {
+        let mut h = Harness::new();
+        assert_eq!(h.client.next_poll(h.now), Duration::from_millis(0));
+        assert_eq!(h.poll().1.len(), 1);
+        assert_eq!(h.client.next_poll(h.now), MIN_REQUEST_INTERVAL);
+
+        // Past the scheduled request, the client is due immediately
+        let late = h.client.next_poll_at() + Duration::from_secs(5);
+        assert_eq!(h.client.next_poll(late), Duration::from_millis(0));
+    }
+
+    #[test]
+    fn test_accepted_modes() {
+        let server = mock::remote_ep(1, SNTP_PORT);
+        let mut client = Client::detached(server.addr, Instant::from_millis(0));
+        let mut tx = mock::Outbox::default();
+        let now = Instant::from_millis(20);
+        let reply = |mode| {
+            let mut reply = response(SERVER_TIME);
+            Packet::new_unchecked(&mut reply).set_protocol_mode(mode);
+            reply
+        };
+        let mut receive = |client: &mut Client, mode| {
+            client.reset(Instant::from_millis(0));
+            client
+                .process_timeouts(&mut tx, Instant::from_millis(0))
+                .unwrap();
+            client.process_datagram(&reply(mode), now)
+        };
+
+        // Only server replies by default
+        assert!(client.accepts_mode(ProtocolMode::Server));
+        assert_eq!(
+            receive(&mut client, ProtocolMode::Server),
+            Some(SERVER_TIME)
+        );
+        for mode in (0..=7).map(ProtocolMode::from) {
+            if mode != ProtocolMode::Server {
+                assert!(!client.accepts_mode(mode));
+                assert_eq!(receive(&mut client, mode), None, "{:?}", mode);
+            }
+        }
+
+        // Symmetric passive and broadcast peers, but no longer servers
+        client.set_accept_mode(ProtocolMode::SymmetricPassive, true);
+        client.set_accept_mode(ProtocolMode::Broadcast, true);
+        client.set_accept_mode(ProtocolMode::Server, false);
+        assert_eq!(
+            receive(&mut client, ProtocolMode::SymmetricPassive),
+            Some(SERVER_TIME)
+        );
+        assert_eq!(
+            receive(&mut client, ProtocolMode::Broadcast),
+            Some(SERVER_TIME)
+        );
+        assert_eq!(receive(&mut client, ProtocolMode::Server), None);
+        assert_eq!(receive(&mut client, ProtocolMode::SymmetricActive), None);
+
+        // Never our own requests
+        client.set_accept_mode(ProtocolMode::Client, true);
+        assert!(!client.accepts_mode(ProtocolMode::Client));
+        assert_eq!(receive(&mut client, ProtocolMode::Client), None);
+    }
+
+    #[test]
+    fn test_zero_timestamps() {
+        let server = mock::remote_ep(1, SNTP_PORT);
+        let mut client = Client::detached(server.addr, Instant::from_millis(0));
+        let mut tx = mock::Outbox::default();
+        client
+            .process_timeouts(&mut tx, Instant::from_millis(0))
+            .unwrap();
+        let now = Instant::from_millis(20);
+
+        // Not mistaken for the start of era 1
+        let mut reply = response(SERVER_TIME);
+        Packet::new_unchecked(&mut reply).set_xmit_timestamp(Timestamp::default());
+        assert_eq!(client.process_datagram(&reply, now), None);
+
+        // Never synchronized
+        let mut reply = response(SERVER_TIME);
+        Packet::new_unchecked(&mut reply).set_ref_timestamp(Timestamp::default());
+        assert_eq!(client.process_datagram(&reply, now), None);
+
+        assert!(!client.is_idle());
+        assert_eq!(
+            client.process_datagram(&response(SERVER_TIME), now),
+            Some(SERVER_TIME)
+        );
+    }
+
+    #[test]
+    fn test_reply_length() {
+        let server = mock::remote_ep(1, SNTP_PORT);
+        let mut client = Client::detached(server.addr, Instant::from_millis(0));
+        let mut tx = mock::Outbox::default();
+        client
+            .process_timeouts(&mut tx, Instant::from_millis(0))
+            .unwrap();
+        let now = Instant::from_millis(20);
+
+        let mut reply = response(SERVER_TIME);
+        reply.resize(256, 0);
+        assert_eq!(client.process_datagram(&reply, now), None);
+
+        // Authenticated replies are accepted, even though the digest is not checked
+        reply.truncate(MAX_REPLY_LEN);
+        assert_eq!(client.process_datagram(&reply, now), Some(SERVER_TIME));
+    }
+
+    #[test]
+    fn test_datagram_api() {
+        let server = mock::remote_ep(1, SNTP_PORT);
+        let mut client = Client::detached(server.addr, Instant::from_millis(0));
+        let mut tx = mock::Outbox::default();
+
+        // Request sent right away, then only once the timeout expires
+        let now = Instant::from_millis(0);
+        assert_eq!(client.process_timeouts(&mut tx, now), Ok(true));
+        assert_eq!(client.process_timeouts(&mut tx, now), Ok(false));
+        assert_eq!(tx.sent.len(), 1);
+
+        let (ep, bytes) = &tx.sent[0];
+        assert_eq!(*ep, server);
+        let request = Packet::new_checked(&bytes[..]).unwrap();
+        assert_eq!(request.protocol_mode(), ProtocolMode::Client);
+
+        let now = now + Duration::from_millis(20);
+        let reply = response(SERVER_TIME);
+        assert_eq!(client.process_datagram(&reply, now), Some(SERVER_TIME));
+        assert!(client.is_idle());
+
+        // Not attached to any socket
+        let mut sockets = mock::socket_set(1);
+        assert_eq!(client.poll(&mut sockets, now), Err(Error::Illegal));
+    }
+
+    #[test]
+    fn test_burst() {
+        let server = mock::remote_ep(1, SNTP_PORT);
+        let mut client = Client::detached(server.addr, Instant::from_millis(0));
+        let mut tx = mock::Outbox::default();
+        client.set_burst(3);
+
+        // The second reply is 100 s off
+        for (i, offset) in [0, 100, 0].iter().enumerate() {
+            let now = Instant::from_millis(i as i64 * 250);
+            assert_eq!(client.process_timeouts(&mut tx, now), Ok(true));
+            assert_eq!(client.process_timeouts(&mut tx, now), Ok(false));
+
+            let reply = response(SERVER_TIME + offset);
+            let result = client.process_datagram(&reply, now + Duration::from_millis(100));
+            if i < 2 {
+                assert_eq!(result, None);
+                assert!(!client.is_idle());
+            } else {
+                // The outlier doesn't contribute to the result
+                assert_eq!(result, Some(SERVER_TIME));
+            }
+        }
+
+        assert_eq!(tx.sent.len(), 3);
+        assert!(client.is_idle());
+        assert_eq!(
+            client.next_poll_at(),
+            Instant::from_millis(600) + MAX_REQUEST_INTERVAL
+        );
+    }
+
+    #[test]
+    fn test_local_port() {
+        // Requests leave from an ephemeral port by default
+        let mut h = Harness::new();
+        let (_, sent) = h.poll();
+        assert!(sent[0].src.port >= EPHEMERAL_PORT_BASE);
+        assert_eq!(sent[0].dst, h.server);
+
+        // ...or from the configured one, still towards the server port
+        let mut h = Harness::new();
+        h.client.set_local_port(4123);
+        assert_eq!(h.client.local_port(), 4123);
+        let (_, sent) = h.poll();
+        assert_eq!(sent[0].src, mock::local_ep(4123));
+        assert_eq!(sent[0].dst, h.server);
+        assert_eq!(h.respond(SERVER_TIME), Some(SERVER_TIME));
+    }
+
+    #[test]
+    fn test_poll_counted() {
+        let mut h = Harness::new();
+        let poll = |h: &mut Harness| {
+            h.net.poll(&mut h.sockets, h.now);
+            let res = h.client.poll_counted(&mut h.sockets, h.now).unwrap();
+            h.net.poll(&mut h.sockets, h.now);
+            res
+        };
+
+        let (ts, activity) = poll(&mut h);
+        assert_eq!(ts, None);
+        assert_eq!(
+            activity,
+            Activity {
+                received: 0,
+                sent: 1
+            }
+        );
+        assert_eq!(h.net.transmitted().len(), 1);
+
+        // Both replies are processed by the same call, without sending anything
+        let to = h.local_ep();
+        for _ in 0..2 {
+            h.net.inject(h.server, to, &response(SERVER_TIME));
+        }
+        let (ts, activity) = poll(&mut h);
+        assert_eq!(ts, Some(SERVER_TIME));
+        assert_eq!(
+            activity,
+            Activity {
+                received: 2,
+                sent: 0
+            }
+        );
+        assert!(poll(&mut h).1.is_idle());
+    }
+
+    #[test]
+    fn test_queued_replies() {
+        let mut h = Harness::new();
+        assert_eq!(h.poll().1.len(), 1);
+
+        // An invalid reply doesn't hide the valid one queued after it
+        let mut invalid = response(SERVER_TIME + 10);
+        Packet::new_unchecked(&mut invalid).set_version(3);
+        let to = h.local_ep();
+        h.net.inject(h.server, to, &invalid);
+        h.net.inject(h.server, to, &response(SERVER_TIME));
+        assert_eq!(h.poll(), (Some(SERVER_TIME), vec![]));
+
+        // The most recent valid reply wins
+        h.client.reset(h.now);
+        assert_eq!(h.poll().1.len(), 1);
+        h.net.inject(h.server, to, &response(SERVER_TIME + 1));
+        h.net.inject(h.server, to, &response(SERVER_TIME + 2));
+        assert_eq!(h.poll(), (Some(SERVER_TIME + 2), vec![]));
+    }
+
+    #[test]
+    fn test_sanity_window() {
+        let mut h = Harness::new();
+        h.client.set_sanity_window(Some(Duration::from_secs(3600)));
+        h.client.set_time_estimate(SERVER_TIME - 7200, h.now);
+
+        // Out of window: discarded, the client keeps retrying
+        assert_eq!(h.exchange(SERVER_TIME), None);
+
+        // The estimate advances with the monotonic clock
+        h.now += Duration::from_secs(3600);
+        assert_eq!(h.exchange(SERVER_TIME), Some(SERVER_TIME));
+    }
+
+    #[test]
+    fn test_idle() {
+        let mut h = Harness::new();
+        assert!(h.client.is_idle());
+
+        // A request is pending: wake up to retry it soon
+        h.poll();
+        assert!(!h.client.is_idle());
+        assert_eq!(h.client.next_poll_at(), h.now + MIN_REQUEST_INTERVAL);
+
+        // Synchronized: nothing to do until the next scheduled request
+        h.now += Duration::from_secs(1);
+        assert_eq!(h.respond(SERVER_TIME), Some(SERVER_TIME));
+        assert!(h.client.is_idle());
+        assert_eq!(h.client.next_poll_at(), h.now + MAX_REQUEST_INTERVAL);
+    }
+
+    #[test]
+    fn test_fixed_interval() {
+        let mut h = Harness::new();
+        let interval = Duration::from_secs(300);
+        h.client.set_fixed_interval(Some(interval));
+        assert_eq!(h.client.fixed_interval(), Some(interval));
+
+        // Unanswered requests are retried at a constant rate
+        for _ in 0..4 {
+            let start = h.now;
+            assert_eq!(h.poll().1.len(), 1);
+            assert_eq!(h.client.next_poll_at(), start + interval);
+            h.now = h.client.next_poll_at();
+        }
+
+        // ...and so are answered ones
+        for _ in 0..2 {
+            let start = h.now;
+            assert_eq!(h.exchange(SERVER_TIME), Some(SERVER_TIME));
+            assert_eq!(h.client.next_poll_at(), start + interval);
+            h.now = h.client.next_poll_at();
+        }
+
+        // Back to backoff
+        h.client.set_fixed_interval(None);
+        assert_eq!(h.exchange(SERVER_TIME), Some(SERVER_TIME));
+        assert_eq!(h.client.next_poll_at(), h.now + MAX_REQUEST_INTERVAL);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut h = Harness::new();
+
+        // Back off a few times
+        for _ in 0..3 {
+            h.poll();
+            h.now = h.client.next_poll_at();
+        }
+        assert!(h.client.curr_interval > MIN_REQUEST_INTERVAL);
+
+        h.client.reset(h.now);
+        assert_eq!(h.client.curr_interval, MIN_REQUEST_INTERVAL);
+        assert_eq!(h.client.next_poll_at(), h.now);
+        assert!(h.client.is_idle());
+
+        assert_eq!(h.exchange(SERVER_TIME), Some(SERVER_TIME));
+    }
+
+    #[test]
+    fn test_pool() {
+        let mut h = Harness::new();
+        let backup = mock::remote_ep(2, SNTP_PORT);
+        let mut entries = [
+            PoolEntry::new(h.server.addr, 1),
+            PoolEntry::new(backup.addr, 1),
+        ];
+        let mut pool = ServerPool::new(&mut entries[..]);
+
+        let poll = |h: &mut Harness, pool: &mut ServerPool| {
+            h.net.poll(&mut h.sockets, h.now);
+            let res = h.client.poll_pool(&mut h.sockets, pool, h.now).unwrap();
+            h.net.poll(&mut h.sockets, h.now);
+            (res, h.net.transmitted())
+        };
+
+        // The first server doesn't answer, so the retry goes to the backup one
+        let (_, sent) = poll(&mut h, &mut pool);
+        assert_eq!(sent[0].dst, h.server);
+
+        h.now = h.client.next_poll_at();
+        let (_, sent) = poll(&mut h, &mut pool);
+        assert_eq!(sent[0].dst, backup);
+
+        // The backup server replies and keeps being preferred
+        h.now += Duration::from_millis(20);
+        h.server = backup;
+        h.respond(SERVER_TIME);
+        assert_eq!(pool.select(), Some(backup.addr));
+    }
+
+    #[test]
+    fn test_from_dhcp() {
+        let mut sockets = mock::socket_set(2);
+        let now = Instant::from_millis(0);
+        let (primary, backup) = (mock::remote_ep(1, SNTP_PORT), mock::remote_ep(2, SNTP_PORT));
+        let addrs = [Ipv4Address::new(10, 0, 0, 1), Ipv4Address::new(10, 0, 0, 2)];
+
+        let no_servers = Client::from_dhcp(
+            &mut sockets,
+            mock::udp_buffer(2, 256),
+            mock::udp_buffer(2, 256),
+            &[],
+            now,
+        );
+        assert!(no_servers.is_none());
+
+        let client = Client::from_dhcp(
+            &mut sockets,
+            mock::udp_buffer(2, 256),
+            mock::udp_buffer(2, 256),
+            &addrs,
+            now,
+        )
+        .unwrap();
+        let mut h = Harness {
+            net: Network::new(),
+            sockets,
+            client,
+            server: primary,
+            now,
+        };
+
+        // The first server doesn't answer, so the retry goes to the second one
+        let (_, sent) = h.poll();
+        assert_eq!(sent[0].dst, primary);
+
+        h.now = h.client.next_poll_at();
+        let (_, sent) = h.poll();
+        assert_eq!(sent[0].dst, backup);
+
+        h.server = backup;
+        assert_eq!(h.respond(SERVER_TIME), Some(SERVER_TIME));
+        assert_eq!(h.client.server(), backup.addr);
+    }
+
+    #[test]
+    fn test_pool_unreachable() {
+        let mut h = Harness::new();
+        let backup = mock::remote_ep(2, SNTP_PORT);
+        let mut entries = [
+            PoolEntry::new(h.server.addr, 1),
+            PoolEntry::new(backup.addr, 1),
+        ];
+        let mut pool = ServerPool::new(&mut entries[..]);
+
+        let poll = |h: &mut Harness, pool: &mut ServerPool| {
+            h.net.poll(&mut h.sockets, h.now);
+            h.client.poll_pool(&mut h.sockets, pool, h.now).unwrap();
+            h.net.poll(&mut h.sockets, h.now);
+            h.net.transmitted()
+        };
+
+        let sent = poll(&mut h, &mut pool);
+        assert_eq!(sent[0].dst, h.server);
+
+        // Only the server of the pending request matters
+        assert!(!h.client.peer_unreachable(backup.addr));
+        h.now += Duration::from_millis(10);
+        assert!(poll(&mut h, &mut pool).is_empty());
+
+        // The backup server is queried without waiting for the timeout
+        assert!(h.client.peer_unreachable(h.server.addr));
+        h.now += Duration::from_millis(10);
+        let sent = poll(&mut h, &mut pool);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dst, backup);
+        assert!(h.now < h.client.next_poll_at());
+
+        // With nowhere else to go, the schedule is kept
+        let mut entries = [PoolEntry::new(backup.addr, 1)];
+        let mut pool = ServerPool::new(&mut entries[..]);
+        assert!(h.client.peer_unreachable(backup.addr));
+        let next_poll = h.client.next_poll_at();
+        assert!(poll(&mut h, &mut pool).is_empty());
+        assert_eq!(h.client.next_poll_at(), next_poll);
+    }
+
+    #[test]
+    fn test_seeded_estimate() {
+        let mut h = Harness::new();
+        h.client.set_sanity_window(Some(Duration::from_secs(3600)));
+        h.client.seed_estimate(SERVER_TIME - 30 * 86_400, h.now);
+
+        // Replies earlier than the last known good time are discarded
+        assert_eq!(h.exchange(SERVER_TIME - 31 * 86_400), None);
+
+        // Later ones are accepted, however long the device has been off
+        h.now += Duration::from_secs(60);
+        assert_eq!(h.exchange(SERVER_TIME), Some(SERVER_TIME));
+
+        // From then on, the window applies both ways
+        h.client.reset(h.now);
+        assert_eq!(h.exchange(SERVER_TIME + 7200), None);
+    }
+
+    #[test]
+    fn test_sanity_window_disabled() {
+        let mut h = Harness::new();
+        h.client.set_sanity_window(Some(Duration::from_secs(1)));
+        assert_eq!(h.exchange(SERVER_TIME), Some(SERVER_TIME));
+    }
+
+    #[test]
+    fn test_coordinator_staggers_requests() {
+        let mut sockets = mock::socket_set(2);
+        let mut net = Network::new();
+        let mut now = Instant::from_millis(0);
+
+        let mut clients = (1..=2)
+            .map(|host| {
+                Client::new(
+                    &mut sockets,
+                    mock::udp_buffer(2, 256),
+                    mock::udp_buffer(2, 256),
+                    mock::remote_ep(host, SNTP_PORT).addr,
+                    now,
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut coordinator = Coordinator::new(&mut clients[..], Duration::from_secs(1));
+
+        let mut poll = |coordinator: &mut Coordinator, now| {
+            net.poll(&mut sockets, now);
+            coordinator.poll(&mut sockets, now).unwrap();
+            net.poll(&mut sockets, now);
+            net.transmitted()
+        };
+
+        // Both requests are due, but only the first one goes out
+        let sent = poll(&mut coordinator, now);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dst, mock::remote_ep(1, SNTP_PORT));
+        assert!(poll(&mut coordinator, now).is_empty());
+        assert_eq!(coordinator.next_poll(now), Duration::from_secs(1));
+
+        // The second one is delayed until the spacing has elapsed
+        now += Duration::from_millis(500);
+        assert!(poll(&mut coordinator, now).is_empty());
+
+        now += Duration::from_millis(500);
+        let sent = poll(&mut coordinator, now);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dst, mock::remote_ep(2, SNTP_PORT));
+    }
+
+    #[test]
+    #[cfg(feature = "trace-bytes")]
+    fn test_trace_bytes() {
+        mock::logger::install();
+
+        let mut h = Harness::new();
+        h.exchange(SERVER_TIME);
+
+        // Both the request and the reply are dumped, up to the maximum length
+        let lines = mock::logger::take();
+        assert!(lines
+            .iter()
+            .any(|l| l.starts_with("SNTP send: [48] 23 00") && l.ends_with(" ...")));
+        assert!(lines.iter().any(|l| l.starts_with("SNTP recv: [48] ")));
+    }
+}
@@ -0,0 +1,165 @@
+//! Selection of the best server to query among a set of candidates.
+
+use crate::net::{time::Duration, wire::IpAddress};
+use managed::ManagedSlice;
+
+/// Score penalty for each stratum level, equivalent to that many milliseconds of delay.
+const STRATUM_PENALTY: u32 = 50;
+
+/// Score penalty for each unanswered request, equivalent to that many milliseconds of delay.
+const FAILURE_PENALTY: u32 = 1_000;
+
+/// A server in a [`ServerPool`], along with the statistics of its past replies.
+///
+/// [`ServerPool`]: struct.ServerPool.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolEntry {
+    addr: IpAddress,
+    weight: u8,
+    delay_ms: u32,
+    stratum: u8,
+    failures: u32,
+    measured: bool,
+}
+
+impl PoolEntry {
+    /// Creates an entry for the server at `addr`.
+    ///
+    /// Servers with a higher `weight` are preferred over others with similar statistics.
+    /// A weight of zero is treated as one.
+    pub fn new(addr: IpAddress, weight: u8) -> Self {
+        PoolEntry {
+            addr,
+            weight: weight.max(1),
+            delay_ms: 0,
+            stratum: 0,
+            failures: 0,
+            measured: false,
+        }
+    }
+
+    /// Returns the address of the server.
+    pub fn addr(&self) -> IpAddress {
+        self.addr
+    }
+
+    /// Returns the score of the server: the lower, the better.
+    ///
+    /// Servers that never replied are only scored by their unanswered requests,
+    /// so that they are tried at least once.
+    fn score(&self) -> u32 {
+        let penalty = self.failures.saturating_mul(FAILURE_PENALTY);
+        if !self.measured {
+            return penalty;
+        }
+        let cost = self
+            .delay_ms
+            .saturating_add(u32::from(self.stratum) * STRATUM_PENALTY)
+            .saturating_add(penalty);
+        cost / u32::from(self.weight)
+    }
+}
+
+/// A set of SNTP servers, ranked according to their past replies.
+///
+/// Each server is scored from the round-trip delay and the stratum of its last reply,
+/// and from the number of requests it left unanswered since then, scaled down by its weight.
+/// The pool is meant to be used along with `Client::poll_pool()`.
+pub struct ServerPool<'a> {
+    entries: ManagedSlice<'a, PoolEntry>,
+}
+
+impl<'a> ServerPool<'a> {
+    /// Creates a pool with the given servers.
+    pub fn new<E>(entries: E) -> Self
+    where
+        E: Into<ManagedSlice<'a, PoolEntry>>,
+    {
+        ServerPool {
+            entries: entries.into(),
+        }
+    }
+
+    /// Returns the servers in the pool.
+    pub fn entries(&self) -> &[PoolEntry] {
+        &self.entries
+    }
+
+    /// Returns the address of the best server to query next, if there is any.
+    ///
+    /// Ties are resolved in favor of the server coming first.
+    pub fn select(&self) -> Option<IpAddress> {
+        self.entries
+            .iter()
+            .enumerate()
+            .min_by_key(|(idx, entry)| (entry.score(), *idx))
+            .map(|(_, entry)| entry.addr)
+    }
+
+    /// Records a reply received from `addr`, `delay` after the request was sent.
+    pub fn record_reply(&mut self, addr: IpAddress, stratum: u8, delay: Duration) {
+        if let Some(entry) = self.entry_mut(addr) {
+            entry.delay_ms = delay.total_millis().min(u64::from(u32::MAX)) as u32;
+            entry.stratum = stratum;
+            entry.failures = 0;
+            entry.measured = true;
+        }
+    }
+
+    /// Records a request sent to `addr` that was left unanswered.
+    pub fn record_failure(&mut self, addr: IpAddress) {
+        if let Some(entry) = self.entry_mut(addr) {
+            entry.failures = entry.failures.saturating_add(1);
+        }
+    }
+
+    fn entry_mut(&mut self, addr: IpAddress) -> Option<&mut PoolEntry> {
+        self.entries.iter_mut().find(|entry| entry.addr == addr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::wire::Ipv4Address;
+
+    const SERVER_A: IpAddress = IpAddress::Ipv4(Ipv4Address([10, 0, 0, 1]));
+    const SERVER_B: IpAddress = IpAddress::Ipv4(Ipv4Address([10, 0, 0, 2]));
+
+    #[test]
+    fn test_prefers_lower_delay() {
+        let mut entries = [PoolEntry::new(SERVER_A, 1), PoolEntry::new(SERVER_B, 1)];
+        let mut pool = ServerPool::new(&mut entries[..]);
+        assert_eq!(pool.select(), Some(SERVER_A));
+
+        pool.record_reply(SERVER_A, 2, Duration::from_millis(800));
+        pool.record_reply(SERVER_B, 2, Duration::from_millis(30));
+        assert_eq!(pool.select(), Some(SERVER_B));
+    }
+
+    #[test]
+    fn test_stratum_weight_and_failures() {
+        let mut entries = [PoolEntry::new(SERVER_A, 1), PoolEntry::new(SERVER_B, 1)];
+        let mut pool = ServerPool::new(&mut entries[..]);
+
+        // Same delay, lower stratum wins
+        pool.record_reply(SERVER_A, 3, Duration::from_millis(30));
+        pool.record_reply(SERVER_B, 1, Duration::from_millis(30));
+        assert_eq!(pool.select(), Some(SERVER_B));
+
+        // Unanswered requests push a server down the ranking
+        pool.record_failure(SERVER_B);
+        assert_eq!(pool.select(), Some(SERVER_A));
+
+        // Until it replies again
+        pool.record_reply(SERVER_B, 1, Duration::from_millis(30));
+        assert_eq!(pool.select(), Some(SERVER_B));
+
+        // A higher weight compensates for a worse delay
+        let mut entries = [PoolEntry::new(SERVER_A, 4), PoolEntry::new(SERVER_B, 1)];
+        let mut pool = ServerPool::new(&mut entries[..]);
+        pool.record_reply(SERVER_A, 1, Duration::from_millis(100));
+        pool.record_reply(SERVER_B, 1, Duration::from_millis(50));
+        assert_eq!(pool.select(), Some(SERVER_A));
+    }
+}
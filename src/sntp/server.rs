@@ -0,0 +1,341 @@
+//! Simple Network Time Protocol server, answering unicast requests.
+
+use super::{DEFAULT_PRECISION, MAX_REPLY_LEN, SNTP_PORT};
+use crate::net::{
+    socket::{SocketHandle, SocketSet, UdpSocket, UdpSocketBuffer},
+    wire::{IpAddress, IpEndpoint},
+    {Error, Result},
+};
+use crate::transport::Transmit;
+use crate::wire::sntp::{LeapIndicator, Packet, ProtocolMode, Repr, Stratum, Timestamp};
+
+/// A source of the current NTP time, read by the server to timestamp its replies.
+pub type Clock = fn() -> Timestamp;
+
+/// SNTPv4 server, answering the requests of unicast clients (RFC 4330).
+///
+/// The server has no clock of its own: each reply is timestamped by reading the [`Clock`]
+/// it is given, which should be disciplined by other means, e.g. a GPS receiver or
+/// an upstream SNTP [`Client`]. Until `Server::set_reference()` is called, the server
+/// reports itself unsynchronized, and well-behaved clients discard its replies.
+///
+/// The clock is read twice for each request, so that the time spent by the server is
+/// accounted for by clients: once upon receipt, for the receive timestamp, and once last
+/// thing before the reply is emitted, for the transmit timestamp. The transmit timestamp
+/// is guaranteed not to precede the receive timestamp, even if the clock steps back
+/// in between.
+///
+/// [`Clock`]: type.Clock.html
+/// [`Client`]: struct.Client.html
+pub struct Server {
+    udp_handle: Option<SocketHandle>,
+    clock: Clock,
+    /// Precision of the clock advertised in replies, in log2 seconds.
+    precision: i8,
+    /// Leap second warning advertised in replies.
+    leap_indicator: LeapIndicator,
+    /// Stratum, reference identifier and reference timestamp, once synchronized.
+    reference: Option<(Stratum, [u8; 4], Timestamp)>,
+}
+
+impl Server {
+    /// Creates an SNTP server reading the time from `clock`.
+    ///
+    /// A new socket will be allocated and added to the provided `SocketSet`,
+    /// to be bound to the SNTP port at the first `Server::serve()`.
+    pub fn new<'a, 'b, 'c>(
+        sockets: &mut SocketSet<'a, 'b, 'c>,
+        rx_buffer: UdpSocketBuffer<'b, 'c>,
+        tx_buffer: UdpSocketBuffer<'b, 'c>,
+        clock: Clock,
+    ) -> Self {
+        let socket = UdpSocket::new(rx_buffer, tx_buffer);
+        let udp_handle = sockets.add(socket);
+
+        net_trace!("SNTP server initialised");
+
+        Server {
+            udp_handle: Some(udp_handle),
+            ..Server::detached(clock)
+        }
+    }
+
+    /// Creates an SNTP server that is not attached to any socket.
+    ///
+    /// The server must then be driven through `Server::process_datagram()`,
+    /// while `Server::serve()` fails with `Err(Error::Illegal)`.
+    pub fn detached(clock: Clock) -> Self {
+        Server {
+            udp_handle: None,
+            clock,
+            precision: DEFAULT_PRECISION,
+            leap_indicator: LeapIndicator::NoWarning,
+            reference: None,
+        }
+    }
+
+    /// Returns the handle of the UDP socket used by the server, if it's attached to one.
+    pub fn socket_handle(&self) -> Option<SocketHandle> {
+        self.udp_handle
+    }
+
+    /// Sets the precision of the clock advertised in replies, in log2 seconds
+    /// (-10, about a millisecond, by default).
+    pub fn set_precision(&mut self, precision: i8) {
+        self.precision = precision;
+    }
+
+    /// Returns the precision of the clock advertised in replies, in log2 seconds.
+    pub fn precision(&self) -> i8 {
+        self.precision
+    }
+
+    /// Sets the leap second warning advertised in replies (none by default).
+    pub fn set_leap_indicator(&mut self, leap_indicator: LeapIndicator) {
+        self.leap_indicator = leap_indicator;
+    }
+
+    /// Returns the leap second warning advertised in replies.
+    pub fn leap_indicator(&self) -> LeapIndicator {
+        self.leap_indicator
+    }
+
+    /// Marks the clock as synchronized to a reference, e.g. after it has been set.
+    ///
+    /// `ref_identifier` is the address of the upstream server for secondary servers,
+    /// or a four-character code such as `GPS\0` for primary ones. `ref_timestamp` is
+    /// the time at which the clock was last set or corrected.
+    pub fn set_reference(
+        &mut self,
+        stratum: Stratum,
+        ref_identifier: [u8; 4],
+        ref_timestamp: Timestamp,
+    ) {
+        self.reference = Some((stratum, ref_identifier, ref_timestamp));
+    }
+
+    /// Marks the clock as unsynchronized, e.g. after its reference has been lost.
+    pub fn clear_reference(&mut self) {
+        self.reference = None;
+    }
+
+    /// Returns `true` if the clock is synchronized to a reference.
+    pub fn is_synchronized(&self) -> bool {
+        self.reference.is_some()
+    }
+
+    /// Answers all the requests queued in the socket, returning how many were answered.
+    ///
+    /// Requests are timestamped when they are processed here rather than when they arrived,
+    /// so this should be called right after `Interface::poll()`.
+    pub fn serve(&mut self, sockets: &mut SocketSet) -> Result<usize> {
+        let handle = self.udp_handle.ok_or(Error::Illegal)?;
+        let mut socket = sockets.get::<UdpSocket>(handle);
+
+        if !socket.is_open() {
+            socket.bind(IpEndpoint {
+                addr: IpAddress::Unspecified,
+                port: SNTP_PORT,
+            })?;
+        }
+
+        let mut answered = 0;
+        let mut buffer = [0; MAX_REPLY_LEN];
+        while socket.can_send() {
+            let (len, ep) = match socket.recv() {
+                Ok((payload, _)) if payload.len() > buffer.len() => continue,
+                Ok((payload, ep)) => {
+                    buffer[..payload.len()].copy_from_slice(payload);
+                    (payload.len(), ep)
+                }
+                Err(Error::Exhausted) => break,
+                Err(e) => return Err(e),
+            };
+            if self.process_datagram(&buffer[..len], ep, &mut *socket)? {
+                answered += 1;
+            }
+        }
+        Ok(answered)
+    }
+
+    /// Processes a datagram received from `ep`, sending the reply through `tx`.
+    ///
+    /// This is the socket-less equivalent of `Server::serve()`. Returns `true` if the datagram
+    /// was a client request and got answered, and `false` if it was dropped.
+    pub fn process_datagram<T>(&mut self, data: &[u8], ep: IpEndpoint, tx: &mut T) -> Result<bool>
+    where
+        T: Transmit,
+    {
+        // Timestamp the request first, as close as possible to its arrival
+        let recv_timestamp = (self.clock)();
+
+        let request = match Packet::new_checked(data).and_then(|packet| Repr::parse(&packet)) {
+            Ok(request) => request,
+            Err(_) => return Ok(false),
+        };
+        if request.protocol_mode != ProtocolMode::Client || !(1..=4).contains(&request.version) {
+            net_debug!("SNTP server ignoring packet from {}", ep);
+            return Ok(false);
+        }
+
+        let (leap_indicator, (stratum, ref_identifier, ref_timestamp)) = match self.reference {
+            Some(reference) => (self.leap_indicator, reference),
+            None => (
+                LeapIndicator::AlarmCondition,
+                (Stratum::Reserved(16), [0; 4], Timestamp::default()),
+            ),
+        };
+        let mut reply = Repr {
+            leap_indicator,
+            version: request.version,
+            protocol_mode: ProtocolMode::Server,
+            stratum,
+            poll_interval: request.poll_interval,
+            precision: self.precision,
+            root_delay: 0,
+            root_dispersion: 0,
+            ref_identifier,
+            ref_timestamp,
+            orig_timestamp: request.xmit_timestamp,
+            recv_timestamp,
+            xmit_timestamp: recv_timestamp,
+        };
+        let payload = tx.send(reply.buffer_len(), ep)?;
+
+        // Then timestamp the reply as late as possible, once everything else is ready
+        let xmit_timestamp = (self.clock)();
+        if !xmit_timestamp.is_before(recv_timestamp) {
+            reply.xmit_timestamp = xmit_timestamp;
+        }
+        reply.emit(&mut Packet::new_unchecked(payload))?;
+        net_trace_bytes!("sntp: send", payload);
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::{self, Network};
+    use crate::net::time::Instant;
+    use crate::sntp::Client;
+    use core::cell::Cell;
+
+    /// Unix time of the first reading of the test clock, in nanoseconds.
+    const START_NANOS: i128 = 1_589_753_489 * 1_000_000_000;
+
+    std::thread_local! {
+        static READINGS: Cell<i128> = const { Cell::new(0) };
+        static STEP_NANOS: Cell<i128> = const { Cell::new(1_000_000) };
+    }
+
+    /// A clock advancing by `STEP_NANOS` at each reading.
+    fn clock() -> Timestamp {
+        let readings = READINGS.with(|r| r.replace(r.get() + 1));
+        let step = STEP_NANOS.with(Cell::get);
+        Timestamp::from_unix_nanos(START_NANOS + readings * step)
+    }
+
+    /// Lets a detached client send a request, returning it.
+    fn request(client: &mut Client) -> std::vec::Vec<u8> {
+        let mut tx = mock::Outbox::default();
+        client.reset(Instant::from_millis(0));
+        assert_eq!(
+            client.process_timeouts(&mut tx, Instant::from_millis(0)),
+            Ok(true)
+        );
+        tx.sent.remove(0).1
+    }
+
+    fn parse(bytes: &[u8]) -> Repr {
+        Repr::parse(&Packet::new_checked(bytes).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_turnaround() {
+        let ep = mock::remote_ep(1, 50000);
+        let mut server = Server::detached(clock);
+        let mut client = Client::detached(ep.addr, Instant::from_millis(0));
+        let mut tx = mock::Outbox::default();
+
+        // Unsynchronized servers are ignored by clients
+        let bytes = request(&mut client);
+        assert_eq!(server.process_datagram(&bytes, ep, &mut tx), Ok(true));
+        let (dst, reply) = tx.sent.remove(0);
+        assert_eq!(dst, ep);
+        assert_eq!(parse(&reply).leap_indicator, LeapIndicator::AlarmCondition);
+        assert_eq!(
+            client.process_datagram(&reply, Instant::from_millis(5)),
+            None
+        );
+
+        let reference = Timestamp::from_unix_nanos(START_NANOS - 1_000_000_000);
+        server.set_reference(Stratum::Primary, *b"GPS\0", reference);
+        assert!(server.is_synchronized());
+        let bytes = request(&mut client);
+        server.process_datagram(&bytes, ep, &mut tx).unwrap();
+        let (_, reply) = tx.sent.remove(0);
+        let repr = parse(&reply);
+        assert_eq!(repr.protocol_mode, ProtocolMode::Server);
+        assert_eq!(repr.stratum, Stratum::Primary);
+        assert_eq!(repr.ref_timestamp, reference);
+        assert_eq!(repr.orig_timestamp, parse(&bytes).xmit_timestamp);
+
+        // The clock is read upon receipt and again right before sending
+        assert!(repr.xmit_timestamp.is_after(repr.recv_timestamp));
+        assert_eq!(
+            repr.xmit_timestamp.to_unix_nanos() - repr.recv_timestamp.to_unix_nanos(),
+            1_000_000
+        );
+        assert!(client
+            .process_datagram(&reply, Instant::from_millis(5))
+            .is_some());
+
+        // ...and the reply never departs before the request arrived
+        STEP_NANOS.with(|s| s.set(-1_000_000));
+        let bytes = request(&mut client);
+        server.process_datagram(&bytes, ep, &mut tx).unwrap();
+        let repr = parse(&tx.sent.remove(0).1);
+        STEP_NANOS.with(|s| s.set(1_000_000));
+        assert_eq!(repr.xmit_timestamp, repr.recv_timestamp);
+
+        // Only client requests are answered
+        let mut reply = reply;
+        assert_eq!(server.process_datagram(&reply, ep, &mut tx), Ok(false));
+        reply.truncate(40);
+        assert_eq!(server.process_datagram(&reply, ep, &mut tx), Ok(false));
+        assert!(tx.sent.is_empty());
+    }
+
+    #[test]
+    fn test_serve() {
+        let mut net = Network::new();
+        let mut sockets = mock::socket_set(1);
+        let mut server = Server::new(
+            &mut sockets,
+            mock::udp_buffer(2, 256),
+            mock::udp_buffer(2, 256),
+            clock,
+        );
+        let now = Instant::from_millis(0);
+        assert_eq!(server.serve(&mut sockets), Ok(0));
+
+        let ep = mock::remote_ep(1, 50000);
+        let bytes = request(&mut Client::detached(ep.addr, now));
+        net.inject(ep, mock::local_ep(SNTP_PORT), &bytes);
+        net.inject(ep, mock::local_ep(SNTP_PORT), &bytes);
+        net.poll(&mut sockets, now);
+        assert_eq!(server.serve(&mut sockets), Ok(2));
+        net.poll(&mut sockets, now);
+        let sent = net.transmitted();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].src, mock::local_ep(SNTP_PORT));
+        assert_eq!(sent[0].dst, ep);
+        let repr = parse(&sent[0].payload);
+        assert!(!repr.xmit_timestamp.is_before(repr.recv_timestamp));
+
+        let mut detached = Server::detached(clock);
+        assert_eq!(detached.serve(&mut sockets), Err(Error::Illegal));
+    }
+}
@@ -0,0 +1,759 @@
+//! Trivial File Transfer Protocol client, reading files from a remote server.
+
+use super::{
+    Handle, DATA_HEADER_LEN, DEFAULT_BLOCK_SIZE, MAX_BLOCK_SIZE, MAX_RETRIES, MIN_BLOCK_SIZE,
+    RETRY_TIMEOUT,
+};
+use crate::net::{
+    self,
+    socket::{SocketHandle, SocketSet, UdpSocket, UdpSocketBuffer},
+    time::{Duration, Instant},
+    wire::{IpAddress, IpEndpoint},
+    Error,
+};
+use crate::transport::Transmit;
+use crate::wire::tftp::*;
+
+/// Maximum length of a read request, including the filename and mode.
+const MAX_REQUEST_LEN: usize = 128;
+
+/// First port from which the local port of a client is picked, unless set explicitly.
+const EPHEMERAL_PORT_BASE: u16 = 49152;
+
+/// Interval reported by `Client::next_poll()` when no transfer is in progress.
+const IDLE_POLL_INTERVAL: Duration = Duration {
+    millis: 24 * 60 * 60 * 1_000,
+};
+
+/// Longest interval between two polls of `fetch()`.
+#[cfg(feature = "std")]
+const FETCH_POLL_INTERVAL: Duration = Duration { millis: 1 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// No transfer in progress.
+    Idle,
+    /// The request was sent, and the first block or the OACK is awaited.
+    Requesting,
+    /// The transfer is underway, with the server's transfer ID known.
+    Receiving,
+}
+
+/// TFTP client, reading files from a server in octet mode.
+///
+/// Blocks are 512 bytes long, unless a smaller size is negotiated through
+/// `Client::set_options()`.
+/// A read is started by `Client::read()`, then carried out by calling `Client::poll()`
+/// along with `Interface::poll()`, until the whole file is written to the given [`Handle`].
+/// On `std`, [`fetch()`] does both in a single call.
+///
+/// As per RFC 1350, the first DATA block fixes the endpoint of the server for the rest of
+/// the transfer: it may come from any port of the server address, and packets from any
+/// other endpoint are then ignored. So does the OACK, when options are requested.
+///
+/// [`Handle`]: ../trait.Handle.html
+/// [`fetch()`]: fn.fetch.html
+pub struct Client {
+    udp_handle: SocketHandle,
+    local_port: u16,
+    state: State,
+    server: IpEndpoint,
+    block_num: u16,
+    received: usize,
+    // Options requested with each read, and the block size and file size acknowledged
+    options: Options,
+    block_size: usize,
+    transfer_size: Option<u64>,
+    attempts: u8,
+    next_send: Instant,
+    packet: [u8; MAX_REQUEST_LEN],
+    packet_len: usize,
+    remote_error: Option<ErrorCode>,
+}
+
+impl Client {
+    /// Creates a TFTP client.
+    ///
+    /// A new socket will be allocated and added to the provided `SocketSet`.
+    /// Its receive buffer should have room for at least one DATA packet of 516 bytes.
+    pub fn new<'a, 'b, 'c>(
+        sockets: &mut SocketSet<'a, 'b, 'c>,
+        rx_buffer: UdpSocketBuffer<'b, 'c>,
+        tx_buffer: UdpSocketBuffer<'b, 'c>,
+    ) -> Self {
+        let socket = UdpSocket::new(rx_buffer, tx_buffer);
+        let udp_handle = sockets.add(socket);
+
+        net_trace!("TFTP client initialised");
+
+        Client {
+            udp_handle,
+            local_port: 0,
+            state: State::Idle,
+            server: IpEndpoint::default(),
+            block_num: 0,
+            received: 0,
+            options: Options::default(),
+            block_size: DEFAULT_BLOCK_SIZE,
+            transfer_size: None,
+            attempts: 0,
+            next_send: Instant::from_millis(0),
+            packet: [0; MAX_REQUEST_LEN],
+            packet_len: 0,
+            remote_error: None,
+        }
+    }
+
+    /// Returns the handle of the UDP socket used by the client.
+    pub fn socket_handle(&self) -> SocketHandle {
+        self.udp_handle
+    }
+
+    /// Sets the local port of the client, or 0 (the default) to pick an ephemeral one.
+    ///
+    /// This must be called before the first `Client::poll()`: the socket can't be rebound
+    /// once it is open.
+    pub fn set_local_port(&mut self, port: u16) {
+        self.local_port = port;
+    }
+
+    /// Returns the local port of the client, or 0 if an ephemeral one is picked.
+    pub fn local_port(&self) -> u16 {
+        self.local_port
+    }
+
+    /// Sets the options requested along with each read (none by default).
+    ///
+    /// The block size is kept between 8 and `MAX_BLOCK_SIZE` bytes, and `offset` is ignored.
+    /// Servers not supporting options simply send the first block with the default settings,
+    /// otherwise they answer with an OACK. Until then, the request itself is retransmitted.
+    pub fn set_options(&mut self, options: Options) {
+        self.options = Options {
+            block_size: options
+                .block_size
+                .map(|size| size.max(MIN_BLOCK_SIZE as u16).min(MAX_BLOCK_SIZE as u16)),
+            transfer_size: options.transfer_size,
+            offset: None,
+        };
+    }
+
+    /// Returns the options requested along with each read.
+    pub fn options(&self) -> Options {
+        self.options
+    }
+
+    /// Returns the size of the file announced by the server through the `tsize` option,
+    /// if requested and acknowledged for the current or last transfer.
+    pub fn transfer_size(&self) -> Option<u64> {
+        self.transfer_size
+    }
+
+    /// Returns `true` if no transfer is in progress.
+    pub fn is_idle(&self) -> bool {
+        self.state == State::Idle
+    }
+
+    /// Returns the number of bytes received so far by the current or last transfer.
+    pub fn received(&self) -> usize {
+        self.received
+    }
+
+    /// Returns the code of the error that aborted the last transfer, if the server sent one.
+    pub fn remote_error(&self) -> Option<ErrorCode> {
+        self.remote_error
+    }
+
+    /// Starts reading `filename` from the TFTP server listening at `server`.
+    ///
+    /// The request is sent at the next `Client::poll()`. Returns `Err(Error::Illegal)`
+    /// if a transfer is already in progress, and `Err(Error::Exhausted)` if the filename
+    /// is too long to fit a request.
+    pub fn read(&mut self, server: IpEndpoint, filename: &str, now: Instant) -> net::Result<()> {
+        if self.state != State::Idle {
+            return Err(Error::Illegal);
+        }
+
+        let repr = Repr::ReadRequest {
+            filename,
+            mode: Mode::Octet,
+            options: self.options,
+        };
+        if repr.buffer_len() > MAX_REQUEST_LEN {
+            return Err(Error::Exhausted);
+        }
+        repr.emit(&mut Packet::new_unchecked(&mut self.packet[..]))?;
+        self.packet_len = repr.buffer_len();
+
+        net_debug!("TFTP client reading {} from {}", filename, server);
+
+        self.state = State::Requesting;
+        self.server = server;
+        self.block_num = 0;
+        self.received = 0;
+        self.block_size = DEFAULT_BLOCK_SIZE;
+        self.transfer_size = None;
+        self.attempts = 0;
+        self.next_send = now;
+        self.remote_error = None;
+        Ok(())
+    }
+
+    /// Abandons the transfer in progress, if any, without notifying the server.
+    pub fn cancel(&mut self) {
+        self.state = State::Idle;
+    }
+
+    /// Returns the duration until the next poll activity.
+    #[must_use]
+    pub fn next_poll(&self, now: Instant) -> Duration {
+        match self.state {
+            State::Idle => IDLE_POLL_INTERVAL,
+            _ if self.next_send > now => self.next_send - now,
+            _ => Duration::from_millis(0),
+        }
+    }
+
+    /// Processes incoming packets, writing the received data to `handle`, and sends
+    /// the request or retransmits the last acknowledgment when timeouts expire.
+    ///
+    /// Returns the total number of bytes received once the last block is written,
+    /// and `None` while the transfer is in progress or when there is none.
+    /// The transfer is aborted with:
+    ///
+    /// * `Err(Error::Dropped)` if the server sent an error, see `Client::remote_error()`;
+    /// * `Err(Error::Exhausted)` if `handle` fails to write a block, in which case
+    ///   a `DiskFull` error is sent to the server;
+    /// * `Err(Error::Unrecognized)` if the server acknowledges options that were not requested,
+    ///   in which case an `OptionNegotiation` error is sent to the server;
+    /// * `Err(Error::Unaddressable)` if the server stops answering.
+    pub fn poll<H: Handle>(
+        &mut self,
+        sockets: &mut SocketSet,
+        handle: &mut H,
+        now: Instant,
+    ) -> net::Result<Option<usize>> {
+        let mut socket = sockets.get::<UdpSocket>(self.udp_handle);
+
+        // Bind the socket if necessary, deriving an ephemeral port from the clock
+        if !socket.is_open() {
+            let port = match self.local_port {
+                0 => EPHEMERAL_PORT_BASE + (now.total_millis() as u64 % 16384) as u16,
+                port => port,
+            };
+            socket.bind(IpEndpoint {
+                addr: IpAddress::Unspecified,
+                port,
+            })?;
+        }
+
+        let mut buffer = [0; DATA_HEADER_LEN + MAX_BLOCK_SIZE];
+        loop {
+            let (len, ep) = match socket.recv() {
+                Ok((payload, _)) if payload.len() > buffer.len() => continue,
+                Ok((payload, ep)) => {
+                    buffer[..payload.len()].copy_from_slice(payload);
+                    (payload.len(), ep)
+                }
+                Err(Error::Exhausted) => break,
+                Err(e) => return Err(e),
+            };
+            if let Some(total) = self.process(&mut *socket, handle, &buffer[..len], ep, now)? {
+                return Ok(Some(total));
+            }
+        }
+
+        self.process_timeouts(&mut *socket, now)?;
+        Ok(None)
+    }
+
+    /// Processes a datagram received from `ep`.
+    fn process<T, H>(
+        &mut self,
+        tx: &mut T,
+        handle: &mut H,
+        data: &[u8],
+        ep: IpEndpoint,
+        now: Instant,
+    ) -> net::Result<Option<usize>>
+    where
+        T: Transmit,
+        H: Handle,
+    {
+        let from_server = match self.state {
+            State::Idle => false,
+            State::Requesting => ep.addr == self.server.addr,
+            State::Receiving => ep == self.server,
+        };
+        if !from_server {
+            net_debug!("TFTP client ignoring packet from {}", ep);
+            return Ok(None);
+        }
+
+        let packet = match Packet::new_checked(data) {
+            Ok(packet) => packet,
+            Err(_) => return Ok(None),
+        };
+        match Repr::parse(&packet) {
+            Ok(Repr::Data { block_num, data }) if block_num == self.block_num.wrapping_add(1) => {
+                self.server = ep;
+                self.state = State::Receiving;
+
+                if handle.write_block(block_num, data).is_err() {
+                    self.state = State::Idle;
+                    send_repr(
+                        tx,
+                        ep,
+                        Repr::Error {
+                            code: ErrorCode::DiskFull,
+                            msg: "Write failed",
+                        },
+                    )?;
+                    return Err(Error::Exhausted);
+                }
+                self.block_num = block_num;
+                self.received += data.len();
+
+                self.acknowledge(tx, block_num, now)?;
+
+                if data.len() < self.block_size {
+                    net_debug!("TFTP client received {} bytes", self.received);
+                    self.state = State::Idle;
+                    return Ok(Some(self.received));
+                }
+                Ok(None)
+            }
+            Ok(Repr::OptionAck { options }) if self.state == State::Requesting => {
+                // Only the options we requested may be acknowledged, and only downwards
+                let block_size = match (options.block_size, self.options.block_size) {
+                    (None, _) => Some(DEFAULT_BLOCK_SIZE),
+                    (Some(acked), Some(requested))
+                        if acked <= requested && usize::from(acked) >= MIN_BLOCK_SIZE =>
+                    {
+                        Some(usize::from(acked))
+                    }
+                    _ => None,
+                };
+                let transfer_size_ok =
+                    options.transfer_size.is_none() || self.options.transfer_size.is_some();
+                let block_size = match block_size {
+                    Some(size) if transfer_size_ok && options.offset.is_none() => size,
+                    _ => {
+                        self.state = State::Idle;
+                        send_repr(
+                            tx,
+                            ep,
+                            Repr::Error {
+                                code: ErrorCode::OptionNegotiation,
+                                msg: "Unexpected options",
+                            },
+                        )?;
+                        return Err(Error::Unrecognized);
+                    }
+                };
+
+                self.server = ep;
+                self.state = State::Receiving;
+                self.block_size = block_size;
+                self.transfer_size = options.transfer_size;
+
+                // From now on, ACK #0 is retransmitted instead of the request
+                self.acknowledge(tx, 0, now)?;
+                Ok(None)
+            }
+            Ok(Repr::Data { block_num, .. })
+                if self.state == State::Receiving && block_num == self.block_num =>
+            {
+                // Our acknowledgment was lost, send it again
+                self.next_send = now;
+                self.process_timeouts(tx, now)?;
+                Ok(None)
+            }
+            Ok(Repr::OptionAck { .. }) if self.state == State::Receiving && self.block_num == 0 => {
+                // Our acknowledgment of the OACK was lost, send it again
+                self.next_send = now;
+                self.process_timeouts(tx, now)?;
+                Ok(None)
+            }
+            Ok(Repr::Error { code, msg }) => {
+                net_debug!("TFTP client aborted by {}: {}", ep, msg);
+                self.state = State::Idle;
+                self.remote_error = Some(code);
+                Err(Error::Dropped)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Acknowledges a block, keeping the ACK around in case it is lost.
+    fn acknowledge<T: Transmit>(
+        &mut self,
+        tx: &mut T,
+        block_num: u16,
+        now: Instant,
+    ) -> net::Result<()> {
+        let ack = Repr::Ack { block_num };
+        ack.emit(&mut Packet::new_unchecked(&mut self.packet[..]))?;
+        self.packet_len = ack.buffer_len();
+        self.attempts = 0;
+        self.next_send = now;
+        self.process_timeouts(tx, now)
+    }
+
+    /// Sends the last packet again if its timeout has expired.
+    fn process_timeouts<T: Transmit>(&mut self, tx: &mut T, now: Instant) -> net::Result<()> {
+        if self.state == State::Idle || now < self.next_send {
+            return Ok(());
+        }
+        if self.attempts > MAX_RETRIES {
+            net_debug!("TFTP client timed out waiting for {}", self.server);
+            self.state = State::Idle;
+            return Err(Error::Unaddressable);
+        }
+
+        tx.send(self.packet_len, self.server)?
+            .copy_from_slice(&self.packet[..self.packet_len]);
+        self.attempts += 1;
+        self.next_send = now + RETRY_TIMEOUT;
+        Ok(())
+    }
+}
+
+/// Emits `repr` into a datagram for `ep`.
+fn send_repr<T: Transmit>(tx: &mut T, ep: IpEndpoint, repr: Repr) -> net::Result<()> {
+    let payload = tx.send(repr.buffer_len(), ep)?;
+    repr.emit(&mut Packet::new_unchecked(payload))
+}
+
+/// A `Handle` writing the received data into a fixed buffer.
+#[cfg(feature = "std")]
+struct Buffer<'a> {
+    data: &'a mut [u8],
+    len: usize,
+}
+
+#[cfg(feature = "std")]
+impl<'a> Handle for Buffer<'a> {
+    fn read(&mut self, _: &mut [u8]) -> Result<usize, ()> {
+        Err(())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        let end = self.len + buf.len();
+        if end > self.data.len() {
+            return Err(());
+        }
+        self.data[self.len..end].copy_from_slice(buf);
+        self.len = end;
+        Ok(buf.len())
+    }
+}
+
+/// Reads the whole of `filename` from `server` into `out`, returning the number of bytes read.
+///
+/// This runs a read transfer to completion with `client`, calling `poll_iface` to move
+/// the datagrams between the sockets and the network, typically through `Interface::poll()`.
+/// It blocks the calling thread meanwhile: use `Client::read()` and `Client::poll()` to
+/// carry out transfers from a main loop instead.
+///
+/// Fails with the same errors as `Client::read()` and `Client::poll()`. In particular,
+/// if `out` is too small to hold the file, the transfer is aborted with `Err(Error::Exhausted)`.
+#[cfg(feature = "std")]
+pub fn fetch<F>(
+    sockets: &mut SocketSet,
+    client: &mut Client,
+    server: IpEndpoint,
+    filename: &str,
+    out: &mut [u8],
+    mut poll_iface: F,
+) -> net::Result<usize>
+where
+    F: FnMut(&mut SocketSet, Instant),
+{
+    let mut buffer = Buffer { data: out, len: 0 };
+    client.read(server, filename, Instant::now())?;
+
+    loop {
+        let now = Instant::now();
+        let result = client.poll(sockets, &mut buffer, now);
+        // Flush the final acknowledgment, or error, before returning
+        poll_iface(sockets, now);
+        if let Some(total) = result? {
+            return Ok(total);
+        }
+
+        let timeout = client.next_poll(now).min(FETCH_POLL_INTERVAL);
+        std::thread::sleep(timeout.into());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::{self, Network};
+    use std::vec::Vec;
+
+    const SERVER: u8 = 1;
+
+    /// A handle collecting the received data.
+    #[derive(Default)]
+    struct Sink(Vec<u8>);
+
+    impl Handle for Sink {
+        fn read(&mut self, _: &mut [u8]) -> Result<usize, ()> {
+            Err(())
+        }
+
+        fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+            self.0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    /// Replies to the requests and acknowledgments sent by the client with the blocks of `file`,
+    /// returning them.
+    #[cfg(feature = "std")]
+    fn serve(
+        net: &mut Network,
+        sockets: &mut SocketSet,
+        now: Instant,
+        file: &[u8],
+    ) -> Vec<mock::Datagram> {
+        net.poll(sockets, now);
+        let sent = net.transmitted();
+        for datagram in sent.iter() {
+            let packet = Packet::new_checked(&datagram.payload[..]).unwrap();
+            let block_num = match Repr::parse(&packet).unwrap() {
+                Repr::ReadRequest { .. } => 1,
+                Repr::Ack { block_num } => block_num + 1,
+                Repr::Error { .. } => continue,
+                other => panic!("unexpected packet {:?}", other),
+            };
+            let start = (usize::from(block_num) - 1) * DEFAULT_BLOCK_SIZE;
+            if start > file.len() {
+                continue;
+            }
+            let end = file.len().min(start + DEFAULT_BLOCK_SIZE);
+            let data = Repr::Data {
+                block_num,
+                data: &file[start..end],
+            };
+            let mut bytes = std::vec![0; data.buffer_len()];
+            data.emit(&mut Packet::new_unchecked(&mut bytes)).unwrap();
+            net.inject(mock::remote_ep(SERVER, 50000), datagram.src, &bytes);
+        }
+        net.poll(sockets, now);
+        sent
+    }
+
+    fn client() -> (SocketSet<'static, 'static, 'static>, Client) {
+        let mut sockets = mock::socket_set(1);
+        let client = Client::new(
+            &mut sockets,
+            mock::udp_buffer(2, 1100),
+            mock::udp_buffer(2, 1100),
+        );
+        (sockets, client)
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_fetch() {
+        let file: Vec<u8> = (0..1300).map(|i| (i % 251) as u8).collect();
+        let (mut sockets, mut client) = client();
+        let mut net = Network::new();
+        let mut out = [0; 2048];
+        let mut sent = Vec::new();
+
+        let len = fetch(
+            &mut sockets,
+            &mut client,
+            mock::remote_ep(SERVER, 69),
+            "file",
+            &mut out,
+            |sockets, now| sent.extend(serve(&mut net, sockets, now, &file)),
+        )
+        .unwrap();
+        assert_eq!(&out[..len], &file[..]);
+        assert!(client.is_idle());
+
+        // The request goes to the well-known port, and the blocks are acknowledged to
+        // the transfer ID of the server, the last one included
+        assert_eq!(sent.len(), 4);
+        assert_eq!(sent[0].dst, mock::remote_ep(SERVER, 69));
+        assert_eq!(sent[3].dst, mock::remote_ep(SERVER, 50000));
+        assert_eq!(&sent[3].payload[..], &[0, 4, 0, 3]);
+
+        // A buffer too small for the file aborts the transfer
+        let mut out = [0; 1024];
+        let res = fetch(
+            &mut sockets,
+            &mut client,
+            mock::remote_ep(SERVER, 69),
+            "file",
+            &mut out,
+            |sockets, now| {
+                serve(&mut net, sockets, now, &file);
+            },
+        );
+        assert_eq!(res, Err(Error::Exhausted));
+        assert!(client.is_idle());
+    }
+
+    #[test]
+    fn test_lost_oack() {
+        let (mut sockets, mut client) = client();
+        let mut net = Network::new();
+        let mut out = Sink::default();
+        let server = mock::remote_ep(SERVER, 69);
+        let tid = mock::remote_ep(SERVER, 50000);
+        let mut now = Instant::from_millis(0);
+        let options = Options {
+            block_size: Some(256),
+            transfer_size: Some(0),
+            offset: None,
+        };
+        client.set_options(options);
+        assert_eq!(client.options(), options);
+
+        let mut poll = |client: &mut Client,
+                        sockets: &mut SocketSet,
+                        out: &mut Sink,
+                        inject: Option<Repr>,
+                        now| {
+            if let Some(repr) = inject {
+                let handle = client.socket_handle();
+                let port = sockets.get::<UdpSocket>(handle).endpoint().port;
+                let mut bytes = std::vec![0; repr.buffer_len()];
+                repr.emit(&mut Packet::new_unchecked(&mut bytes)).unwrap();
+                net.inject(tid, mock::local_ep(port), &bytes);
+                net.poll(sockets, now);
+            }
+            let res = client.poll(sockets, out, now);
+            net.poll(sockets, now);
+            (res, net.transmitted())
+        };
+        let parse = |sent: &mock::Datagram| {
+            let packet = Packet::new_checked(&sent.payload[..]).unwrap();
+            let repr = Repr::parse(&packet).unwrap();
+            match repr {
+                Repr::ReadRequest { options, .. } => (OpCode::Read, options.block_size),
+                Repr::Ack { block_num } => (OpCode::Ack, Some(block_num)),
+                other => panic!("unexpected packet {:?}", other),
+            }
+        };
+
+        client.read(server, "file", now).unwrap();
+        let (_, sent) = poll(&mut client, &mut sockets, &mut out, None, now);
+        assert_eq!(parse(&sent[0]), (OpCode::Read, Some(256)));
+
+        // The OACK is lost: the request is sent again, not an ACK
+        now += RETRY_TIMEOUT;
+        let (_, sent) = poll(&mut client, &mut sockets, &mut out, None, now);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dst, server);
+        assert_eq!(parse(&sent[0]), (OpCode::Read, Some(256)));
+
+        let oack = Repr::OptionAck {
+            options: Options {
+                block_size: Some(256),
+                transfer_size: Some(300),
+                offset: None,
+            },
+        };
+        let (_, sent) = poll(&mut client, &mut sockets, &mut out, Some(oack), now);
+        assert_eq!(sent[0].dst, tid);
+        assert_eq!(parse(&sent[0]), (OpCode::Ack, Some(0)));
+        assert_eq!(client.transfer_size(), Some(300));
+
+        // Past the OACK, ACK #0 is retransmitted instead
+        now += RETRY_TIMEOUT;
+        let (_, sent) = poll(&mut client, &mut sockets, &mut out, None, now);
+        assert_eq!(sent[0].dst, tid);
+        assert_eq!(parse(&sent[0]), (OpCode::Ack, Some(0)));
+        let (_, sent) = poll(&mut client, &mut sockets, &mut out, Some(oack), now);
+        assert_eq!(parse(&sent[0]), (OpCode::Ack, Some(0)));
+
+        // Blocks shorter than the negotiated size end the transfer
+        let file = [0x5a; 300];
+        let data = Repr::Data {
+            block_num: 1,
+            data: &file[..256],
+        };
+        let (res, _) = poll(&mut client, &mut sockets, &mut out, Some(data), now);
+        assert_eq!(res, Ok(None));
+        let data = Repr::Data {
+            block_num: 2,
+            data: &file[256..],
+        };
+        let (res, sent) = poll(&mut client, &mut sockets, &mut out, Some(data), now);
+        assert_eq!(res, Ok(Some(300)));
+        assert_eq!(parse(&sent[0]), (OpCode::Ack, Some(2)));
+        assert_eq!(&out.0[..], &file[..]);
+
+        // Options that weren't requested are refused
+        client.set_options(Options {
+            block_size: Some(256),
+            ..Options::default()
+        });
+        client.read(server, "file", now).unwrap();
+        let (res, _) = poll(&mut client, &mut sockets, &mut out, None, now);
+        assert_eq!(res, Ok(None));
+        let (res, sent) = poll(&mut client, &mut sockets, &mut out, Some(oack), now);
+        assert_eq!(res, Err(Error::Unrecognized));
+        assert_eq!(&sent[0].payload[..4], &[0, 5, 0, 8]);
+        assert!(client.is_idle());
+    }
+
+    #[test]
+    fn test_read_retries_and_errors() {
+        let (mut sockets, mut client) = client();
+        let mut net = Network::new();
+        let mut out = Sink::default();
+        let server = mock::remote_ep(SERVER, 69);
+        let mut now = Instant::from_millis(0);
+
+        client.read(server, "file", now).unwrap();
+        assert_eq!(client.read(server, "file", now), Err(Error::Illegal));
+
+        // The request is retransmitted until answered
+        for _ in 0..2 {
+            assert_eq!(client.poll(&mut sockets, &mut out, now), Ok(None));
+            net.poll(&mut sockets, now);
+            let sent = net.transmitted();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(sent[0].dst, server);
+            assert_eq!(&sent[0].payload[..2], &[0, 1]);
+            now += RETRY_TIMEOUT;
+        }
+
+        // Errors from the server abort the transfer
+        let local_ep = mock::local_ep(
+            sockets
+                .get::<UdpSocket>(client.socket_handle())
+                .endpoint()
+                .port,
+        );
+        net.inject(server, local_ep, b"\x00\x05\x00\x01Not found\x00");
+        net.poll(&mut sockets, now);
+        assert_eq!(
+            client.poll(&mut sockets, &mut out, now),
+            Err(Error::Dropped)
+        );
+        assert_eq!(client.remote_error(), Some(ErrorCode::FileNotFound));
+        assert!(client.is_idle());
+
+        // Until the server stops answering altogether
+        client.read(server, "file", now).unwrap();
+        for _ in 0..=MAX_RETRIES {
+            assert_eq!(client.poll(&mut sockets, &mut out, now), Ok(None));
+            net.poll(&mut sockets, now);
+            now += RETRY_TIMEOUT;
+        }
+        assert_eq!(net.transmitted().len(), usize::from(MAX_RETRIES) + 1);
+        assert_eq!(
+            client.poll(&mut sockets, &mut out, now),
+            Err(Error::Unaddressable)
+        );
+        assert!(client.is_idle());
+    }
+}
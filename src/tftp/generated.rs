@@ -0,0 +1,115 @@
+//! Serving contents computed on the fly, such as an index of the available files.
+
+use super::{Context, Handle};
+
+/// A read-only [`Handle`] over a buffer held in memory.
+///
+/// This lets a [`Context`] serve contents generated upon request, e.g. a status report
+/// or an index of its files built with [`write_index()`], without touching real storage.
+/// The buffer is owned by the handle: it may be a fixed-size array, of which only the first
+/// `len` bytes are served, a `&'static [u8]` or, on `std`, a `Vec<u8>`. Writes are refused.
+///
+/// [`Handle`]: trait.Handle.html
+/// [`Context`]: trait.Context.html
+/// [`write_index()`]: fn.write_index.html
+pub struct GeneratedHandle<B> {
+    content: B,
+    len: usize,
+    pos: usize,
+}
+
+impl<B: AsRef<[u8]>> GeneratedHandle<B> {
+    /// Creates a handle serving the whole of `content`.
+    pub fn new(content: B) -> Self {
+        let len = content.as_ref().len();
+        GeneratedHandle::with_len(content, len)
+    }
+
+    /// Creates a handle serving the first `len` bytes of `content`, or all of it if shorter.
+    pub fn with_len(content: B, len: usize) -> Self {
+        let len = len.min(content.as_ref().len());
+        GeneratedHandle {
+            content,
+            len,
+            pos: 0,
+        }
+    }
+
+    /// Consumes the handle, returning the buffer it was serving, e.g. to reuse it.
+    pub fn into_inner(self) -> B {
+        self.content
+    }
+}
+
+impl<B: AsRef<[u8]>> Handle for GeneratedHandle<B> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        let remaining = &self.content.as_ref()[self.pos..self.len];
+        let len = buf.len().min(remaining.len());
+        buf[..len].copy_from_slice(&remaining[..len]);
+        self.pos += len;
+        Ok(len)
+    }
+
+    fn write(&mut self, _: &[u8]) -> Result<usize, ()> {
+        Err(())
+    }
+
+    fn size(&self) -> Option<u64> {
+        Some(self.len as u64)
+    }
+}
+
+/// Writes the files listed by `context` into `buf`, one per line, returning the length
+/// of the index.
+///
+/// Each filename is terminated by a line feed. Returns `None` if `buf` is too small to hold
+/// them all.
+///
+/// ```rust
+/// # use smolapps::tftp::{write_index, Context, GeneratedHandle};
+/// struct Device;
+///
+/// impl Context for Device {
+///     type Handle = GeneratedHandle<[u8; 64]>;
+///
+///     fn list(&self, entry: &mut dyn FnMut(&str)) {
+///         entry("/index");
+///         entry("version");
+///     }
+///
+///     fn open(&mut self, filename: &str, write_mode: bool) -> Result<Self::Handle, ()> {
+///         let mut buf = [0; 64];
+///         let len = match (filename, write_mode) {
+///             ("/index", false) => write_index(&*self, &mut buf).ok_or(())?,
+///             ("version", false) => {
+///                 buf[..6].copy_from_slice(b"1.2.3\n");
+///                 6
+///             }
+///             _ => return Err(()),
+///         };
+///         Ok(GeneratedHandle::with_len(buf, len))
+///     }
+///
+///     fn close(&mut self, _: Self::Handle) {}
+/// }
+/// ```
+pub fn write_index<C: Context + ?Sized>(context: &C, buf: &mut [u8]) -> Option<usize> {
+    let mut len = 0;
+    let mut overflow = false;
+    context.list(&mut |filename| {
+        let end = len + filename.len() + 1;
+        if overflow || end > buf.len() {
+            overflow = true;
+            return;
+        }
+        buf[len..end - 1].copy_from_slice(filename.as_bytes());
+        buf[end - 1] = b'\n';
+        len = end;
+    });
+
+    if overflow {
+        None
+    } else {
+        Some(len)
+    }
+}
@@ -1,4 +1,4 @@
-//! Trivial File Transfer Protocol server implementation.
+//! Trivial File Transfer Protocol server and client implementation.
 
 use crate::net::{
     self,
@@ -7,9 +7,23 @@ use crate::net::{
     wire::{IpAddress, IpEndpoint},
     Error,
 };
+use crate::transport::{Activity, Counted, EndpointFilter, Transmit};
 use crate::wire::tftp::*;
 use managed::ManagedSlice;
 
+pub mod client;
+mod generated;
+mod routing;
+mod transform;
+
+pub use crate::wire::tftp::{ErrorCode, Mode, OpCode, ParseMode};
+pub use generated::{write_index, GeneratedHandle};
+pub use routing::{Routed, RoutingContext};
+pub use transform::{Transform, Transformed};
+
+/// A source of the current time, read by `Server::serve_now()`.
+pub type Clock = fn() -> Instant;
+
 /// Maximum number of retransmissions attempted by the server before giving up.
 const MAX_RETRIES: u8 = 10;
 
@@ -19,6 +33,150 @@ const RETRY_TIMEOUT: Duration = Duration { millis: 200 };
 /// IANA port for TFTP servers.
 const TFTP_PORT: u16 = 69;
 
+/// Port on which servers receive requests, unless changed through `Server::set_port()`.
+///
+/// This is the IANA port 69, unless the `SMOLAPPS_TFTP_PORT` environment variable is set
+/// to another port number at build time (e.g. 6969, for servers that can't bind privileged
+/// ports). Invalid values fail the build.
+pub const DEFAULT_PORT: u16 = match option_env!("SMOLAPPS_TFTP_PORT") {
+    Some(port) => parse_port(port),
+    None => TFTP_PORT,
+};
+
+/// Parses a decimal port number, failing the build if it is invalid.
+const fn parse_port(port: &str) -> u16 {
+    let bytes = port.as_bytes();
+    let mut value: u32 = 0;
+    let mut valid = !bytes.is_empty();
+    let mut i = 0;
+    while i < bytes.len() {
+        let digit = bytes[i].wrapping_sub(b'0') as u32;
+        value = value * 10 + digit;
+        valid &= digit < 10 && value <= u16::MAX as u32;
+        i += 1;
+    }
+    // Constants can't panic, but indexing out of bounds still stops the build
+    [value as u16][!valid as usize]
+}
+
+/// Maximum number of sockets in the transfer ID pool of a server.
+const MAX_TID_SOCKETS: usize = 8;
+
+/// Port of the first socket in the transfer ID pool, the others following in sequence.
+const TID_BASE_PORT: u16 = 49152;
+
+/// Size of a data block, in bytes, when no `blksize` option is negotiated (RFC 1350).
+pub const DEFAULT_BLOCK_SIZE: usize = 512;
+
+/// Largest block size that can be negotiated through the `blksize` option, in bytes.
+///
+/// Requested sizes are only negotiated downwards for now, so this is the default block size.
+/// It also sizes the block buffer of each transfer.
+pub const MAX_BLOCK_SIZE: usize = DEFAULT_BLOCK_SIZE;
+
+/// Maximum number of datagrams processed by each call to `Server::serve()`, by default.
+const DEFAULT_SERVE_BUDGET: usize = 8;
+
+/// Minimum block size that can be negotiated, as per RFC 2348.
+const MIN_BLOCK_SIZE: usize = 8;
+
+/// Interval reported by `Server::next_poll()` when there are no active transfers.
+const IDLE_POLL_INTERVAL: Duration = Duration {
+    millis: 24 * 60 * 60 * 1_000,
+};
+
+/// Start of the messages of the errors sent to busy clients, see `Server::set_busy_retry_after()`.
+pub const BUSY_MESSAGE_PREFIX: &str = "busy; retry-after=";
+
+/// Maximum length of a busy message: the prefix followed by a 64-bit number of seconds.
+const BUSY_MESSAGE_LEN: usize = BUSY_MESSAGE_PREFIX.len() + 20;
+
+/// Number of completed read transfers whose final ACK is still absorbed, see `Server::dally()`.
+const DALLY_SLOTS: usize = 4;
+
+/// How long duplicates of the final ACK of a read transfer are absorbed after it completes,
+/// covering all the retransmissions of the final block.
+const DALLY_DURATION: Duration = Duration {
+    millis: RETRY_TIMEOUT.millis * MAX_RETRIES as u64,
+};
+
+/// Length of the header of a DATA packet.
+const DATA_HEADER_LEN: usize = 4;
+
+/// Maximum length of a packet sent in reply to a received one.
+const MAX_REPLY_LEN: usize = DATA_HEADER_LEN + MAX_BLOCK_SIZE;
+
+/// Extracts the opcode, filename and mode of a read or write request, without consuming it.
+///
+/// This lets custom loops peek at a datagram received on the server port, e.g. to route it
+/// to a server depending on the filename, before handing it to `Server::process_datagram()`.
+/// Returns `None` for any other packet, including truncated or otherwise malformed requests.
+///
+/// ```rust
+/// use smolapps::tftp::{self, Mode, OpCode};
+///
+/// let rrq = b"\x00\x01fw/image.bin\x00octet\x00";
+/// assert_eq!(
+///     tftp::peek_request(rrq),
+///     Some((OpCode::Read, "fw/image.bin", Mode::Octet))
+/// );
+/// ```
+pub fn peek_request(data: &[u8]) -> Option<(OpCode, &str, Mode)> {
+    let packet = Packet::new_checked(data).ok()?;
+    match packet.opcode() {
+        opcode @ OpCode::Read | opcode @ OpCode::Write => {
+            // The filename follows the opcode, and is borrowed from `data` rather than
+            // from the packet wrapper
+            let len = packet.filename().len();
+            let filename = core::str::from_utf8(&data[2..2 + len]).ok()?;
+            Some((opcode, filename, packet.mode()))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the payload storage, in bytes, needed by the receive buffer of each server socket
+/// to carry out transfers with blocks of up to `max_blksize` bytes.
+///
+/// This leaves room for two DATA packets, so that a duplicate or an early packet never
+/// causes the next one to be dropped, including when the ring buffer wraps around.
+///
+/// ```rust
+/// use smolapps::tftp;
+///
+/// let rx_storage = [0u8; tftp::required_rx_size(tftp::MAX_BLOCK_SIZE)];
+/// assert_eq!(rx_storage.len(), 1032);
+/// ```
+pub const fn required_rx_size(max_blksize: usize) -> usize {
+    required_rx_packets() * (DATA_HEADER_LEN + max_blksize)
+}
+
+/// Returns the number of packet metadata entries needed by the receive buffer of each
+/// server socket, matching `required_rx_size()`.
+pub const fn required_rx_packets() -> usize {
+    2
+}
+
+/// Returns the payload storage, in bytes, needed by the transmit buffer of each server socket
+/// to send up to `windowsize` blocks of up to `max_blksize` bytes at once.
+///
+/// One more packet is accounted for, so that an ERROR or OACK packet can always be queued
+/// alongside the data. The server currently sends a single block at a time, i.e.
+/// a `windowsize` of 1; a `windowsize` of 0 is treated as 1 as well.
+pub const fn required_tx_size(max_blksize: usize, windowsize: usize) -> usize {
+    required_tx_packets(windowsize) * (DATA_HEADER_LEN + max_blksize)
+}
+
+/// Returns the number of packet metadata entries needed by the transmit buffer of each
+/// server socket, matching `required_tx_size()`.
+pub const fn required_tx_packets(windowsize: usize) -> usize {
+    if windowsize > 1 {
+        windowsize + 1
+    } else {
+        2
+    }
+}
+
 /// The context over which the [`Server`] will operate.
 ///
 /// The context allows the [`Server`] to open and close [`Handle`]s to files.
@@ -32,6 +190,58 @@ pub trait Context {
     /// The `Handle` type used by this `Context`.
     type Handle: Handle;
 
+    /// Checks whether a request for `filename` should be served, before any resource
+    /// is committed to it.
+    ///
+    /// This is called before a transfer slot is allocated and before [`open()`], so it
+    /// must not have side effects. Returning an error rejects the request, and the
+    /// [`ErrorCode`] is relayed to the client. The default implementation accepts everything.
+    ///
+    /// [`open()`]: #tymethod.open
+    /// [`ErrorCode`]: enum.ErrorCode.html
+    fn accept(&self, filename: &str, write_mode: bool) -> Result<(), ErrorCode> {
+        let _ = (filename, write_mode);
+        Ok(())
+    }
+
+    /// Returns the priority of a request for `filename` from `ep`.
+    ///
+    /// When all the transfer slots are taken, a request that could otherwise be served,
+    /// i.e. whose file could be opened, preempts the active transfer of lowest priority,
+    /// provided it is strictly lower than its own: the preempted client
+    /// is sent an error, and its transfer is closed as if it had been aborted. Like [`accept()`],
+    /// this must not have side effects. The default implementation gives all requests
+    /// the same priority, so that none is ever preempted.
+    ///
+    /// [`accept()`]: #method.accept
+    fn priority(&self, ep: IpEndpoint, filename: &str) -> u8 {
+        let _ = (ep, filename);
+        0
+    }
+
+    /// Returns whether `filename` already exists.
+    ///
+    /// This is only called for write requests when overwriting is disabled through
+    /// `Server::set_overwrite()`, in which case existing files are answered with
+    /// a `FileExists` error instead of being opened. The default implementation returns `false`.
+    fn exists(&self, filename: &str) -> bool {
+        let _ = filename;
+        false
+    }
+
+    /// Calls `entry` with the name of each file available through this context.
+    ///
+    /// TFTP has no way of listing files, and the server never calls this method itself.
+    /// It lets self-describing devices serve a synthetic index, e.g. by answering requests
+    /// for `/index` with a [`GeneratedHandle`] filled by [`write_index()`]. The default
+    /// implementation lists nothing.
+    ///
+    /// [`GeneratedHandle`]: struct.GeneratedHandle.html
+    /// [`write_index()`]: fn.write_index.html
+    fn list(&self, entry: &mut dyn FnMut(&str)) {
+        let _ = entry;
+    }
+
     /// Attempts to open a file in read-only mode if `write_mode` is `false`,
     /// otherwise in read-write mode.
     ///
@@ -39,8 +249,48 @@ pub trait Context {
     /// are applied besides stripping the NULL terminator.
     fn open(&mut self, filename: &str, write_mode: bool) -> Result<Self::Handle, ()>;
 
+    /// Attempts to open a file in read-write mode, positioned `offset` bytes past its beginning.
+    ///
+    /// This is called instead of [`open()`] when a client asks to resume an interrupted write.
+    /// If an error is returned, the server falls back to [`open()`] and lets the client know
+    /// that the write must restart from the beginning.
+    ///
+    /// The default implementation doesn't support resuming writes.
+    ///
+    /// [`open()`]: #tymethod.open
+    fn open_append(&mut self, filename: &str, offset: u64) -> Result<Self::Handle, ()> {
+        let _ = (filename, offset);
+        Err(())
+    }
+
     /// Closes the file handle, flushing all pending changes to disk if necessary.
     fn close(&mut self, handle: Self::Handle);
+
+    /// Finalizes a write transfer that completed successfully.
+    ///
+    /// Together with [`discard()`], this allows for crash-safe uploads: [`open()`] can return
+    /// a handle to a temporary file, which is atomically renamed here to its final name.
+    /// Handles of read transfers are always released through [`close()`].
+    ///
+    /// The default implementation calls [`close()`].
+    ///
+    /// [`discard()`]: #method.discard
+    /// [`open()`]: #tymethod.open
+    /// [`close()`]: #tymethod.close
+    fn commit(&mut self, handle: Self::Handle) {
+        self.close(handle);
+    }
+
+    /// Disposes of a write transfer that was aborted, e.g. because of an error or a timeout.
+    ///
+    /// See [`commit()`]. The default implementation calls [`close()`], leaving
+    /// the partially written file behind.
+    ///
+    /// [`commit()`]: #method.commit
+    /// [`close()`]: #tymethod.close
+    fn discard(&mut self, handle: Self::Handle) {
+        self.close(handle);
+    }
 }
 
 /// An open file handle returned by a [`Context::open()`] operation.
@@ -49,19 +299,122 @@ pub trait Context {
 pub trait Handle {
     /// Pulls some bytes from this handle into the specified buffer, returning how many bytes were read.
     ///
-    /// `buf` is guaranteed to be exactly 512 bytes long, the maximum packet size allowed by the protocol.
+    /// `buf` is guaranteed to be exactly as long as the block size of the transfer,
+    /// which is `DEFAULT_BLOCK_SIZE` (512 bytes) unless another size has been negotiated.
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()>;
 
     /// Writes a buffer into this handle's buffer, returning how many bytes were written.
     ///
-    /// `buf` can be anywhere from 0 to 512 bytes long.
+    /// `buf` can be anywhere from 0 to the block size of the transfer (`DEFAULT_BLOCK_SIZE`
+    /// by default) bytes long.
     fn write(&mut self, buf: &[u8]) -> Result<usize, ()>;
+
+    /// Writes the data block numbered `block_num` into this handle, returning how many bytes
+    /// were written.
+    ///
+    /// Blocks are delivered exactly once and in order, starting from 1: retransmitted
+    /// blocks are filtered out by the server. Note that block numbers wrap around after 65535.
+    /// This is useful for sinks that need to track their position across block boundaries,
+    /// such as flash backends erasing whole pages before writing them.
+    ///
+    /// The default implementation forwards `buf` to [`write()`].
+    ///
+    /// [`write()`]: #tymethod.write
+    fn write_block(&mut self, block_num: u16, buf: &[u8]) -> Result<usize, ()> {
+        let _ = block_num;
+        self.write(buf)
+    }
+
+    /// Returns the total size of the file in bytes, if known.
+    ///
+    /// This is used to answer the `tsize` option (RFC 2349) of read requests, which lets
+    /// clients check whether a file exists and how big it is before downloading it:
+    ///
+    /// 1. the client sends a RRQ with `tsize=0`;
+    /// 2. the server replies with an OACK carrying the actual size;
+    /// 3. the client either acknowledges the OACK with ACK #0 to start the transfer,
+    ///    or sends an ERROR (usually `OptionNegotiation`, as per RFC 2347) to end it without
+    ///    any data being sent.
+    ///
+    /// The default implementation returns `None`, in which case `tsize` is not acknowledged.
+    fn size(&self) -> Option<u64> {
+        None
+    }
+
+    /// Returns whether the handle can take a data block right away.
+    ///
+    /// When `false`, the server keeps a copy of the received block without acknowledging it,
+    /// and writes it at a later `Server::serve()` once this returns `true`. Meanwhile, the
+    /// client retransmits the block, which paces the transfer to the speed of the storage.
+    /// The default implementation returns `true`.
+    fn is_writable(&self) -> bool {
+        true
+    }
+
+    /// Persists the data written so far, e.g. by syncing it to storage.
+    ///
+    /// This is called during write transfers every `Server::set_flush_interval()` blocks,
+    /// so that a crash loses at most that many blocks of a long upload. Returning an error
+    /// aborts the transfer. The default implementation does nothing.
+    fn flush(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
 }
 
 /// TFTP server.
+///
+/// # Transfer IDs
+///
+/// The server runs in one of two modes, chosen when it is created:
+///
+/// * `Server::new()` uses a single socket, bound to the well-known port, for requests and
+///   transfers alike. This is the smallest footprint, with a single pair of socket buffers,
+///   but replies come from port 69, which clients enforcing RFC 1350's transfer IDs reject.
+///   Packets from an endpoint without a transfer are answered with an `AccessViolation` error.
+/// * `Server::with_tid_pool()` enforces transfer IDs strictly: each transfer is carried out
+///   from a socket of its own, and packets sent to it from any other endpoint are rejected
+///   with an `UnknownID` error. This takes one more socket, and pair of buffers, for each
+///   concurrent transfer.
+///
+/// `Server::strict_tid()` tells which mode is in use.
+///
+/// # Broadcast requests
+///
+/// smoltcp delivers datagrams sent to the limited broadcast address (255.255.255.255) to the
+/// listening socket just like unicast ones, and doesn't tell them apart. Broadcast requests, as
+/// sent by some PXE-style bring-up tools, are then served like any other: replies are sent from
+/// the unicast address of the interface, and transfers are matched on the client endpoint only,
+/// regardless of where the request was sent. Requests sent to a subnet-directed broadcast
+/// address are dropped by the interface. There is no way to ignore broadcast requests from
+/// the server, since it never sees the destination address; filter them before the interface
+/// if needed.
 pub struct Server {
-    udp_handle: SocketHandle,
-    next_poll: Instant,
+    udp_handle: Option<SocketHandle>,
+    port: u16,
+    tid_sockets: [Option<SocketHandle>; MAX_TID_SOCKETS],
+    next_poll: Option<Instant>,
+    mtu: Option<usize>,
+    max_write_bytes: Option<usize>,
+    max_blocks: Option<usize>,
+    parse_mode: ParseMode,
+    overwrite: bool,
+    mode_case_sensitive: bool,
+    octet_aliases: bool,
+    flush_interval: Option<usize>,
+    serve_budget: usize,
+    clock: Option<Clock>,
+    endpoint_filter: Option<EndpointFilter>,
+    busy_retry_after: Option<Duration>,
+    #[cfg(feature = "read-ahead")]
+    read_ahead: bool,
+    paused: bool,
+    // Client and transfer ID of the transfer preempted by the request being handled
+    preempted: Option<(IpEndpoint, Option<usize>)>,
+    // Recently completed read transfers, with their final block and until when it is absorbed
+    dallying: [Option<(IpEndpoint, u16, Instant)>; DALLY_SLOTS],
+    #[cfg(feature = "metrics")]
+    packet_stats: PacketStats,
+    stats: ServerStats,
 }
 
 impl Server {
@@ -114,399 +467,4655 @@ impl Server {
         net_trace!("TFTP initialised");
 
         Server {
-            udp_handle,
-            next_poll: now,
+            udp_handle: Some(udp_handle),
+            ..Server::detached(now)
         }
     }
 
-    /// Returns the duration until the next poll activity.
+    /// Creates a TFTP server carrying out each transfer from a dedicated socket.
     ///
-    /// Useful for suspending execution after polling.
-    pub fn next_poll(&self, now: Instant) -> Duration {
-        self.next_poll - now
-    }
-
-    /// Serves files from the provided context and manages any active transfers.
+    /// By default, transfers are carried out from the well-known port on which requests
+    /// are received. Here, as per RFC 1350, each transfer is taken over by a socket from
+    /// a pool instead, bound to a port of its own (its transfer ID), so that clients checking
+    /// where replies come from are satisfied.
     ///
-    /// This function must be called after `Interface::poll()` to handle packed transmission
-    /// and reception. File errors are handled internally by relaying an error packet to the client
-    /// and terminating the transfer, if necessary.
+    /// One socket is added to the provided `SocketSet` for each pair of buffers yielded by
+    /// `tid_buffers`, up to `MAX_TID_SOCKETS`. The sockets are bound to consecutive ports
+    /// starting from `TID_BASE_PORT`, which they keep across transfers.
+    /// Requests are rejected while all of them are in use.
     ///
-    /// The `context` and the active `transfers` need to be persisted across calls to this function.
-    pub fn serve<'a, C>(
-        &mut self,
-        sockets: &mut SocketSet,
-        context: &mut C,
-        transfers: &mut ManagedSlice<'a, Option<Transfer<C::Handle>>>,
+    /// The listening socket is then reserved for requests: packets of an ongoing transfer
+    /// sent to the well-known port are dropped, so that bulk data never fills its receive buffer
+    /// and new clients are always answered promptly. Its buffers only need to hold a few
+    /// requests, while each socket of the pool should have room for a couple of DATA packets.
+    pub fn with_tid_pool<'a, 'b, 'c, I>(
+        sockets: &mut SocketSet<'a, 'b, 'c>,
+        rx_buffer: UdpSocketBuffer<'b, 'c>,
+        tx_buffer: UdpSocketBuffer<'b, 'c>,
+        tid_buffers: I,
         now: Instant,
-    ) -> net::Result<()>
+    ) -> Self
     where
-        C: Context,
+        I: IntoIterator<Item = (UdpSocketBuffer<'b, 'c>, UdpSocketBuffer<'b, 'c>)>,
     {
-        let mut socket = sockets.get::<UdpSocket>(self.udp_handle);
-
-        // Bind the socket if necessary
-        if !socket.is_open() {
-            socket.bind(IpEndpoint {
-                addr: IpAddress::Unspecified,
-                port: TFTP_PORT,
-            })?;
+        let mut server = Server::new(sockets, rx_buffer, tx_buffer, now);
+        for (slot, (rx_buffer, tx_buffer)) in server.tid_sockets.iter_mut().zip(tid_buffers) {
+            *slot = Some(sockets.add(UdpSocket::new(rx_buffer, tx_buffer)));
         }
+        server
+    }
 
-        // Schedule next activation
-        self.next_poll = now + Duration::from_millis(50);
+    /// Returns the number of sockets in the transfer ID pool, if any.
+    pub fn tid_pool_size(&self) -> usize {
+        self.tid_sockets.iter().filter(|s| s.is_some()).count()
+    }
 
-        // Process incoming packets
-        match socket.recv() {
-            Ok((data, ep)) => {
-                // Validate packet length
-                let tftp_packet = match Packet::new_checked(data) {
-                    Ok(tftp_packet) => tftp_packet,
-                    Err(_) => {
-                        send_error(
-                            &mut *socket,
-                            ep,
-                            ErrorCode::AccessViolation,
-                            "Packet truncated",
-                        )?;
-                        return Ok(());
-                    }
-                };
+    /// Returns `true` if transfers are carried out from sockets of their own,
+    /// i.e. if the server was created with a non-empty transfer ID pool.
+    pub fn strict_tid(&self) -> bool {
+        self.tid_pool_size() > 0
+    }
 
-                // Validate packet contents
-                let tftp_repr = match Repr::parse(&tftp_packet) {
-                    Ok(tftp_repr) => tftp_repr,
-                    Err(_) => {
-                        return send_error(
-                            &mut *socket,
-                            ep,
-                            ErrorCode::AccessViolation,
-                            "Malformed packet",
-                        );
-                    }
-                };
+    /// Returns the handle of the UDP socket on which requests are received,
+    /// or `None` if the server is detached.
+    ///
+    /// This gives access to the underlying `UdpSocket` through the `SocketSet`, e.g. to
+    /// inspect its buffers. The socket must not be closed, rebound or read from,
+    /// or the server stops working.
+    ///
+    /// ```rust
+    /// # use smolapps::tftp::Server;
+    /// # use smolapps::net::socket::{SocketSet, UdpSocketBuffer, UdpPacketMetadata};
+    /// # use smolapps::net::time::Instant;
+    /// use smolapps::net::socket::UdpSocket;
+    ///
+    /// # let mut sockets_entries: [_; 1] = Default::default();
+    /// # let mut sockets = SocketSet::new(&mut sockets_entries[..]);
+    /// # let mut rx_storage = [0; 1048];
+    /// # let mut rx_metadata = [UdpPacketMetadata::EMPTY; 2];
+    /// # let mut tx_storage = [0; 1048];
+    /// # let mut tx_metadata = [UdpPacketMetadata::EMPTY; 2];
+    /// # let rx_buffer = UdpSocketBuffer::new(&mut rx_metadata[..], &mut rx_storage[..]);
+    /// # let tx_buffer = UdpSocketBuffer::new(&mut tx_metadata[..], &mut tx_storage[..]);
+    /// let tftp = Server::new(&mut sockets, rx_buffer, tx_buffer, Instant::from_secs(0));
+    ///
+    /// let socket = sockets.get::<UdpSocket>(tftp.socket_handle().unwrap());
+    /// assert_eq!(socket.payload_send_capacity(), 1048);
+    /// assert!(socket.can_send());
+    /// ```
+    pub fn socket_handle(&self) -> Option<SocketHandle> {
+        self.udp_handle
+    }
 
-                // Retrieve the index of the transfer associated to the remote endpoint
-                let xfer_idx = transfers.iter_mut().position(|xfer| {
-                    if let Some(xfer) = xfer {
-                        if xfer.ep == ep {
-                            return true;
-                        }
-                    }
-                    false
-                });
+    /// Creates a TFTP server that is not attached to any socket.
+    ///
+    /// The server must then be driven through `Server::process_datagram()` and
+    /// `Server::process_timeouts()`, while `Server::serve()` fails with `Err(Error::Illegal)`.
+    pub fn detached(now: Instant) -> Self {
+        Server {
+            udp_handle: None,
+            port: DEFAULT_PORT,
+            tid_sockets: [None; MAX_TID_SOCKETS],
+            next_poll: Some(now),
+            mtu: None,
+            max_write_bytes: None,
+            max_blocks: None,
+            parse_mode: ParseMode::Lenient,
+            overwrite: true,
+            mode_case_sensitive: false,
+            octet_aliases: false,
+            flush_interval: None,
+            serve_budget: DEFAULT_SERVE_BUDGET,
+            clock: None,
+            endpoint_filter: None,
+            busy_retry_after: None,
+            #[cfg(feature = "read-ahead")]
+            read_ahead: false,
+            paused: false,
+            preempted: None,
+            dallying: [None; DALLY_SLOTS],
+            #[cfg(feature = "metrics")]
+            packet_stats: PacketStats::default(),
+            stats: ServerStats::default(),
+        }
+    }
 
-                let is_write = tftp_packet.opcode() == OpCode::Write;
+    /// Sets the port on which requests are received (`DEFAULT_PORT` by default).
+    ///
+    /// This overrides the build-time default, and must be called before the first
+    /// `Server::serve()`: the listening socket can't be rebound once it is open.
+    pub fn set_port(&mut self, port: u16) {
+        self.port = port;
+    }
 
-                match (tftp_repr, xfer_idx) {
-                    (Repr::ReadRequest { .. }, Some(_)) | (Repr::WriteRequest { .. }, Some(_)) => {
-                        // Multiple connections from the same host are not supported
-                        net_debug!("tftp: multiple connection attempts from {}", ep);
+    /// Returns the port on which requests are received.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
 
-                        return send_error(
-                            &mut *socket,
-                            ep,
-                            ErrorCode::AccessViolation,
-                            "Multiple connections not supported",
-                        );
-                    }
-                    (Repr::ReadRequest { filename, mode, .. }, None)
-                    | (Repr::WriteRequest { filename, mode, .. }, None) => {
-                        if mode != Mode::Octet {
-                            return send_error(
-                                &mut *socket,
-                                ep,
-                                ErrorCode::IllegalOperation,
-                                "Only octet mode is supported",
-                            );
-                        }
+    /// Sets the maximum UDP payload size that can be carried by the link.
+    ///
+    /// When set, the block size of every transfer is capped so that DATA packets always fit
+    /// within `mtu` bytes. If the cap falls below the default block size, clients must request
+    /// a `blksize` option (RFC 2348) for their requests to be accepted.
+    pub fn set_mtu(&mut self, mtu: Option<usize>) {
+        self.mtu = mtu;
+    }
 
-                        // Find the first free transfer available, or allocate one if possible
-                        let opt_idx =
-                            transfers.iter().position(|t| t.is_none()).or_else(
-                                || match transfers {
-                                    ManagedSlice::Borrowed(_) => None,
-                                    #[cfg(feature = "std")]
-                                    ManagedSlice::Owned(v) => {
-                                        let idx = v.len();
-                                        v.push(None);
-                                        Some(idx)
-                                    }
-                                },
-                            );
-
-                        if let Some(idx) = opt_idx {
-                            // Open file handle
-                            let handle = match context.open(filename, is_write) {
-                                Ok(handle) => handle,
-                                Err(_) => {
-                                    net_debug!("tftp: unable to open requested file");
-                                    return send_error(
-                                        &mut *socket,
-                                        ep,
-                                        ErrorCode::FileNotFound,
-                                        "Unable to open requested file",
-                                    );
-                                }
-                            };
+    /// Returns the maximum UDP payload size that can be carried by the link, if any.
+    pub fn mtu(&self) -> Option<usize> {
+        self.mtu
+    }
 
-                            // Allocate new transfer
-                            let mut xfer = Transfer {
-                                handle,
-                                ep,
-                                is_write,
-                                block_num: 1,
-                                last_data: None,
-                                last_len: 0,
-                                retries: 0,
-                                timeout: now + Duration::from_millis(50),
-                            };
+    /// Sets the maximum number of bytes that can be written by a single write transfer.
+    ///
+    /// When a write is resumed, the bytes already stored count towards the limit.
+    /// Requests announcing a larger `tsize` option (RFC 2349) are rejected upfront.
+    /// Otherwise, a transfer exceeding the limit is aborted with a `DiskFull` error,
+    /// without writing the offending block. The handle is then passed to [`Context::discard()`],
+    /// which is responsible for disposing of the partially written file.
+    ///
+    /// [`Context::discard()`]: trait.Context.html#method.discard
+    pub fn set_max_write_bytes(&mut self, max: Option<usize>) {
+        self.max_write_bytes = max;
+    }
 
-                            net_debug!(
-                                "tftp: {} request from {}",
-                                if is_write { "write" } else { "read" },
-                                ep
-                            );
-
-                            if is_write {
-                                xfer.send_ack(&mut *socket, 0)?;
-                            } else {
-                                xfer.send_data(&mut *socket)?;
-                            }
-
-                            // Enque transfer
-                            transfers[idx] = Some(xfer);
-                        } else {
-                            // Exhausted transfers buffer
-                            net_debug!("tftp: connections exhausted");
-
-                            return send_error(
-                                &mut *socket,
-                                ep,
-                                ErrorCode::AccessViolation,
-                                "No more available connections",
-                            );
-                        }
-                    }
-                    (Repr::Data { .. }, None) | (Repr::Ack { .. }, None) => {
-                        // Data request on unconnected socket
-                        return send_error(
-                            &mut *socket,
-                            ep,
-                            ErrorCode::AccessViolation,
-                            "Data packet without active transfer",
-                        );
-                    }
-                    (Repr::Data { block_num, data }, Some(idx)) => {
-                        let xfer = transfers[idx].as_mut().unwrap();
-
-                        // Reset retransmission counter
-                        xfer.timeout = now + RETRY_TIMEOUT;
-                        xfer.retries = 0;
-
-                        // Make sure this is a write connection
-                        if !xfer.is_write {
-                            return send_error(
-                                &mut *socket,
-                                ep,
-                                ErrorCode::AccessViolation,
-                                "Not a write connection",
-                            );
-                        }
+    /// Returns the maximum number of bytes that can be written by a single write transfer, if any.
+    pub fn max_write_bytes(&self) -> Option<usize> {
+        self.max_write_bytes
+    }
 
-                        // Unexpected packet, resend ACK for (block_num - 1)
-                        if block_num != xfer.block_num {
-                            return xfer.send_ack(&mut *socket, xfer.block_num - 1);
-                        }
+    /// Sets the maximum number of DATA or ACK packets accepted from the client of a transfer,
+    /// or `None` (the default) for no limit.
+    ///
+    /// Duplicates count as well, so that a client endlessly re-acknowledging a block to force
+    /// retransmissions can't hold a transfer slot forever. Once the limit is exceeded,
+    /// the transfer is aborted with an error. Transfers of `n` blocks need a limit of at least
+    /// `n` packets, or `n + 1` if options are negotiated, to account for the acknowledgment of
+    /// the OACK by readers.
+    pub fn set_max_blocks(&mut self, max: Option<usize>) {
+        self.max_blocks = max;
+    }
 
-                        // Update block number
-                        xfer.block_num += 1;
-
-                        // Write data to the destination file
-                        match xfer.handle.write(data) {
-                            Ok(_) => {
-                                let last_block = data.len() < 512;
-
-                                // Send ACK and optionally close the transfer
-                                xfer.send_ack(&mut *socket, block_num)?;
-                                if last_block {
-                                    self.close_transfer(context, &mut transfers[idx]);
-                                }
-                            }
-                            Err(_) => {
-                                send_error(
-                                    &mut *socket,
-                                    ep,
-                                    ErrorCode::AccessViolation,
-                                    "Error writing file",
-                                )?;
-                                self.close_transfer(context, &mut transfers[idx]);
-                            }
-                        }
-                    }
-                    (Repr::Ack { block_num }, Some(idx)) => {
-                        let xfer = transfers[idx].as_mut().unwrap();
-
-                        // Reset retransmission counter
-                        xfer.timeout = now + RETRY_TIMEOUT;
-                        xfer.retries = 0;
-
-                        // Make sure this is a read connection
-                        if xfer.is_write {
-                            return send_error(
-                                &mut *socket,
-                                ep,
-                                ErrorCode::AccessViolation,
-                                "Not a read connection",
-                            );
-                        }
+    /// Returns the maximum number of DATA or ACK packets accepted for a transfer, if any.
+    pub fn max_blocks(&self) -> Option<usize> {
+        self.max_blocks
+    }
 
-                        // Unexpected ACK, resend previous block
-                        if block_num != xfer.block_num {
-                            return xfer.resend_data(&mut *socket);
-                        }
+    /// Sets how tolerant the server is of malformed packets (`ParseMode::Lenient` by default).
+    ///
+    /// Rejected packets are answered with an `IllegalOperation` error.
+    pub fn set_parse_mode(&mut self, mode: ParseMode) {
+        self.parse_mode = mode;
+    }
 
-                        // Update block number
-                        xfer.block_num += 1;
+    /// Returns how tolerant the server is of malformed packets.
+    pub fn parse_mode(&self) -> ParseMode {
+        self.parse_mode
+    }
 
-                        if xfer.last_len == 512 {
-                            xfer.send_data(&mut *socket)?;
-                        } else {
-                            self.close_transfer(context, &mut transfers[idx]);
-                        }
-                    }
-                    (Repr::Error { .. }, _) => {
-                        return send_error(
-                            &mut *socket,
-                            ep,
-                            ErrorCode::IllegalOperation,
-                            "Unknown operation",
-                        );
-                    }
-                }
+    /// Sets whether write requests may overwrite existing files (`true` by default).
+    ///
+    /// When disabled, writes to files reported by `Context::exists()` are rejected with
+    /// a `FileExists` error. Resumed writes are still allowed to append to existing files.
+    pub fn set_overwrite(&mut self, overwrite: bool) {
+        self.overwrite = overwrite;
+    }
 
-                Ok(())
-            }
-            Err(Error::Exhausted) => {
-                // Nothing to receive, process outgoing packets
-                if socket.can_send() && now >= self.next_poll {
-                    for xfer in transfers.iter_mut() {
-                        let do_drop = if let Some(xfer) = xfer {
-                            xfer.process_timeout(&mut socket, now)?
-                        } else {
-                            false
-                        };
+    /// Returns whether write requests may overwrite existing files.
+    pub fn overwrite(&self) -> bool {
+        self.overwrite
+    }
 
-                        if do_drop {
-                            self.close_transfer(context, xfer);
-                        }
-                    }
-                }
-                Ok(())
-            }
-            Err(e) => Err(e),
-        }
+    /// Sets whether the mode string of requests must be spelled in lowercase (`false` by default).
+    ///
+    /// As RFC 1350 requires, mode strings are case-insensitive by default, so that `octet`,
+    /// `OCTET` and `Octet` are all accepted. When enabled, the mode string must match
+    /// `Mode::as_str()` exactly, regardless of the parse mode, and requests spelling it
+    /// otherwise are rejected with an `IllegalOperation` error.
+    pub fn set_mode_case_sensitive(&mut self, case_sensitive: bool) {
+        self.mode_case_sensitive = case_sensitive;
     }
 
-    /// Terminates a transfer, releasing the handle and freeing up the transfer slot.
-    fn close_transfer<C>(&mut self, context: &mut C, xfer: &mut Option<Transfer<C::Handle>>)
-    where
-        C: Context,
-    {
-        if let Some(xfer) = xfer.take() {
-            net_debug!("tftp: closing {}", xfer.ep);
-            context.close(xfer.handle);
-        }
+    /// Returns whether the mode string of requests must be spelled in lowercase.
+    pub fn mode_case_sensitive(&self) -> bool {
+        self.mode_case_sensitive
     }
-}
 
-/// An active TFTP transfer.
-pub struct Transfer<H> {
-    handle: H,
-    ep: IpEndpoint,
+    /// Sets whether the obsolete `binary` and `image` mode strings are accepted as synonyms
+    /// of `octet` (`false` by default).
+    ///
+    /// This improves interoperability with legacy clients. The aliases are always rejected
+    /// in `ParseMode::Strict`, and must be spelled in lowercase when
+    /// `Server::set_mode_case_sensitive()` is enabled. See `Mode::octet_alias()`.
+    pub fn set_octet_aliases(&mut self, accept: bool) {
+        self.octet_aliases = accept;
+    }
 
-    is_write: bool,
-    block_num: u16,
-    // FIXME: I'd reeeally love to avoid a potential stack allocation this big :\
-    last_data: Option<[u8; 512]>,
-    last_len: usize,
+    /// Returns whether the obsolete `binary` and `image` mode strings are accepted.
+    pub fn octet_aliases(&self) -> bool {
+        self.octet_aliases
+    }
 
-    retries: u8,
-    timeout: Instant,
-}
+    /// Sets how many blocks are received by write transfers between calls to `Handle::flush()`.
+    ///
+    /// Periodic flushing is disabled by default (and when `interval` is `Some(0)`), in which case
+    /// the data is only expected to be persisted when the handle is returned to the context.
+    pub fn set_flush_interval(&mut self, interval: Option<usize>) {
+        self.flush_interval = interval.filter(|n| *n > 0);
+    }
 
-impl<H> Transfer<H>
-where
-    H: Handle,
-{
-    fn process_timeout(&mut self, socket: &mut UdpSocket, now: Instant) -> net::Result<bool> {
-        if now >= self.timeout && self.retries < MAX_RETRIES {
-            self.retries += 1;
-            self.resend_data(socket).map(|_| false)
-        } else {
-            net_debug!("tftp: connection timeout");
-            Ok(true)
-        }
+    /// Returns how many blocks are received by write transfers between flushes, if any.
+    pub fn flush_interval(&self) -> Option<usize> {
+        self.flush_interval
     }
 
-    fn send_data(&mut self, socket: &mut UdpSocket) -> net::Result<bool> {
-        // Allocate data
-        if self.last_data.is_none() {
-            self.last_data = Some([0; 512]);
-        }
+    /// Sets the maximum number of datagrams processed by each call to `Server::serve()`
+    /// (8 by default).
+    ///
+    /// Queued datagrams are processed until the sockets are drained or the budget is spent,
+    /// so that bursts of packets don't add latency while the rest of the system isn't starved.
+    /// Timed out transfers are retransmitted after that on every call, however many datagrams
+    /// were processed. The budget is at least one.
+    pub fn set_serve_budget(&mut self, budget: usize) {
+        self.serve_budget = budget.max(1);
+    }
 
-        // Read next chunk
-        self.last_len = match self.handle.read(&mut self.last_data.as_mut().unwrap()[..]) {
-            Ok(n) => n,
-            Err(_) => {
-                send_error(
-                    socket,
-                    self.ep,
-                    ErrorCode::AccessViolation,
-                    "Error occurred while reading the file",
-                )?;
-                return Ok(false);
-            }
-        };
+    /// Returns the maximum number of datagrams processed by each call to `Server::serve()`.
+    pub fn serve_budget(&self) -> usize {
+        self.serve_budget
+    }
 
-        self.resend_data(socket).map(|_| false)
+    /// Sets the clock read by `Server::serve_now()`, or `None` (the default) to disable it.
+    ///
+    /// Handy to keep the clock in a single place, or to drive timeouts from a fake clock in tests.
+    pub fn set_clock(&mut self, clock: Option<Clock>) {
+        self.clock = clock;
     }
 
-    fn resend_data(&mut self, socket: &mut UdpSocket) -> net::Result<()> {
-        if let Some(last_data) = &self.last_data {
-            net_trace!("tftp: sending data block #{}", self.block_num);
+    /// Returns the clock read by `Server::serve_now()`, if any.
+    pub fn clock(&self) -> Option<Clock> {
+        self.clock
+    }
 
-            let data = Repr::Data {
-                block_num: self.block_num,
-                data: &last_data[..self.last_len],
-            };
-            let payload = socket.send(data.buffer_len(), self.ep)?;
-            let mut pkt = Packet::new_unchecked(payload);
-            data.emit(&mut pkt)?;
+    /// Sets the filter deciding which peers may reach the server, or `None` (the default)
+    /// to accept packets from anyone.
+    ///
+    /// Packets from endpoints rejected by the filter are dropped without a reply, whether
+    /// they are requests or belong to a transfer, before anything else is done with them.
+    ///
+    /// ```rust
+    /// # use smolapps::tftp::Server;
+    /// # use smolapps::net::time::Instant;
+    /// use smolapps::net::wire::{IpAddress, IpEndpoint};
+    ///
+    /// let mut tftp = Server::detached(Instant::from_secs(0));
+    /// // Only serve the hosts of 192.168.69.0/24
+    /// tftp.set_endpoint_filter(Some(|ep: IpEndpoint| match ep.addr {
+    ///     IpAddress::Ipv4(addr) => addr.0[..3] == [192, 168, 69],
+    ///     _ => false,
+    /// }));
+    /// ```
+    pub fn set_endpoint_filter(&mut self, filter: Option<EndpointFilter>) {
+        self.endpoint_filter = filter;
+    }
+
+    /// Returns the filter deciding which peers may reach the server, if any.
+    pub fn endpoint_filter(&self) -> Option<EndpointFilter> {
+        self.endpoint_filter
+    }
+
+    /// Returns `true` if packets from `ep` may be processed, as per the endpoint filter.
+    fn allows(&self, ep: IpEndpoint) -> bool {
+        match self.endpoint_filter {
+            Some(filter) => filter(ep),
+            None => true,
+        }
+    }
+
+    /// Sets whether read transfers read each block ahead of time (disabled by default).
+    ///
+    /// Normally, a block is only read from its handle once the previous one is acknowledged,
+    /// so that storage reads and network round-trips add up. With read-ahead, the next block
+    /// is read right after a block is sent, and is ready to go as soon as the client ACKs it.
+    /// This speeds up reads from slow storage. Read errors are only reported to the client
+    /// once the block is due.
+    ///
+    /// The second block buffer this needs is only part of transfers when the `read-ahead`
+    /// feature is enabled, in which case every transfer slot carries it, whether it is used
+    /// for a read or not.
+    #[cfg(feature = "read-ahead")]
+    pub fn set_read_ahead(&mut self, read_ahead: bool) {
+        self.read_ahead = read_ahead;
+    }
+
+    /// Returns `true` if read transfers read each block ahead of time.
+    #[cfg(feature = "read-ahead")]
+    pub fn read_ahead(&self) -> bool {
+        self.read_ahead
+    }
+
+    /// Sets the delay suggested to clients turned away while the server is busy, or `None`
+    /// (the default) to reject them with a plain `AccessViolation` error.
+    ///
+    /// Requests received while all the transfer slots (or transfer IDs) are in use are then
+    /// answered with an `Undefined` error, whose message is `BUSY_MESSAGE_PREFIX` followed by
+    /// the delay in whole seconds, rounded up, e.g. `busy; retry-after=5`. TFTP has no standard
+    /// field for this, so only cooperating clients back off accordingly, see `parse_retry_after()`;
+    /// the others just see their request fail. Either way, the rejection is counted in
+    /// `ServerStats::busy`.
+    pub fn set_busy_retry_after(&mut self, delay: Option<Duration>) {
+        self.busy_retry_after = delay;
+    }
+
+    /// Returns the delay suggested to clients turned away while the server is busy, if any.
+    pub fn busy_retry_after(&self) -> Option<Duration> {
+        self.busy_retry_after
+    }
+
+    /// Stops accepting new transfers, e.g. to drain the server before a shutdown.
+    ///
+    /// Requests received while paused are turned away as if the server were busy, see
+    /// `Server::set_busy_retry_after()`, and never preempt active transfers. Transfers
+    /// already underway are carried out to completion as usual: the server is drained
+    /// once `Server::serve()` leaves no active transfer.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Starts accepting new transfers again after `Server::pause()`.
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Returns `true` if the server is paused, i.e. turns new requests away.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Turns a request away because all the transfers are in use, or the server is paused.
+    fn reject_busy(&mut self, socket: &mut impl Transmit, ep: IpEndpoint) -> net::Result<()> {
+        self.stats.busy += 1;
+
+        let delay = match self.busy_retry_after {
+            Some(delay) => delay,
+            None => {
+                return self.relay_error(
+                    socket,
+                    ep,
+                    ErrorCode::AccessViolation,
+                    "No more available connections",
+                )
+            }
+        };
+
+        let mut buf = [0; BUSY_MESSAGE_LEN];
+        let msg = busy_message(&mut buf, delay);
+        self.relay_error(socket, ep, ErrorCode::Undefined, msg)
+    }
+
+    /// Returns the sizes of the largest packets exchanged by all the transfers closed so far.
+    ///
+    /// Active transfers can be inspected through `Transfer::packet_stats()`.
+    #[cfg(feature = "metrics")]
+    pub fn packet_stats(&self) -> PacketStats {
+        self.packet_stats
+    }
+
+    /// Returns the counters of the activity of this server.
+    pub fn stats(&self) -> &ServerStats {
+        &self.stats
+    }
+
+    /// Returns the effective configuration of this server, e.g. to log it at startup.
+    ///
+    /// `transfers` is the table of transfer slots handed to `Server::serve()`, whose length
+    /// is the number of transfers that can be carried out at once.
+    pub fn describe<H>(&self, transfers: &[Option<Transfer<H>>]) -> ServerConfig {
+        ServerConfig {
+            port: self.port,
+            attached: self.udp_handle.is_some(),
+            tid_pool_size: self.tid_pool_size(),
+            transfer_capacity: transfers.len(),
+            retry_timeout: RETRY_TIMEOUT,
+            max_retries: MAX_RETRIES,
+            max_block_size: self.max_block_size(),
+            mtu: self.mtu,
+            max_write_bytes: self.max_write_bytes,
+            max_blocks: self.max_blocks,
+            parse_mode: self.parse_mode,
+            overwrite: self.overwrite,
+            mode_case_sensitive: self.mode_case_sensitive,
+            octet_aliases: self.octet_aliases,
+            flush_interval: self.flush_interval,
+            serve_budget: self.serve_budget,
+            busy_retry_after: self.busy_retry_after,
+            #[cfg(feature = "read-ahead")]
+            read_ahead: self.read_ahead,
+            paused: self.paused,
+        }
+    }
+
+    /// Returns the duration until the next poll activity.
+    ///
+    /// Useful for suspending execution after polling. If there is no scheduled activity,
+    /// `IDLE_POLL_INTERVAL` is returned: incoming requests are still served as they arrive,
+    /// as long as `Server::serve()` is called whenever the interface receives something.
+    #[must_use]
+    pub fn next_poll(&self, now: Instant) -> Duration {
+        match self.next_poll {
+            Some(next_poll) if next_poll > now => next_poll - now,
+            Some(_) => Duration::from_millis(0),
+            None => IDLE_POLL_INTERVAL,
+        }
+    }
+
+    /// Returns the instant of the next poll activity, or `None` if there is nothing to do
+    /// until a new request arrives.
+    #[must_use]
+    pub fn next_poll_at(&self) -> Option<Instant> {
+        self.next_poll
+    }
+
+    /// Returns `true` if there are no active transfers, hence no pending retransmissions.
+    ///
+    /// An idle server only needs to be polled when a packet is received.
+    pub fn is_idle<H>(&self, transfers: &[Option<Transfer<H>>]) -> bool {
+        transfers.iter().all(Option::is_none)
+    }
+
+    /// Terminates all the active transfers, e.g. after the link has gone down and back up.
+    ///
+    /// This is equivalent to [`close_all()`].
+    ///
+    /// [`close_all()`]: #method.close_all
+    pub fn reset<C>(&mut self, context: &mut C, transfers: &mut Transfers<C::Handle>)
+    where
+        C: Context,
+    {
+        self.close_all(context, transfers);
+    }
+
+    /// Terminates all the active transfers, returning their handles to the `context`.
+    ///
+    /// Clients are not notified: their transfers are simply forgotten,
+    /// and the handles of write transfers are discarded.
+    ///
+    /// Since transfers don't own the context, dropping them never releases their handles:
+    /// this must be called before dropping `transfers` (or the server) with transfers
+    /// still active. Debug builds log a warning for every transfer dropped while active.
+    pub fn close_all<C>(&mut self, context: &mut C, transfers: &mut Transfers<C::Handle>)
+    where
+        C: Context,
+    {
+        for xfer in transfers.iter_mut() {
+            self.close_transfer(context, xfer);
+        }
+        self.dallying = [None; DALLY_SLOTS];
+        self.next_poll = None;
+    }
+
+    /// Serves files from the provided context and manages any active transfers.
+    ///
+    /// This function must be called after `Interface::poll()` to handle packed transmission
+    /// and reception. File errors are handled internally by relaying an error packet to the client
+    /// and terminating the transfer, if necessary.
+    ///
+    /// The `context` and the active `transfers` need to be persisted across calls to this function.
+    ///
+    /// Returns `Err(Error::Truncated)` if the receive buffer of a socket cannot hold a full DATA
+    /// packet, which would otherwise be silently dropped and stall write transfers. The largest
+    /// DATA packet is 516 bytes long, unless a smaller block size is enforced through `set_mtu()`.
+    #[must_use = "errors, e.g. undersized socket buffers, go unnoticed if the result is discarded"]
+    pub fn serve<'a, C>(
+        &mut self,
+        sockets: &mut SocketSet,
+        context: &mut C,
+        transfers: &mut Transfers<'a, C::Handle>,
+        now: Instant,
+    ) -> net::Result<()>
+    where
+        C: Context,
+    {
+        self.serve_counted(sockets, context, transfers, now)
+            .map(|_| ())
+    }
+
+    /// Same as `Server::serve()`, also returning how many datagrams were received and sent.
+    ///
+    /// Retransmissions are included in the datagrams sent. If all the `Server::serve_budget()`
+    /// datagrams were received, more may be waiting and the server can be served again
+    /// right away.
+    #[must_use = "errors, e.g. undersized socket buffers, go unnoticed if the result is discarded"]
+    pub fn serve_counted<'a, C>(
+        &mut self,
+        sockets: &mut SocketSet,
+        context: &mut C,
+        transfers: &mut Transfers<'a, C::Handle>,
+        now: Instant,
+    ) -> net::Result<Activity>
+    where
+        C: Context,
+    {
+        let res = self
+            .process(sockets, context, transfers, now)
+            .and_then(|activity| {
+                let sent = self.retransmit_timed_out(sockets, context, transfers, now)?;
+                Ok(Activity {
+                    sent: activity.sent + sent,
+                    ..activity
+                })
+            });
+        self.schedule(transfers, now);
+        res
+    }
+
+    /// Same as `Server::serve()`, reading the current time from the clock set through
+    /// `Server::set_clock()`.
+    ///
+    /// Returns `Err(Error::Illegal)` if no clock is set.
+    #[must_use = "errors, e.g. undersized socket buffers, go unnoticed if the result is discarded"]
+    pub fn serve_now<'a, C>(
+        &mut self,
+        sockets: &mut SocketSet,
+        context: &mut C,
+        transfers: &mut Transfers<'a, C::Handle>,
+    ) -> net::Result<()>
+    where
+        C: Context,
+    {
+        match self.clock {
+            Some(clock) => self.serve(sockets, context, transfers, clock()),
+            None => Err(Error::Illegal),
+        }
+    }
+
+    /// Retransmits the packets of the active transfers that timed out, and drops the transfers
+    /// that exhausted their retries, without receiving any packet.
+    ///
+    /// `Server::serve()` already does this after processing incoming packets, so that transfers
+    /// are looked after even under steady inbound traffic. This is only useful to service
+    /// timeouts more often than packets are processed.
+    #[must_use = "errors, e.g. undersized socket buffers, go unnoticed if the result is discarded"]
+    pub fn poll_transfers<'a, C>(
+        &mut self,
+        sockets: &mut SocketSet,
+        context: &mut C,
+        transfers: &mut Transfers<'a, C::Handle>,
+        now: Instant,
+    ) -> net::Result<()>
+    where
+        C: Context,
+    {
+        let res = self.retransmit_timed_out(sockets, context, transfers, now);
+        self.schedule(transfers, now);
+        res.map(|_| ())
+    }
+
+    /// Processes a datagram received from `ep`, sending the reply (if any) through `tx`.
+    ///
+    /// This is the socket-less equivalent of `Server::serve()` receiving a packet,
+    /// to be used along with `Server::process_timeouts()`.
+    pub fn process_datagram<'a, C, T>(
+        &mut self,
+        context: &mut C,
+        transfers: &mut Transfers<'a, C::Handle>,
+        data: &[u8],
+        ep: IpEndpoint,
+        now: Instant,
+        tx: &mut T,
+    ) -> net::Result<()>
+    where
+        C: Context,
+        T: Transmit,
+    {
+        if !self.allows(ep) {
+            net_debug!("tftp: dropping packet from filtered endpoint {}", ep);
+            return Ok(());
+        }
+        let res = self.handle_datagram(context, transfers, data, ep, now, tx);
+        let notified = match self.preempted.take() {
+            Some((victim, _)) => self.notify_preempted(tx, victim),
+            None => Ok(()),
+        };
+        self.schedule(transfers, now);
+        res.and(notified)
+    }
+
+    /// Retransmits the packets of the active transfers that timed out through `tx`,
+    /// and drops the transfers that exhausted their retries.
+    ///
+    /// This is the socket-less equivalent of `Server::poll_transfers()`.
+    /// It should be called at least as often as `Server::next_poll()` indicates.
+    pub fn process_timeouts<'a, C, T>(
+        &mut self,
+        context: &mut C,
+        transfers: &mut Transfers<'a, C::Handle>,
+        now: Instant,
+        tx: &mut T,
+    ) -> net::Result<()>
+    where
+        C: Context,
+        T: Transmit,
+    {
+        let res = self.handle_timeouts(context, transfers, now, tx);
+        self.schedule(transfers, now);
+        res
+    }
+
+    /// Sends an error packet to `ep` from the listening socket, outside of any transfer.
+    ///
+    /// This lets the application reject a peer on its own terms between polls, e.g. one
+    /// that is not allowed to reach the server. The packet goes out at the next
+    /// `Interface::poll()`. Returns `Err(Error::Illegal)` if the server is not attached
+    /// to any socket.
+    pub fn send_error(
+        &mut self,
+        sockets: &mut SocketSet,
+        ep: IpEndpoint,
+        code: ErrorCode,
+        msg: &str,
+    ) -> net::Result<()> {
+        let udp_handle = match self.udp_handle {
+            Some(udp_handle) => udp_handle,
+            None => return Err(Error::Illegal),
+        };
+        self.bind(sockets)?;
+
+        let mut socket = sockets.get::<UdpSocket>(udp_handle);
+        self.relay_error(&mut *socket, ep, code, msg)
+    }
+
+    /// Closes the transfer with `ep` right away, after learning that the client is unreachable.
+    ///
+    /// Without this, the transfer of a client that went away is only dropped once
+    /// all retransmissions are exhausted. No error packet is sent to the client, and the
+    /// handle of a write transfer is discarded. Returns whether a transfer was closed.
+    ///
+    /// smoltcp does not report ICMP errors to UDP sockets. Destination Unreachable messages
+    /// can be received by an `IcmpSocket` bound to `IcmpEndpoint::Udp` with the endpoint of the
+    /// server instead: the destination of the UDP packet quoted in the message is `ep`.
+    pub fn peer_unreachable<C>(
+        &mut self,
+        context: &mut C,
+        transfers: &mut Transfers<C::Handle>,
+        ep: IpEndpoint,
+        now: Instant,
+    ) -> bool
+    where
+        C: Context,
+    {
+        let xfer = transfers
+            .iter_mut()
+            .find(|xfer| xfer.as_ref().map(|xfer| xfer.ep) == Some(ep));
+        let closed = match xfer {
+            Some(xfer) => {
+                net_debug!("tftp: {} unreachable", ep);
+                self.close_transfer(context, xfer);
+                true
+            }
+            None => false,
+        };
+        self.schedule(transfers, now);
+        closed
+    }
+
+    /// Schedules the next activation, only if there are transfers to look after.
+    fn schedule<H>(&mut self, transfers: &[Option<Transfer<H>>], now: Instant) {
+        self.next_poll = if self.is_idle(transfers) {
+            None
+        } else {
+            Some(now + Duration::from_millis(50))
+        };
+    }
+
+    fn process<'a, C>(
+        &mut self,
+        sockets: &mut SocketSet,
+        context: &mut C,
+        transfers: &mut Transfers<'a, C::Handle>,
+        now: Instant,
+    ) -> net::Result<Activity>
+    where
+        C: Context,
+    {
+        let udp_handle = match self.udp_handle {
+            Some(udp_handle) => udp_handle,
+            None => return Err(Error::Illegal),
+        };
+
+        self.bind(sockets)?;
+
+        // Process incoming packets, until the sockets are drained or the budget is spent
+        let mut budget = self.serve_budget;
+        let tid_sockets = self.tid_sockets;
+        let handles = tid_sockets
+            .iter()
+            .enumerate()
+            .filter_map(|(tid, handle)| handle.map(|h| (h, Some(tid))))
+            .chain(core::iter::once((udp_handle, None)));
+        let mut activity = Activity::default();
+        loop {
+            let mut received = false;
+            for (handle, tid) in handles.clone() {
+                if budget == 0 {
+                    break;
+                }
+                if let Some(sent) =
+                    self.process_socket(sockets, handle, tid, context, transfers, now)?
+                {
+                    received = true;
+                    budget -= 1;
+                    activity.received += 1;
+                    activity.sent += sent;
+                }
+            }
+            if !received || budget == 0 {
+                break;
+            }
+        }
+        Ok(activity)
+    }
+
+    /// Handles the timeouts of the active transfers, each on its own socket.
+    ///
+    /// Returns the number of datagrams sent.
+    fn retransmit_timed_out<'a, C>(
+        &mut self,
+        sockets: &mut SocketSet,
+        context: &mut C,
+        transfers: &mut Transfers<'a, C::Handle>,
+        now: Instant,
+    ) -> net::Result<usize>
+    where
+        C: Context,
+    {
+        let udp_handle = match self.udp_handle {
+            Some(udp_handle) => udp_handle,
+            None => return Err(Error::Illegal),
+        };
+
+        let mut sent = 0;
+        for xfer in transfers.iter_mut() {
+            let tid = xfer.as_ref().and_then(|xfer| xfer.tid);
+            let mut socket = sockets.get::<UdpSocket>(self.socket_of(tid, udp_handle));
+            let mut socket = Counted::new(&mut *socket);
+            self.handle_timeout(context, xfer, now, &mut socket)?;
+            sent += socket.sent;
+        }
+        Ok(sent)
+    }
+
+    /// Sends the last packet of `xfer` again right away, from the socket carrying it.
+    ///
+    /// This is `Transfer::retransmit()` for attached servers, picking the socket of the
+    /// transfer ID of `xfer` if the pool is in use, and the listening socket otherwise.
+    /// Returns `Err(Error::Illegal)` for detached servers.
+    pub fn retransmit<H>(&self, sockets: &mut SocketSet, xfer: &mut Transfer<H>) -> net::Result<()>
+    where
+        H: Handle,
+    {
+        let udp_handle = self.udp_handle.ok_or(Error::Illegal)?;
+        let mut socket = sockets.get::<UdpSocket>(self.socket_of(xfer.tid, udp_handle));
+        xfer.retransmit(&mut *socket)
+    }
+
+    /// Binds the sockets of the server, if necessary, after checking that they can receive
+    /// full DATA packets.
+    fn bind(&self, sockets: &mut SocketSet) -> net::Result<()> {
+        let min_capacity = DATA_HEADER_LEN + self.max_block_size();
+        for (handle, port) in self.sockets() {
+            let mut socket = sockets.get::<UdpSocket>(handle);
+            if socket.payload_recv_capacity() < min_capacity {
+                net_debug!(
+                    "tftp: rx buffer of {} bytes too small, at least {} bytes are needed",
+                    socket.payload_recv_capacity(),
+                    min_capacity
+                );
+                return Err(Error::Truncated);
+            }
+            if !socket.is_open() {
+                socket.bind(IpEndpoint {
+                    addr: IpAddress::Unspecified,
+                    port,
+                })?;
+            }
         }
         Ok(())
     }
 
-    fn send_ack(&mut self, socket: &mut UdpSocket, block: u16) -> net::Result<()> {
-        net_trace!("tftp: sending ack #{}", block);
+    /// Returns the handles of all the sockets of the server, along with their ports.
+    fn sockets(&self) -> impl Iterator<Item = (SocketHandle, u16)> + '_ {
+        let tids = self.tid_sockets.iter().enumerate();
+        let tids = tids.filter_map(|(tid, handle)| handle.map(|h| (h, TID_BASE_PORT + tid as u16)));
+        self.udp_handle
+            .map(|h| (h, self.port))
+            .into_iter()
+            .chain(tids)
+    }
 
-        let ack = Repr::Ack { block_num: block };
-        let payload = socket.send(ack.buffer_len(), self.ep)?;
-        let mut pkt = Packet::new_unchecked(payload);
-        ack.emit(&mut pkt)
+    /// Returns the handle of the socket of transfer ID `tid`, or `udp_handle` if there is none.
+    fn socket_of(&self, tid: Option<usize>, udp_handle: SocketHandle) -> SocketHandle {
+        tid.and_then(|tid| self.tid_sockets[tid])
+            .unwrap_or(udp_handle)
     }
-}
 
-fn send_error(
-    socket: &mut UdpSocket,
-    ep: IpEndpoint,
-    code: ErrorCode,
-    msg: &str,
-) -> net::Result<()> {
-    net_debug!("tftp: {:?}, message: {}", code, msg);
+    /// Processes a packet received on the socket of transfer ID `tid`, or on the listening
+    /// socket if `None`.
+    ///
+    /// Returns the number of replies sent if there was a packet, `None` otherwise.
+    fn process_socket<'a, C>(
+        &mut self,
+        sockets: &mut SocketSet,
+        handle: SocketHandle,
+        tid: Option<usize>,
+        context: &mut C,
+        transfers: &mut Transfers<'a, C::Handle>,
+        now: Instant,
+    ) -> net::Result<Option<usize>>
+    where
+        C: Context,
+    {
+        let tid_of = |transfers: &[Option<Transfer<C::Handle>>], ep: IpEndpoint| {
+            transfers
+                .iter()
+                .flatten()
+                .find(|xfer| xfer.ep == ep)
+                .and_then(|xfer| xfer.tid)
+        };
 
-    let err = Repr::Error { code, msg };
-    let payload = socket.send(err.buffer_len(), ep)?;
-    let mut pkt = Packet::new_unchecked(payload);
-    err.emit(&mut pkt)
+        // The reply is buffered, since the socket can't be borrowed while `data` is
+        let mut reply = Reply::new();
+        let mut socket = sockets.get::<UdpSocket>(handle);
+        let (res, ep) = match socket.recv() {
+            Ok((_, ep)) if !self.allows(ep) => {
+                net_debug!("tftp: dropping packet from filtered endpoint {}", ep);
+                return Ok(Some(0));
+            }
+            Ok((data, ep)) if tid.is_none() && tid_of(transfers, ep).is_some() => {
+                // The listening socket only accepts requests once the pool is in use
+                let packet = Packet::new_unchecked(data);
+                if data.len() < 2 || !matches!(packet.opcode(), OpCode::Read | OpCode::Write) {
+                    net_debug!("tftp: dropping packet of {} sent to the listening port", ep);
+                    return Ok(Some(0));
+                }
+                (
+                    self.handle_datagram(context, transfers, data, ep, now, &mut reply),
+                    ep,
+                )
+            }
+            Ok((data, ep)) if tid.is_some() && self.is_dallying_ack(data, ep, now) => {
+                // The transfer is over, but its client may not have got the final block
+                (
+                    self.handle_datagram(context, transfers, data, ep, now, &mut reply),
+                    ep,
+                )
+            }
+            Ok((data, ep)) if tid.is_some() && tid_of(transfers, ep) != tid => {
+                // Transfer IDs only accept packets from the client of their transfer
+                net_trace_bytes!("tftp: recv", data);
+                let res =
+                    self.relay_error(&mut reply, ep, ErrorCode::UnknownID, "Unknown transfer ID");
+                (res, ep)
+            }
+            Ok((data, ep)) => (
+                self.handle_datagram(context, transfers, data, ep, now, &mut reply),
+                ep,
+            ),
+            Err(Error::Exhausted) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        drop(socket);
+        let udp_handle = self.udp_handle.unwrap_or(handle);
+
+        // A preempted client is told through the transfer ID it was using
+        let mut preempted = 0;
+        if let Some((victim, victim_tid)) = self.preempted.take() {
+            let mut socket = sockets.get::<UdpSocket>(self.socket_of(victim_tid, udp_handle));
+            self.notify_preempted(&mut *socket, victim)?;
+            preempted = 1;
+        }
+
+        // Replies to the client of a transfer always come from its transfer ID,
+        // starting from the one accepting a request
+        let reply_tid = tid.or_else(|| tid_of(transfers, ep));
+        let mut socket = sockets.get::<UdpSocket>(self.socket_of(reply_tid, udp_handle));
+        reply.flush(&mut *socket)?;
+        res.map(|_| Some(preempted + usize::from(reply.endpoint.is_some())))
+    }
+
+    /// Picks a free socket from the transfer ID pool, if the pool is in use.
+    ///
+    /// Returns `Err(())` if all the sockets of the pool are taken.
+    fn allocate_tid<H>(&self, transfers: &[Option<Transfer<H>>]) -> Result<Option<usize>, ()> {
+        if self.tid_pool_size() == 0 {
+            return Ok(None);
+        }
+        self.tid_sockets
+            .iter()
+            .enumerate()
+            .filter(|(_, handle)| handle.is_some())
+            .map(|(tid, _)| tid)
+            .find(|tid| transfers.iter().flatten().all(|x| x.tid != Some(*tid)))
+            .map(Some)
+            .ok_or(())
+    }
+
+    fn handle_datagram<'a, C, T>(
+        &mut self,
+        context: &mut C,
+        transfers: &mut Transfers<'a, C::Handle>,
+        data: &[u8],
+        ep: IpEndpoint,
+        now: Instant,
+        socket: &mut T,
+    ) -> net::Result<()>
+    where
+        C: Context,
+        T: Transmit,
+    {
+        net_trace_bytes!("tftp: recv", data);
+
+        #[cfg(feature = "metrics")]
+        let packet_len = data.len();
+
+        // Validate packet length and contents
+        let tftp_packet = Packet::new_unchecked(data);
+        let tftp_repr = match tftp_packet
+            .check_len_verbose(self.parse_mode)
+            .and_then(|_| Repr::parse_verbose(&tftp_packet, self.parse_mode))
+        {
+            Ok(tftp_repr) => tftp_repr,
+            Err(e) => {
+                net_debug!("tftp: invalid packet from {}: {:?}", ep, e);
+                self.stats.malformed += 1;
+                return self.send_repr(&mut *socket, ep, &Repr::error_from_net(e.into()));
+            }
+        };
+
+        // Retrieve the index of the transfer associated to the remote endpoint
+        let xfer_idx = transfers.iter_mut().position(|xfer| {
+            if let Some(xfer) = xfer {
+                if xfer.ep == ep {
+                    return true;
+                }
+            }
+            false
+        });
+
+        let is_write = tftp_packet.opcode() == OpCode::Write;
+
+        match (tftp_repr, xfer_idx) {
+            (Repr::ReadRequest { .. }, Some(_)) | (Repr::WriteRequest { .. }, Some(_)) => {
+                // Multiple connections from the same host are not supported
+                net_debug!("tftp: multiple connection attempts from {}", ep);
+
+                return self.relay_error(
+                    &mut *socket,
+                    ep,
+                    ErrorCode::AccessViolation,
+                    "Multiple connections not supported",
+                );
+            }
+            (
+                Repr::ReadRequest {
+                    filename,
+                    mode,
+                    options,
+                },
+                None,
+            )
+            | (
+                Repr::WriteRequest {
+                    filename,
+                    mode,
+                    options,
+                },
+                None,
+            ) => {
+                if self.paused {
+                    net_debug!("tftp: paused, turning away request from {}", ep);
+                    return self.reject_busy(&mut *socket, ep);
+                }
+
+                // Legacy clients may name octet mode after one of its obsolete aliases
+                let alias = match mode {
+                    Mode::Unknown if self.octet_aliases => {
+                        Mode::octet_alias(tftp_packet.mode_str())
+                    }
+                    _ => None,
+                };
+                let mode = alias.map_or(mode, |_| Mode::Octet);
+
+                if mode != Mode::Octet {
+                    return self.relay_error(
+                        &mut *socket,
+                        ep,
+                        ErrorCode::IllegalOperation,
+                        "Only octet mode is supported",
+                    );
+                }
+                let spelling = alias.unwrap_or_else(|| mode.as_str());
+                if self.mode_case_sensitive && tftp_packet.mode_str() != spelling.as_bytes() {
+                    return self.relay_error(
+                        &mut *socket,
+                        ep,
+                        ErrorCode::IllegalOperation,
+                        "Mode must be lowercase",
+                    );
+                }
+
+                // Let the context reject the request before committing any resource
+                if let Err(code) = context.accept(filename, is_write) {
+                    net_debug!("tftp: request from {} rejected by context", ep);
+                    return self.relay_error(&mut *socket, ep, code, "Request rejected");
+                }
+
+                // Negotiate the block size, taking the link MTU into account
+                let block_size = match self.negotiate_block_size(options.block_size) {
+                    Ok(block_size) => block_size,
+                    Err(msg) => {
+                        return self.relay_error(
+                            &mut *socket,
+                            ep,
+                            ErrorCode::IllegalOperation,
+                            msg,
+                        );
+                    }
+                };
+
+                // Reject writes that are known in advance to exceed the limit
+                if is_write && self.exceeds_write_limit(options.transfer_size) {
+                    return self.relay_error(
+                        &mut *socket,
+                        ep,
+                        ErrorCode::DiskFull,
+                        "Transfer size limit exceeded",
+                    );
+                }
+
+                // Find the first free transfer available, or allocate one if possible
+                let opt_idx = match transfers.iter().position(|t| t.is_none()) {
+                    Some(idx) => Some(idx),
+                    None => match transfers {
+                        ManagedSlice::Borrowed(_) => None,
+                        #[cfg(feature = "std")]
+                        ManagedSlice::Owned(v) => {
+                            let idx = v.len();
+                            v.push(None);
+                            Some(idx)
+                        }
+                    },
+                };
+
+                // Take over the transfer from a dedicated socket, if possible
+                let tid = match opt_idx {
+                    Some(_) => self.allocate_tid(transfers),
+                    None => Err(()),
+                };
+
+                // Otherwise, pick a transfer of lower priority to make room, which is only
+                // preempted once the file is open, so that bad requests never abort anything
+                let slot = match (opt_idx, tid) {
+                    (Some(idx), Ok(tid)) => Ok((idx, tid)),
+                    _ => match self.preemption_victim(context, transfers, filename, ep) {
+                        Some(victim) => Err(victim),
+                        None => {
+                            let exhausted = match opt_idx {
+                                Some(_) => "transfer IDs",
+                                None => "connections",
+                            };
+                            net_debug!("tftp: {} exhausted", exhausted);
+                            return self.reject_busy(&mut *socket, ep);
+                        }
+                    },
+                };
+
+                // Try resuming the write, if requested
+                let resumed = match options.offset {
+                    Some(offset) if is_write => context
+                        .open_append(filename, offset)
+                        .ok()
+                        .map(|handle| (handle, offset)),
+                    _ => None,
+                };
+                let offset = resumed.as_ref().map(|(_, offset)| *offset);
+
+                // Open file handle
+                let opened = match resumed {
+                    Some((handle, _)) => Ok(handle),
+                    None if is_write && !self.overwrite && context.exists(filename) => {
+                        net_debug!("tftp: refusing to overwrite existing file");
+                        return self.relay_error(
+                            &mut *socket,
+                            ep,
+                            ErrorCode::FileExists,
+                            "File already exists",
+                        );
+                    }
+                    None => context.open(filename, is_write),
+                };
+                let handle = match opened {
+                    Ok(handle) => handle,
+                    Err(_) => {
+                        net_debug!("tftp: unable to open requested file");
+                        return self.relay_error(
+                            &mut *socket,
+                            ep,
+                            ErrorCode::FileNotFound,
+                            "Unable to open requested file",
+                        );
+                    }
+                };
+
+                let (idx, tid) = match slot {
+                    Ok(slot) => slot,
+                    Err(victim) => {
+                        // Its client is told once the reply to this request is out
+                        self.preempted = transfers[victim].as_ref().map(|xfer| (xfer.ep, xfer.tid));
+                        self.close_transfer(context, &mut transfers[victim]);
+                        // The transfer ID of the victim, if any, is now free
+                        (victim, self.allocate_tid(transfers).unwrap_or(None))
+                    }
+                };
+
+                // Only acknowledge the options we understood, answering `tsize` queries
+                // of reads with the actual size of the file
+                let transfer_size = match options.transfer_size {
+                    Some(_) if !is_write => handle.size(),
+                    transfer_size => transfer_size,
+                };
+                let options = Options {
+                    block_size: options.block_size.map(|_| block_size as u16),
+                    transfer_size,
+                    offset,
+                };
+
+                // Allocate new transfer
+                let mut xfer = Transfer {
+                    handle,
+                    ep,
+                    is_write,
+                    // A read transfer starts with the client acknowledging the OACK
+                    block_num: if is_write || options.is_empty() { 1 } else { 0 },
+                    block_size,
+                    options,
+                    awaiting_oack_ack: !options.is_empty(),
+                    transferred: offset.unwrap_or(0) as usize,
+                    unflushed: 0,
+                    blocks: 0,
+                    priority: context.priority(ep, filename),
+                    #[cfg(feature = "metrics")]
+                    packet_stats: PacketStats {
+                        max_sent: 0,
+                        max_received: packet_len,
+                    },
+                    last_data: None,
+                    last_len: 0,
+                    deferred_len: None,
+                    #[cfg(feature = "read-ahead")]
+                    read_ahead: self.read_ahead && !is_write,
+                    #[cfg(feature = "read-ahead")]
+                    next_data: None,
+                    #[cfg(feature = "read-ahead")]
+                    next_read: None,
+                    retries: 0,
+                    timeout: now + RETRY_TIMEOUT,
+                    tid,
+                    leak_guard: LeakGuard { ep, armed: true },
+                };
+
+                net_debug!(
+                    "tftp: {} request from {}",
+                    if is_write { "write" } else { "read" },
+                    ep
+                );
+
+                if is_write {
+                    self.stats.writes += 1;
+                } else {
+                    self.stats.reads += 1;
+                }
+
+                let failed = if !options.is_empty() {
+                    xfer.send_oack(&mut *socket).map(|_| false)
+                } else if is_write {
+                    xfer.send_ack(&mut *socket, 0).map(|_| false)
+                } else {
+                    xfer.send_data(&mut *socket)
+                };
+
+                // The transfer is not enqueued yet, so it must be released here,
+                // discarding whatever a write may have left behind
+                match failed {
+                    Ok(false) => (),
+                    Ok(true) => {
+                        self.stats.errors_sent += 1;
+                        self.release_transfer(context, &mut Some(xfer), false);
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        self.release_transfer(context, &mut Some(xfer), false);
+                        return Err(e);
+                    }
+                }
+
+                // Enque transfer
+                transfers[idx] = Some(xfer);
+            }
+            (Repr::Ack { block_num }, None) if self.is_dallying(ep, block_num, now) => {
+                // The client didn't get our final block in time, and acknowledged it again
+                net_debug!("tftp: absorbing duplicate final ack from {}", ep);
+            }
+            (Repr::Data { .. }, None) | (Repr::Ack { .. }, None) => {
+                // Data request on unconnected socket
+                return self.relay_error(
+                    &mut *socket,
+                    ep,
+                    ErrorCode::AccessViolation,
+                    "Data packet without active transfer",
+                );
+            }
+            (Repr::Data { block_num, data }, Some(idx)) => {
+                let xfer = transfers[idx].as_mut().unwrap();
+
+                #[cfg(feature = "metrics")]
+                xfer.packet_stats.record_received(packet_len);
+
+                // Reset retransmission counter
+                xfer.timeout = now + RETRY_TIMEOUT;
+                xfer.retries = 0;
+
+                // Cap the packets the client can send, duplicates included
+                xfer.blocks += 1;
+                if self.exceeds_block_limit(xfer.blocks) {
+                    self.relay_error(
+                        &mut *socket,
+                        ep,
+                        ErrorCode::Undefined,
+                        "Transfer block limit exceeded",
+                    )?;
+                    self.close_transfer(context, &mut transfers[idx]);
+                    return Ok(());
+                }
+
+                // Only DATA #1 of a write transfer can acknowledge the OACK
+                if xfer.awaiting_oack_ack {
+                    if !xfer.is_write || block_num != 1 {
+                        self.relay_error(
+                            &mut *socket,
+                            ep,
+                            ErrorCode::IllegalOperation,
+                            "Options not acknowledged",
+                        )?;
+                        self.close_transfer(context, &mut transfers[idx]);
+                        return Ok(());
+                    }
+                    xfer.awaiting_oack_ack = false;
+                }
+
+                // Make sure this is a write connection
+                if !xfer.is_write {
+                    return self.relay_error(
+                        &mut *socket,
+                        ep,
+                        ErrorCode::AccessViolation,
+                        "Not a write connection",
+                    );
+                }
+
+                // Duplicate or unexpected packet, re-ACK the last good block.
+                // Block numbers wrap around, so the one before block 0 is 65535.
+                if block_num != xfer.block_num {
+                    return xfer.send_ack(&mut *socket, xfer.block_num.wrapping_sub(1));
+                }
+
+                // Enforce the write limit before touching the file
+                let transferred = xfer.transferred + data.len();
+                if self.exceeds_write_limit(Some(transferred as u64)) {
+                    self.relay_error(
+                        &mut *socket,
+                        ep,
+                        ErrorCode::DiskFull,
+                        "Transfer size limit exceeded",
+                    )?;
+                    self.close_transfer(context, &mut transfers[idx]);
+                    return Ok(());
+                }
+
+                // Retransmissions of a deferred block are answered once it is written
+                if xfer.deferred_len.is_some() {
+                    return Ok(());
+                }
+
+                // Keep a copy of the block if the handle can't take it yet, without ACKing it
+                if !xfer.handle.is_writable() {
+                    net_trace!("tftp: deferring block {}", block_num);
+                    xfer.defer(data);
+                    return Ok(());
+                }
+
+                self.write_data(context, &mut transfers[idx], data, socket)?;
+            }
+            (Repr::Ack { block_num }, Some(idx)) => {
+                let xfer = transfers[idx].as_mut().unwrap();
+
+                #[cfg(feature = "metrics")]
+                xfer.packet_stats.record_received(packet_len);
+
+                // Reset retransmission counter
+                xfer.timeout = now + RETRY_TIMEOUT;
+                xfer.retries = 0;
+
+                // Cap the packets the client can send, duplicates included
+                xfer.blocks += 1;
+                if self.exceeds_block_limit(xfer.blocks) {
+                    self.relay_error(
+                        &mut *socket,
+                        ep,
+                        ErrorCode::Undefined,
+                        "Transfer block limit exceeded",
+                    )?;
+                    self.close_transfer(context, &mut transfers[idx]);
+                    return Ok(());
+                }
+
+                // Only ACK #0 of a read transfer can acknowledge the OACK
+                let oack_acked = xfer.awaiting_oack_ack;
+                if oack_acked {
+                    if xfer.is_write || block_num != 0 {
+                        self.relay_error(
+                            &mut *socket,
+                            ep,
+                            ErrorCode::IllegalOperation,
+                            "Options not acknowledged",
+                        )?;
+                        self.close_transfer(context, &mut transfers[idx]);
+                        return Ok(());
+                    }
+                    xfer.awaiting_oack_ack = false;
+                }
+
+                // Make sure this is a read connection
+                if xfer.is_write {
+                    return self.relay_error(
+                        &mut *socket,
+                        ep,
+                        ErrorCode::AccessViolation,
+                        "Not a read connection",
+                    );
+                }
+
+                // Unexpected ACK, resend previous block
+                if block_num != xfer.block_num {
+                    return xfer.resend_data(&mut *socket);
+                }
+
+                // Update block number
+                xfer.block_num = xfer.block_num.wrapping_add(1);
+
+                if oack_acked || !xfer.is_final_block(xfer.last_len) {
+                    if xfer.send_data(&mut *socket)? {
+                        self.stats.errors_sent += 1;
+                        self.close_transfer(context, &mut transfers[idx]);
+                    }
+                } else {
+                    self.dally(ep, block_num, now);
+                    self.complete_transfer(context, &mut transfers[idx]);
+                }
+            }
+            (Repr::Error { code, .. }, Some(idx)) => {
+                // The client terminated the transfer, e.g. after a size-only probe.
+                // Errors are not acknowledged, as per RFC 1350.
+                net_debug!("tftp: transfer aborted by {}: {:?}", ep, code);
+                self.close_transfer(context, &mut transfers[idx]);
+            }
+            (Repr::Error { .. }, None) | (Repr::OptionAck { .. }, _) => {
+                return self.send_repr(
+                    &mut *socket,
+                    ep,
+                    &Repr::error_from_net(Error::Unrecognized),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_timeouts<'a, C, T>(
+        &mut self,
+        context: &mut C,
+        transfers: &mut Transfers<'a, C::Handle>,
+        now: Instant,
+        socket: &mut T,
+    ) -> net::Result<()>
+    where
+        C: Context,
+        T: Transmit,
+    {
+        for xfer in transfers.iter_mut() {
+            self.handle_timeout(context, xfer, now, &mut *socket)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the next data block of a write transfer, then acknowledges it and completes
+    /// the transfer if it was the last one.
+    fn write_data<C, T>(
+        &mut self,
+        context: &mut C,
+        slot: &mut Option<Transfer<C::Handle>>,
+        data: &[u8],
+        socket: &mut T,
+    ) -> net::Result<()>
+    where
+        C: Context,
+        T: Transmit,
+    {
+        let xfer = match slot {
+            Some(xfer) => xfer,
+            None => return Ok(()),
+        };
+
+        // Update block number
+        let block_num = xfer.block_num;
+        xfer.block_num = xfer.block_num.wrapping_add(1);
+        xfer.transferred += data.len();
+
+        // Write data to the destination file
+        match xfer.write_block(block_num, data, self.flush_interval) {
+            Ok(_) => {
+                let last_block = xfer.is_final_block(data.len());
+
+                // Send ACK and optionally close the transfer
+                xfer.send_ack(&mut *socket, block_num)?;
+                if last_block {
+                    self.complete_transfer(context, slot);
+                }
+            }
+            Err(_) => {
+                let ep = xfer.ep;
+                self.relay_error(
+                    &mut *socket,
+                    ep,
+                    ErrorCode::AccessViolation,
+                    "Error writing file",
+                )?;
+                self.close_transfer(context, slot);
+            }
+        }
+        Ok(())
+    }
+
+    /// Retransmits the last packet of a transfer that timed out through `socket`,
+    /// dropping the transfer if it exhausted its retries.
+    ///
+    /// A block deferred by a write transfer is written first, as soon as the handle accepts it.
+    fn handle_timeout<C, T>(
+        &mut self,
+        context: &mut C,
+        xfer: &mut Option<Transfer<C::Handle>>,
+        now: Instant,
+        socket: &mut T,
+    ) -> net::Result<()>
+    where
+        C: Context,
+        T: Transmit,
+    {
+        if !socket.can_send() {
+            return Ok(());
+        }
+
+        if let Some((data, len)) = xfer.as_mut().and_then(Transfer::take_deferred) {
+            return self.write_data(context, xfer, &data[..len], socket);
+        }
+
+        let do_drop = match xfer {
+            Some(xfer) => xfer.process_timeout(&mut *socket, now)?,
+            None => false,
+        };
+        if do_drop {
+            self.stats.timeouts += 1;
+            self.close_transfer(context, xfer);
+        }
+        Ok(())
+    }
+
+    /// Computes the block size of a new transfer, given the one requested by the client.
+    ///
+    /// Requested sizes are only ever negotiated downwards, to the default block size
+    /// or to what the link MTU allows, whichever is smaller.
+    fn negotiate_block_size(&self, requested: Option<u16>) -> Result<usize, &'static str> {
+        let max = self.max_block_size();
+
+        if max < MIN_BLOCK_SIZE {
+            return Err("Link MTU too small");
+        }
+
+        match requested.map(usize::from) {
+            Some(req) if req >= MIN_BLOCK_SIZE => Ok(req.min(max)),
+            _ if max < DEFAULT_BLOCK_SIZE => Err("Link MTU requires the blksize option"),
+            _ => Ok(DEFAULT_BLOCK_SIZE),
+        }
+    }
+
+    /// Returns the largest block size that can be negotiated.
+    fn max_block_size(&self) -> usize {
+        match self.mtu {
+            Some(mtu) => MAX_BLOCK_SIZE.min(mtu.saturating_sub(DATA_HEADER_LEN)),
+            None => MAX_BLOCK_SIZE,
+        }
+    }
+
+    /// Picks the transfer to preempt for a request for `filename` from `ep`, once all
+    /// the transfer slots or transfer IDs are taken.
+    ///
+    /// This is the active transfer of lowest priority, if lower than the one of the request.
+    fn preemption_victim<C>(
+        &self,
+        context: &C,
+        transfers: &Transfers<C::Handle>,
+        filename: &str,
+        ep: IpEndpoint,
+    ) -> Option<usize>
+    where
+        C: Context,
+    {
+        let priority = context.priority(ep, filename);
+        transfers
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, xfer)| xfer.as_ref().map(|xfer| (idx, xfer.priority)))
+            .filter(|(_, victim)| *victim < priority)
+            .min_by_key(|(_, victim)| *victim)
+            .map(|(idx, _)| idx)
+    }
+
+    /// Lets the client of a transfer preempted by a request of higher priority know.
+    fn notify_preempted(&mut self, socket: &mut impl Transmit, ep: IpEndpoint) -> net::Result<()> {
+        net_debug!("tftp: preempted transfer with {}", ep);
+        self.relay_error(
+            socket,
+            ep,
+            ErrorCode::Undefined,
+            "Preempted by a higher priority transfer",
+        )
+    }
+
+    /// Relays an error to the client.
+    fn relay_error(
+        &mut self,
+        socket: &mut impl Transmit,
+        ep: IpEndpoint,
+        code: ErrorCode,
+        msg: &str,
+    ) -> net::Result<()> {
+        self.send_repr(socket, ep, &Repr::Error { code, msg })
+    }
+
+    /// Sends a packet to the client, keeping track of the errors sent.
+    fn send_repr(
+        &mut self,
+        socket: &mut impl Transmit,
+        ep: IpEndpoint,
+        repr: &Repr,
+    ) -> net::Result<()> {
+        if let Repr::Error { .. } = repr {
+            self.stats.errors_sent += 1;
+        }
+        send_repr(socket, ep, repr)
+    }
+
+    /// Returns `true` if having received `blocks` packets from a client exceeds the configured
+    /// block limit.
+    fn exceeds_block_limit(&self, blocks: usize) -> bool {
+        match self.max_blocks {
+            Some(max) => blocks > max,
+            None => false,
+        }
+    }
+
+    /// Returns `true` if writing `size` bytes would exceed the configured write limit.
+    fn exceeds_write_limit(&self, size: Option<u64>) -> bool {
+        match (size, self.max_write_bytes) {
+            (Some(size), Some(max)) => size > max as u64,
+            _ => false,
+        }
+    }
+
+    /// Remembers the final block of a read transfer that just completed, so that
+    /// duplicates of its ACK are absorbed for a while rather than answered with an error.
+    ///
+    /// This is the "dallying" of RFC 1350, from the side receiving the final ACK. The slot
+    /// expiring first is reused when all of them are taken.
+    fn dally(&mut self, ep: IpEndpoint, block_num: u16, now: Instant) {
+        let slot = self
+            .dallying
+            .iter_mut()
+            .min_by_key(|slot| match slot {
+                Some((_, _, until)) if *until > now => Some(*until),
+                _ => None,
+            })
+            .unwrap();
+        *slot = Some((ep, block_num, now + DALLY_DURATION));
+    }
+
+    /// Returns `true` if `block_num` is the final block of a read transfer with `ep`
+    /// that completed recently.
+    fn is_dallying(&self, ep: IpEndpoint, block_num: u16, now: Instant) -> bool {
+        self.dallying
+            .iter()
+            .flatten()
+            .any(|(e, b, until)| *e == ep && *b == block_num && *until > now)
+    }
+
+    /// Returns `true` if `data` is a duplicate ACK of a final block for which `ep` is dallying.
+    fn is_dallying_ack(&self, data: &[u8], ep: IpEndpoint, now: Instant) -> bool {
+        match Packet::new_checked(data) {
+            Ok(packet) if packet.opcode() == OpCode::Ack => {
+                self.is_dallying(ep, packet.block_number(), now)
+            }
+            _ => false,
+        }
+    }
+
+    /// Terminates a transfer that completed successfully, committing written files.
+    fn complete_transfer<C>(&mut self, context: &mut C, xfer: &mut Option<Transfer<C::Handle>>)
+    where
+        C: Context,
+    {
+        self.release_transfer(context, xfer, true);
+    }
+
+    /// Aborts a transfer, discarding written files.
+    fn close_transfer<C>(&mut self, context: &mut C, xfer: &mut Option<Transfer<C::Handle>>)
+    where
+        C: Context,
+    {
+        self.release_transfer(context, xfer, false);
+    }
+
+    /// Terminates a transfer, releasing the handle and freeing up the transfer slot.
+    fn release_transfer<C>(
+        &mut self,
+        context: &mut C,
+        xfer: &mut Option<Transfer<C::Handle>>,
+        completed: bool,
+    ) where
+        C: Context,
+    {
+        if let Some(xfer) = xfer.take() {
+            net_debug!("tftp: closing {}", xfer.ep);
+            #[cfg(feature = "metrics")]
+            self.packet_stats.merge(xfer.packet_stats);
+            match (xfer.is_write, completed) {
+                (false, _) => context.close(xfer.into_handle()),
+                (true, true) => context.commit(xfer.into_handle()),
+                (true, false) => context.discard(xfer.into_handle()),
+            }
+        }
+    }
+}
+
+/// Holds the packet sent in reply to a received one, until it can be moved to the socket.
+struct Reply {
+    buffer: [u8; MAX_REPLY_LEN],
+    len: usize,
+    endpoint: Option<IpEndpoint>,
+}
+
+impl Reply {
+    fn new() -> Self {
+        Reply {
+            buffer: [0; MAX_REPLY_LEN],
+            len: 0,
+            endpoint: None,
+        }
+    }
+
+    fn flush(&self, socket: &mut UdpSocket) -> net::Result<()> {
+        match self.endpoint {
+            Some(endpoint) => socket.send_slice(&self.buffer[..self.len], endpoint),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Transmit for Reply {
+    fn send(&mut self, size: usize, endpoint: IpEndpoint) -> net::Result<&mut [u8]> {
+        if self.endpoint.is_some() || size > MAX_REPLY_LEN {
+            return Err(Error::Exhausted);
+        }
+        self.len = size;
+        self.endpoint = Some(endpoint);
+        Ok(&mut self.buffer[..size])
+    }
+}
+
+/// Counters of the activity of a [`Server`].
+///
+/// [`Server`]: struct.Server.html
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ServerStats {
+    /// Number of read requests accepted.
+    pub reads: u64,
+    /// Number of write requests accepted.
+    pub writes: u64,
+    /// Number of error packets sent.
+    pub errors_sent: u64,
+    /// Number of transfers dropped after exhausting all retransmissions.
+    pub timeouts: u64,
+    /// Number of truncated or malformed packets received.
+    pub malformed: u64,
+    /// Number of requests turned away because all the transfers were in use,
+    /// or the server was paused.
+    pub busy: u64,
+}
+
+/// The effective configuration of a [`Server`], as returned by `Server::describe()`.
+///
+/// Whether files can be written at all is up to `Context::accept()`, so there is no
+/// read-only flag here.
+///
+/// [`Server`]: struct.Server.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// Port on which requests are received.
+    pub port: u16,
+    /// Whether the server is attached to a socket, rather than driven with raw datagrams.
+    pub attached: bool,
+    /// Number of sockets in the transfer ID pool, see `Server::with_tid_pool()`.
+    pub tid_pool_size: usize,
+    /// Number of transfers that can be carried out at once.
+    pub transfer_capacity: usize,
+    /// Interval between retransmissions.
+    pub retry_timeout: Duration,
+    /// Number of retransmissions before a transfer is dropped.
+    pub max_retries: u8,
+    /// Largest block size that can be negotiated, given the MTU.
+    pub max_block_size: usize,
+    /// See `Server::set_mtu()`.
+    pub mtu: Option<usize>,
+    /// See `Server::set_max_write_bytes()`.
+    pub max_write_bytes: Option<usize>,
+    /// See `Server::set_max_blocks()`.
+    pub max_blocks: Option<usize>,
+    /// See `Server::set_parse_mode()`.
+    pub parse_mode: ParseMode,
+    /// See `Server::set_overwrite()`.
+    pub overwrite: bool,
+    /// See `Server::set_mode_case_sensitive()`.
+    pub mode_case_sensitive: bool,
+    /// See `Server::set_octet_aliases()`.
+    pub octet_aliases: bool,
+    /// See `Server::set_flush_interval()`.
+    pub flush_interval: Option<usize>,
+    /// See `Server::set_serve_budget()`.
+    pub serve_budget: usize,
+    /// See `Server::set_busy_retry_after()`.
+    pub busy_retry_after: Option<Duration>,
+    /// See `Server::set_read_ahead()`.
+    #[cfg(feature = "read-ahead")]
+    pub read_ahead: bool,
+    /// See `Server::pause()`.
+    pub paused: bool,
+}
+
+/// Sizes of the largest packets exchanged, useful to right-size the socket buffers.
+///
+/// The reported lengths refer to the UDP payload, i.e. the TFTP packet itself.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PacketStats {
+    /// Length of the largest packet sent, in bytes.
+    pub max_sent: usize,
+    /// Length of the largest packet received, in bytes.
+    pub max_received: usize,
+}
+
+#[cfg(feature = "metrics")]
+impl PacketStats {
+    fn record_sent(&mut self, len: usize) {
+        self.max_sent = self.max_sent.max(len);
+    }
+
+    fn record_received(&mut self, len: usize) {
+        self.max_received = self.max_received.max(len);
+    }
+
+    fn merge(&mut self, other: PacketStats) {
+        self.record_sent(other.max_sent);
+        self.record_received(other.max_received);
+    }
+}
+
+/// The set of transfer slots managed by [`Server::serve()`].
+///
+/// [`Server::serve()`]: struct.Server.html#method.serve
+pub type Transfers<'a, H> = ManagedSlice<'a, Option<Transfer<H>>>;
+
+/// Fixed-capacity storage for `N` transfer slots.
+pub type TransferPool<H, const N: usize> = [Option<Transfer<H>>; N];
+
+/// Returns storage for `N` free transfer slots, suitable for `no_std` environments.
+///
+/// # Usage
+///
+/// ```rust
+/// use smolapps::tftp::{self, Context, Handle, Server, Transfers};
+/// use smolapps::net::socket::{SocketSet, UdpSocketBuffer, UdpPacketMetadata};
+/// use smolapps::net::time::Instant;
+///
+/// struct NoFiles;
+/// struct NoFile;
+///
+/// impl Context for NoFiles {
+///     type Handle = NoFile;
+///
+///     fn open(&mut self, _: &str, _: bool) -> Result<NoFile, ()> { Err(()) }
+///     fn close(&mut self, _: NoFile) {}
+/// }
+///
+/// impl Handle for NoFile {
+///     fn read(&mut self, _: &mut [u8]) -> Result<usize, ()> { Err(()) }
+///     fn write(&mut self, _: &[u8]) -> Result<usize, ()> { Err(()) }
+/// }
+///
+/// let mut sockets_entries: [_; 1] = Default::default();
+/// let mut sockets = SocketSet::new(&mut sockets_entries[..]);
+///
+/// let mut tftp_rx_storage: [u8; 1048] = [0; 1048];
+/// let mut tftp_rx_metadata: [_; 2] = [UdpPacketMetadata::EMPTY; 2];
+///
+/// let mut tftp_tx_storage: [u8; 1048] = [0; 1048];
+/// let mut tftp_tx_metadata: [_; 2] = [UdpPacketMetadata::EMPTY; 2];
+///
+/// let mut tftp = Server::new(
+///     &mut sockets,
+///     UdpSocketBuffer::new(&mut tftp_rx_metadata[..], &mut tftp_rx_storage[..]),
+///     UdpSocketBuffer::new(&mut tftp_tx_metadata[..], &mut tftp_tx_storage[..]),
+///     Instant::from_secs(0),
+/// );
+///
+/// let mut pool = tftp::transfers::<NoFile, 4>();
+/// let mut transfers = Transfers::from(&mut pool[..]);
+///
+/// tftp.serve(&mut sockets, &mut NoFiles, &mut transfers, Instant::from_secs(0)).unwrap();
+/// assert_eq!(transfers.len(), 4);
+/// ```
+pub fn transfers<H, const N: usize>() -> TransferPool<H, N> {
+    [Transfer::FREE; N]
+}
+
+/// An active TFTP transfer.
+pub struct Transfer<H> {
+    handle: H,
+    ep: IpEndpoint,
+
+    is_write: bool,
+    block_num: u16,
+    block_size: usize,
+    // Options acknowledged to the client, if any
+    options: Options,
+    // Whether the client has yet to confirm the OACK, with ACK #0 for reads
+    // and with DATA #1 for writes
+    awaiting_oack_ack: bool,
+    // Number of data bytes transferred so far
+    transferred: usize,
+    // Number of blocks written since the handle was last flushed
+    unflushed: usize,
+    // Number of DATA or ACK packets received from the client
+    blocks: usize,
+    // Priority given by the context to the request, see `Context::priority()`
+    priority: u8,
+    #[cfg(feature = "metrics")]
+    packet_stats: PacketStats,
+    // Last block sent by read transfers, or the block deferred by write transfers
+    // FIXME: I'd reeeally love to avoid a potential stack allocation this big :\
+    last_data: Option<[u8; MAX_BLOCK_SIZE]>,
+    last_len: usize,
+    // Length of the block held in `last_data` until the handle becomes writable, if any
+    deferred_len: Option<usize>,
+    // Whether blocks are read ahead, and the outcome of reading the next one into `next_data`
+    #[cfg(feature = "read-ahead")]
+    read_ahead: bool,
+    #[cfg(feature = "read-ahead")]
+    next_data: Option<[u8; MAX_BLOCK_SIZE]>,
+    #[cfg(feature = "read-ahead")]
+    next_read: Option<Result<usize, ()>>,
+
+    retries: u8,
+    timeout: Instant,
+    // Index of the socket of the transfer ID pool carrying the transfer, if any
+    tid: Option<usize>,
+    leak_guard: LeakGuard,
+}
+
+impl<H> Transfer<H> {
+    /// A free transfer slot.
+    const FREE: Option<Transfer<H>> = None;
+
+    /// Consumes the transfer, returning its handle so that it can be released.
+    fn into_handle(mut self) -> H {
+        self.leak_guard.armed = false;
+        self.handle
+    }
+
+    /// Returns the endpoint of the client.
+    pub fn endpoint(&self) -> IpEndpoint {
+        self.ep
+    }
+
+    /// Returns `true` if the client is writing a file, `false` if it is reading one.
+    pub fn is_write(&self) -> bool {
+        self.is_write
+    }
+
+    /// Returns the number of data bytes carried by each DATA packet.
+    ///
+    /// This is the `blksize` acknowledged to the client, or `DEFAULT_BLOCK_SIZE` if it wasn't
+    /// negotiated.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Returns the total size of the file, if it was negotiated through the `tsize` option.
+    ///
+    /// For writes, this is the size announced by the client. For reads, this is the size
+    /// reported by `Handle::size()`.
+    pub fn transfer_size(&self) -> Option<u64> {
+        self.options.transfer_size
+    }
+
+    /// Returns the sizes of the largest packets exchanged by this transfer so far.
+    #[cfg(feature = "metrics")]
+    pub fn packet_stats(&self) -> PacketStats {
+        self.packet_stats
+    }
+}
+
+/// Warns when an active transfer is dropped, since its handle never makes it back to the context.
+///
+/// The warning is only logged in debug builds.
+struct LeakGuard {
+    ep: IpEndpoint,
+    armed: bool,
+}
+
+impl Drop for LeakGuard {
+    fn drop(&mut self) {
+        if cfg!(debug_assertions) && self.armed {
+            net_warn!(
+                "tftp: transfer with {} dropped while active, its handle is leaked",
+                self.ep
+            );
+        }
+    }
+}
+
+impl<H> Transfer<H>
+where
+    H: Handle,
+{
+    /// Returns `true` if a block of `len` bytes ends the transfer, given the negotiated block size.
+    fn is_final_block(&self, len: usize) -> bool {
+        len < self.block_size
+    }
+
+    fn process_timeout(&mut self, socket: &mut impl Transmit, now: Instant) -> net::Result<bool> {
+        if now < self.timeout {
+            Ok(false)
+        } else if self.retries < MAX_RETRIES {
+            self.retries += 1;
+            self.timeout = now + RETRY_TIMEOUT;
+            self.resend(socket).map(|_| false)
+        } else {
+            net_debug!("tftp: connection timeout");
+            Ok(true)
+        }
+    }
+
+    /// Sends the last packet of the transfer again right away, without waiting for its timeout.
+    ///
+    /// This is the last DATA block of a read transfer, the last ACK of a write transfer,
+    /// or the OACK if the client has yet to confirm it. It lets integrations that detect
+    /// losses by other means, such as a link-layer NACK, recover from them sooner.
+    /// `socket` must be the one carrying the transfer, which `Server::retransmit()` picks
+    /// for attached servers.
+    ///
+    /// Forced retransmissions count towards the retries of the transfer, which is still
+    /// dropped at its next timeout once they are exhausted: `Err(Error::Exhausted)` is then
+    /// returned without sending anything.
+    pub fn retransmit(&mut self, socket: &mut impl Transmit) -> net::Result<()> {
+        if self.retries >= MAX_RETRIES {
+            return Err(Error::Exhausted);
+        }
+        self.retries += 1;
+        if self.is_write && !self.awaiting_oack_ack {
+            self.send_ack(socket, self.block_num.wrapping_sub(1))
+        } else {
+            self.resend(socket)
+        }
+    }
+
+    /// Copies `data` into the transfer, to be written once the handle becomes writable.
+    fn defer(&mut self, data: &[u8]) {
+        let buf = self.last_data.get_or_insert([0; MAX_BLOCK_SIZE]);
+        buf[..data.len()].copy_from_slice(data);
+        self.deferred_len = Some(data.len());
+    }
+
+    /// Returns the deferred data block, if there is one and the handle is now writable.
+    fn take_deferred(&mut self) -> Option<([u8; MAX_BLOCK_SIZE], usize)> {
+        if !self.handle.is_writable() {
+            return None;
+        }
+        let len = self.deferred_len.take()?;
+        self.last_data.take().map(|data| (data, len))
+    }
+
+    /// Writes a data block to the handle, flushing it every `flush_interval` blocks.
+    fn write_block(
+        &mut self,
+        block_num: u16,
+        data: &[u8],
+        flush_interval: Option<usize>,
+    ) -> Result<usize, ()> {
+        let written = self.handle.write_block(block_num, data)?;
+        self.unflushed += 1;
+        match flush_interval {
+            Some(interval) if self.unflushed >= interval => {
+                self.unflushed = 0;
+                self.handle.flush().map(|_| written)
+            }
+            _ => Ok(written),
+        }
+    }
+
+    /// Sends the next data block, returning `true` if the transfer must be terminated
+    /// because of a read error, which has been relayed to the client.
+    fn send_data(&mut self, socket: &mut impl Transmit) -> net::Result<bool> {
+        let read = match self.take_read_ahead() {
+            Some(read) => read,
+            None => {
+                let buf = self.last_data.get_or_insert([0; MAX_BLOCK_SIZE]);
+                self.handle.read(&mut buf[..self.block_size])
+            }
+        };
+        self.last_len = match read {
+            Ok(n) => n,
+            Err(_) => {
+                send_error(
+                    socket,
+                    self.ep,
+                    ErrorCode::AccessViolation,
+                    "Error occurred while reading the file",
+                )?;
+                return Ok(true);
+            }
+        };
+        self.transferred += self.last_len;
+        self.resend_data(socket)?;
+
+        // Read the next block while this one is on its way
+        #[cfg(feature = "read-ahead")]
+        if self.read_ahead && !self.is_final_block(self.last_len) {
+            let buf = self.next_data.get_or_insert([0; MAX_BLOCK_SIZE]);
+            self.next_read = Some(self.handle.read(&mut buf[..self.block_size]));
+        }
+        Ok(false)
+    }
+
+    /// Takes the outcome of reading the next block ahead of time, if it was, along with
+    /// the block itself, swapping it with the buffer of the block before.
+    #[cfg(feature = "read-ahead")]
+    fn take_read_ahead(&mut self) -> Option<Result<usize, ()>> {
+        let read = self.next_read.take()?;
+        core::mem::swap(&mut self.last_data, &mut self.next_data);
+        Some(read)
+    }
+
+    #[cfg(not(feature = "read-ahead"))]
+    fn take_read_ahead(&mut self) -> Option<Result<usize, ()>> {
+        None
+    }
+
+    fn resend(&mut self, socket: &mut impl Transmit) -> net::Result<()> {
+        if self.awaiting_oack_ack {
+            self.send_oack(socket)
+        } else {
+            self.resend_data(socket)
+        }
+    }
+
+    fn resend_data(&mut self, socket: &mut impl Transmit) -> net::Result<()> {
+        if let Some(last_data) = &self.last_data {
+            net_trace!("tftp: sending data block #{}", self.block_num);
+
+            let data = Repr::Data {
+                block_num: self.block_num,
+                data: &last_data[..self.last_len],
+            };
+            let payload = socket.send(data.buffer_len(), self.ep)?;
+            let mut pkt = Packet::new_unchecked(payload);
+            data.emit(&mut pkt)?;
+            net_trace_bytes!("tftp: send", pkt.into_inner());
+
+            #[cfg(feature = "metrics")]
+            self.packet_stats.record_sent(data.buffer_len());
+        }
+        Ok(())
+    }
+
+    fn send_oack(&mut self, socket: &mut impl Transmit) -> net::Result<()> {
+        net_trace!("tftp: sending oack {:?}", self.options);
+
+        let oack = Repr::OptionAck {
+            options: self.options,
+        };
+        #[cfg(feature = "metrics")]
+        self.packet_stats.record_sent(oack.buffer_len());
+
+        let payload = socket.send(oack.buffer_len(), self.ep)?;
+        let mut pkt = Packet::new_unchecked(payload);
+        oack.emit(&mut pkt)?;
+        net_trace_bytes!("tftp: send", pkt.into_inner());
+        Ok(())
+    }
+
+    fn send_ack(&mut self, socket: &mut impl Transmit, block: u16) -> net::Result<()> {
+        net_trace!("tftp: sending ack #{}", block);
+
+        let ack = Repr::Ack { block_num: block };
+        #[cfg(feature = "metrics")]
+        self.packet_stats.record_sent(ack.buffer_len());
+
+        let payload = socket.send(ack.buffer_len(), self.ep)?;
+        let mut pkt = Packet::new_unchecked(payload);
+        ack.emit(&mut pkt)?;
+        net_trace_bytes!("tftp: send", pkt.into_inner());
+        Ok(())
+    }
+}
+
+/// Extracts the suggested retry delay from the message of an error sent by a busy server.
+///
+/// Returns `None` unless `msg` follows the format described in `Server::set_busy_retry_after()`
+/// with a delay that fits a `Duration`.
+pub fn parse_retry_after(msg: &str) -> Option<Duration> {
+    let secs = msg.strip_prefix(BUSY_MESSAGE_PREFIX)?;
+    if secs.is_empty() || !secs.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let secs: u64 = secs.parse().ok()?;
+    secs.checked_mul(1000).map(Duration::from_millis)
+}
+
+/// Writes the message of the error sent to busy clients into `buf`.
+fn busy_message(buf: &mut [u8; BUSY_MESSAGE_LEN], delay: Duration) -> &str {
+    let prefix_len = BUSY_MESSAGE_PREFIX.len();
+    buf[..prefix_len].copy_from_slice(BUSY_MESSAGE_PREFIX.as_bytes());
+
+    // Round up, so that clients never come back too early
+    let millis = delay.total_millis();
+    let mut secs = millis / 1000;
+    if secs == 0 || secs * 1000 < millis {
+        secs += 1;
+    }
+    let mut digits = [0; 20];
+    let mut n = 0;
+    while secs > 0 {
+        digits[n] = b'0' + (secs % 10) as u8;
+        secs /= 10;
+        n += 1;
+    }
+    for (dst, digit) in buf[prefix_len..].iter_mut().zip(digits[..n].iter().rev()) {
+        *dst = *digit;
+    }
+
+    core::str::from_utf8(&buf[..prefix_len + n]).unwrap_or(BUSY_MESSAGE_PREFIX)
+}
+
+fn send_error(
+    socket: &mut impl Transmit,
+    ep: IpEndpoint,
+    code: ErrorCode,
+    msg: &str,
+) -> net::Result<()> {
+    send_repr(socket, ep, &Repr::Error { code, msg })
+}
+
+fn send_repr(socket: &mut impl Transmit, ep: IpEndpoint, repr: &Repr) -> net::Result<()> {
+    if let Repr::Error { code, msg } = repr {
+        net_debug!("tftp: {:?}, message: {}", code, msg);
+    }
+
+    let payload = socket.send(repr.buffer_len(), ep)?;
+    let mut pkt = Packet::new_unchecked(payload);
+    repr.emit(&mut pkt)?;
+    net_trace_bytes!("tftp: send", pkt.into_inner());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::{self, Datagram, Network};
+    use crate::net::wire::Ipv4Address;
+    use std::{
+        boxed::Box,
+        cell::Cell,
+        rc::Rc,
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    };
+
+    /// A flat, in-memory filesystem.
+    #[derive(Default)]
+    pub(crate) struct TestContext {
+        pub files: Vec<(String, Vec<u8>)>,
+        pub rejected: Option<ErrorCode>,
+        pub opened: usize,
+        pub blocks: Vec<u16>,
+        pub resumed: Vec<(String, u64)>,
+        pub committed: Vec<String>,
+        pub discarded: Vec<String>,
+        pub flushes: Vec<usize>,
+        pub busy: Rc<Cell<bool>>,
+        pub priorities: Vec<(IpEndpoint, u8)>,
+        pub unreadable: Vec<String>,
+        pub closed: usize,
+    }
+
+    impl TestContext {
+        pub fn with_file(name: &str, data: Vec<u8>) -> TestContext {
+            TestContext {
+                files: vec![(name.to_string(), data)],
+                ..TestContext::default()
+            }
+        }
+
+        pub fn file(&self, name: &str) -> Option<&[u8]> {
+            self.files
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, d)| &d[..])
+        }
+
+        /// Saves what was written through `handle`, however it's released.
+        fn store(&mut self, handle: TestHandle) {
+            if handle.write_mode {
+                self.files.retain(|(n, _)| *n != handle.name);
+                self.files.push((handle.name, handle.data));
+                self.blocks.extend(handle.blocks);
+                self.flushes.extend(handle.flushes);
+            }
+        }
+    }
+
+    pub(crate) struct TestHandle {
+        name: String,
+        data: Vec<u8>,
+        pos: usize,
+        write_mode: bool,
+        blocks: Vec<u16>,
+        flushes: Vec<usize>,
+        busy: Rc<Cell<bool>>,
+        unreadable: bool,
+    }
+
+    impl Context for TestContext {
+        type Handle = TestHandle;
+
+        fn accept(&self, _filename: &str, _write_mode: bool) -> Result<(), ErrorCode> {
+            self.rejected.map_or(Ok(()), Err)
+        }
+
+        fn priority(&self, ep: IpEndpoint, _filename: &str) -> u8 {
+            self.priorities
+                .iter()
+                .find(|(e, _)| *e == ep)
+                .map_or(0, |(_, priority)| *priority)
+        }
+
+        fn exists(&self, filename: &str) -> bool {
+            self.file(filename).is_some()
+        }
+
+        fn list(&self, entry: &mut dyn FnMut(&str)) {
+            self.files.iter().for_each(|(name, _)| entry(name));
+        }
+
+        fn open(&mut self, filename: &str, write_mode: bool) -> Result<Self::Handle, ()> {
+            self.opened += 1;
+            let data = match (self.file(filename), write_mode) {
+                (Some(data), false) => data.to_vec(),
+                (_, true) => Vec::new(),
+                (None, false) => return Err(()),
+            };
+            Ok(TestHandle {
+                name: filename.to_string(),
+                data,
+                pos: 0,
+                write_mode,
+                blocks: Vec::new(),
+                flushes: Vec::new(),
+                busy: self.busy.clone(),
+                unreadable: self.unreadable.iter().any(|n| n == filename),
+            })
+        }
+
+        fn open_append(&mut self, filename: &str, offset: u64) -> Result<Self::Handle, ()> {
+            let mut handle = self.open(filename, false)?;
+            if handle.data.len() < offset as usize {
+                return Err(());
+            }
+            handle.data.truncate(offset as usize);
+            handle.write_mode = true;
+            self.resumed.push((filename.to_string(), offset));
+            Ok(handle)
+        }
+
+        fn close(&mut self, handle: Self::Handle) {
+            self.closed += 1;
+            self.store(handle);
+        }
+
+        fn commit(&mut self, handle: Self::Handle) {
+            self.committed.push(handle.name.clone());
+            self.store(handle);
+        }
+
+        fn discard(&mut self, handle: Self::Handle) {
+            self.discarded.push(handle.name.clone());
+            self.store(handle);
+        }
+    }
+
+    impl Handle for TestHandle {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+            if self.unreadable {
+                return Err(());
+            }
+            let len = buf.len().min(self.data.len() - self.pos);
+            buf[..len].copy_from_slice(&self.data[self.pos..self.pos + len]);
+            self.pos += len;
+            Ok(len)
+        }
+
+        fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn write_block(&mut self, block_num: u16, buf: &[u8]) -> Result<usize, ()> {
+            self.blocks.push(block_num);
+            self.write(buf)
+        }
+
+        fn size(&self) -> Option<u64> {
+            Some(self.data.len() as u64)
+        }
+
+        fn is_writable(&self) -> bool {
+            !self.busy.get()
+        }
+
+        fn flush(&mut self) -> Result<(), ()> {
+            self.flushes.push(self.blocks.len());
+            Ok(())
+        }
+    }
+
+    /// Generates a file of `len` bytes with non-repeating block contents.
+    pub(crate) fn file_contents(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// A TFTP server attached to a mock network.
+    struct Harness<C: Context = TestContext>
+    where
+        C::Handle: 'static,
+    {
+        net: Network,
+        sockets: SocketSet<'static, 'static, 'static>,
+        server: Server,
+        context: C,
+        transfers: Transfers<'static, C::Handle>,
+        now: Instant,
+    }
+
+    impl<C: Context> Harness<C>
+    where
+        C::Handle: 'static,
+    {
+        fn new(context: C) -> Harness<C> {
+            let mut sockets = mock::socket_set(1);
+            let server = Server::new(
+                &mut sockets,
+                mock::udp_buffer(4, 2048),
+                mock::udp_buffer(4, 2048),
+                Instant::from_millis(0),
+            );
+            Harness::with_server(context, sockets, server)
+        }
+
+        fn with_server(
+            context: C,
+            sockets: SocketSet<'static, 'static, 'static>,
+            server: Server,
+        ) -> Harness<C> {
+            let now = Instant::from_millis(0);
+            let mut slots = Vec::new();
+            slots.resize_with(2, || None);
+
+            let mut harness = Harness {
+                net: Network::new(),
+                sockets,
+                server,
+                context,
+                transfers: ManagedSlice::Borrowed(Box::leak(slots.into_boxed_slice())),
+                now,
+            };
+
+            // Bind the socket
+            assert!(harness.poll().is_empty());
+            harness
+        }
+
+        fn send(&mut self, from: IpEndpoint, repr: Repr) -> Vec<Datagram> {
+            self.send_bytes(from, &encode(repr))
+        }
+
+        fn send_bytes(&mut self, from: IpEndpoint, bytes: &[u8]) -> Vec<Datagram> {
+            self.send_to(from, DEFAULT_PORT, bytes)
+        }
+
+        fn send_to(&mut self, from: IpEndpoint, port: u16, bytes: &[u8]) -> Vec<Datagram> {
+            self.net.inject(from, mock::local_ep(port), bytes);
+            self.poll()
+        }
+
+        fn poll(&mut self) -> Vec<Datagram> {
+            self.net.poll(&mut self.sockets, self.now);
+            self.server
+                .serve(
+                    &mut self.sockets,
+                    &mut self.context,
+                    &mut self.transfers,
+                    self.now,
+                )
+                .unwrap();
+            self.net.poll(&mut self.sockets, self.now);
+            self.net.transmitted()
+        }
+    }
+
+    fn client(host: u8) -> IpEndpoint {
+        mock::remote_ep(host, 40000 + u16::from(host))
+    }
+
+    fn rrq(filename: &str, options: Options) -> Repr<'_> {
+        Repr::ReadRequest {
+            filename,
+            mode: Mode::Octet,
+            options,
+        }
+    }
+
+    fn wrq(filename: &str, options: Options) -> Repr<'_> {
+        Repr::WriteRequest {
+            filename,
+            mode: Mode::Octet,
+            options,
+        }
+    }
+
+    /// Emits `repr` into a packet of its own.
+    fn encode(repr: Repr) -> Vec<u8> {
+        let mut bytes = vec![0; repr.buffer_len()];
+        repr.emit(&mut Packet::new_unchecked(&mut bytes)).unwrap();
+        bytes
+    }
+
+    /// Parses the only datagram sent by the server.
+    fn reply(sent: &[Datagram]) -> Repr<'_> {
+        assert_eq!(sent.len(), 1, "expected a single reply, got {:?}", sent);
+        // `Repr` borrows from the packet wrapper, so keep it around
+        let packet = Box::leak(Box::new(Packet::new_checked(&sent[0].payload[..]).unwrap()));
+        Repr::parse(packet).unwrap()
+    }
+
+    fn blksize(size: u16) -> Options {
+        Options {
+            block_size: Some(size),
+            ..Options::default()
+        }
+    }
+
+    fn tsize(size: u64) -> Options {
+        Options {
+            transfer_size: Some(size),
+            ..Options::default()
+        }
+    }
+
+    fn error_code(repr: Repr) -> ErrorCode {
+        match repr {
+            Repr::Error { code, .. } => code,
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_datagram_read() {
+        let file = file_contents(600);
+        let mut context = TestContext::with_file("file", file.clone());
+        let mut pool = transfers::<TestHandle, 1>();
+        let mut transfers = Transfers::from(&mut pool[..]);
+        let mut server = Server::detached(Instant::from_millis(0));
+        let mut tx = mock::Outbox::default();
+        let now = Instant::from_millis(0);
+
+        let mut send = |server: &mut Server, tx: &mut mock::Outbox, repr: Repr| {
+            server
+                .process_datagram(
+                    &mut context,
+                    &mut transfers,
+                    &encode(repr),
+                    client(1),
+                    now,
+                    tx,
+                )
+                .unwrap();
+        };
+
+        send(&mut server, &mut tx, rrq("file", Options::default()));
+        send(&mut server, &mut tx, Repr::Ack { block_num: 1 });
+        send(&mut server, &mut tx, Repr::Ack { block_num: 2 });
+
+        let mut received = Vec::new();
+        for (block, (ep, bytes)) in tx.sent.iter().enumerate() {
+            assert_eq!(*ep, client(1));
+            let packet = Packet::new_checked(&bytes[..]).unwrap();
+            match Repr::parse(&packet).unwrap() {
+                Repr::Data { block_num, data } => {
+                    assert_eq!(usize::from(block_num), block + 1);
+                    received.extend_from_slice(data);
+                }
+                other => panic!("unexpected reply {:?}", other),
+            }
+        }
+        assert_eq!(received, file);
+        assert_eq!(server.next_poll_at(), None);
+
+        // Not attached to any socket
+        let mut sockets = mock::socket_set(1);
+        assert_eq!(
+            server.serve(&mut sockets, &mut context, &mut transfers, now),
+            Err(Error::Illegal)
+        );
+    }
+
+    #[test]
+    fn test_read() {
+        let file = file_contents(1100);
+        let mut h = Harness::new(TestContext::with_file("file", file.clone()));
+
+        let mut received = Vec::new();
+        let mut sent = h.send(client(1), rrq("file", Options::default()));
+
+        for block in 1..=3 {
+            match reply(&sent) {
+                Repr::Data { block_num, data } => {
+                    assert_eq!(block_num, block);
+                    received.extend_from_slice(data);
+                }
+                other => panic!("unexpected reply {:?}", other),
+            }
+            sent = h.send(client(1), Repr::Ack { block_num: block });
+        }
+
+        assert!(sent.is_empty());
+        assert_eq!(received, file);
+        assert!(h.transfers.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn test_duplicate_final_ack() {
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(600)));
+
+        h.send(client(1), rrq("file", Options::default()));
+        h.send(client(1), Repr::Ack { block_num: 1 });
+        assert!(h.send(client(1), Repr::Ack { block_num: 2 }).is_empty());
+        assert!(h.transfers.iter().all(Option::is_none));
+
+        // The final ACK is sent again, e.g. after a retransmission of the final block
+        assert!(h.send(client(1), Repr::Ack { block_num: 2 }).is_empty());
+
+        // Other clients and blocks still get an error
+        let sent = h.send(client(2), Repr::Ack { block_num: 2 });
+        assert_eq!(error_code(reply(&sent)), ErrorCode::AccessViolation);
+        let sent = h.send(client(1), Repr::Ack { block_num: 1 });
+        assert_eq!(error_code(reply(&sent)), ErrorCode::AccessViolation);
+
+        // Until the server stops dallying
+        h.now += DALLY_DURATION;
+        let sent = h.send(client(1), Repr::Ack { block_num: 2 });
+        assert_eq!(error_code(reply(&sent)), ErrorCode::AccessViolation);
+    }
+
+    #[test]
+    fn test_duplicate_final_ack_tid_pool() {
+        let mut sockets = mock::socket_set(2);
+        let pool = Some((mock::udp_buffer(4, 2048), mock::udp_buffer(4, 2048)));
+        let server = Server::with_tid_pool(
+            &mut sockets,
+            mock::udp_buffer(4, 2048),
+            mock::udp_buffer(4, 2048),
+            pool,
+            Instant::from_millis(0),
+        );
+        let file = TestContext::with_file("file", file_contents(600));
+        let mut h = Harness::with_server(file, sockets, server);
+        let ack = |block_num| encode(Repr::Ack { block_num });
+
+        h.send(client(1), rrq("file", Options::default()));
+        h.send_to(client(1), TID_BASE_PORT, &ack(1));
+        assert!(h.send_to(client(1), TID_BASE_PORT, &ack(2)).is_empty());
+        assert!(h.transfers.iter().all(Option::is_none));
+
+        // The final ACK sent again to the transfer ID is absorbed too
+        assert!(h.send_to(client(1), TID_BASE_PORT, &ack(2)).is_empty());
+
+        // Other clients and blocks are still unknown to it
+        let sent = h.send_to(client(2), TID_BASE_PORT, &ack(2));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::UnknownID);
+        let sent = h.send_to(client(1), TID_BASE_PORT, &ack(1));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::UnknownID);
+
+        h.now += DALLY_DURATION;
+        let sent = h.send_to(client(1), TID_BASE_PORT, &ack(2));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::UnknownID);
+    }
+
+    #[test]
+    fn test_size_probe() {
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(1100)));
+
+        // The size is reported without sending any data
+        let sent = h.send(client(1), rrq("file", tsize(0)));
+        assert_eq!(
+            reply(&sent),
+            Repr::OptionAck {
+                options: tsize(1100)
+            }
+        );
+
+        // The client is not interested in the file contents
+        let abort = Repr::Error {
+            code: ErrorCode::OptionNegotiation,
+            msg: "Size probe",
+        };
+        assert!(h.send(client(1), abort).is_empty());
+        assert!(h.transfers.iter().all(Option::is_none));
+
+        // Or it is, after all
+        h.send(client(1), rrq("file", tsize(0)));
+        match reply(&h.send(client(1), Repr::Ack { block_num: 0 })) {
+            Repr::Data { block_num: 1, data } => assert_eq!(data.len(), DEFAULT_BLOCK_SIZE),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_broadcast_request() {
+        let file = file_contents(100);
+        let mut h = Harness::new(TestContext::with_file("file", file.clone()));
+
+        let bytes = encode(rrq("file", Options::default()));
+
+        // The request is answered from the unicast address of the interface
+        let to = IpEndpoint::new(Ipv4Address::BROADCAST.into(), DEFAULT_PORT);
+        h.net.inject(client(1), to, &bytes);
+        let sent = h.poll();
+        assert_eq!(sent[0].src, mock::local_ep(DEFAULT_PORT));
+        assert_eq!(sent[0].dst, client(1));
+        assert_eq!(
+            reply(&sent),
+            Repr::Data {
+                block_num: 1,
+                data: &file
+            }
+        );
+
+        // ...and the transfer goes on over unicast
+        let sent = h.send(client(1), Repr::Ack { block_num: 1 });
+        assert!(sent.is_empty());
+        assert!(h.server.is_idle(&h.transfers));
+
+        // Subnet-directed broadcasts never reach the server
+        let to = IpEndpoint::new(Ipv4Address::new(192, 168, 69, 255).into(), DEFAULT_PORT);
+        h.net.inject(client(2), to, &bytes);
+        assert!(h.poll().is_empty());
+    }
+
+    #[test]
+    fn test_single_socket_tid() {
+        let file = file_contents(600);
+        let mut h = Harness::new(TestContext::with_file("file", file));
+        assert!(!h.server.strict_tid());
+
+        // Transfers are carried out from the well-known port
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        assert_eq!(sent[0].src, mock::local_ep(DEFAULT_PORT));
+
+        // Another port of the same host has no transfer of its own
+        let ack = encode(Repr::Ack { block_num: 1 });
+        let other = mock::remote_ep(1, client(1).port + 1);
+        let sent = h.send_bytes(other, &ack);
+        assert_eq!(error_code(reply(&sent)), ErrorCode::AccessViolation);
+
+        let sent = h.send_bytes(client(1), &ack);
+        assert_eq!(sent[0].src, mock::local_ep(DEFAULT_PORT));
+        assert_eq!(sent[0].dst, client(1));
+    }
+
+    #[test]
+    fn test_tid_pool() {
+        let mut sockets = mock::socket_set(3);
+        let pool = (0..2).map(|_| (mock::udp_buffer(4, 2048), mock::udp_buffer(4, 2048)));
+        let server = Server::with_tid_pool(
+            &mut sockets,
+            mock::udp_buffer(4, 2048),
+            mock::udp_buffer(4, 2048),
+            pool,
+            Instant::from_millis(0),
+        );
+        assert_eq!(server.tid_pool_size(), 2);
+        assert!(server.strict_tid());
+
+        let file = file_contents(600);
+        let mut h = Harness::with_server(TestContext::with_file("file", file), sockets, server);
+
+        // Each transfer is taken over by a socket of its own
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        assert_eq!(sent[0].src, mock::local_ep(TID_BASE_PORT));
+        assert_eq!(sent[0].dst, client(1));
+        let sent = h.send(client(2), rrq("file", Options::default()));
+        assert_eq!(sent[0].src, mock::local_ep(TID_BASE_PORT + 1));
+
+        // All transfer IDs are in use
+        let sent = h.send(client(3), rrq("file", Options::default()));
+        assert_eq!(sent[0].src, mock::local_ep(DEFAULT_PORT));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::AccessViolation);
+
+        // Packets from other hosts are rejected without disturbing the transfer
+        let ack = encode(Repr::Ack { block_num: 1 });
+        let sent = h.send_to(client(3), TID_BASE_PORT, &ack);
+        assert_eq!(error_code(reply(&sent)), ErrorCode::UnknownID);
+
+        // ...and so are packets from other ports of the client
+        let other = mock::remote_ep(1, client(1).port + 1);
+        let sent = h.send_to(other, TID_BASE_PORT, &ack);
+        assert_eq!(error_code(reply(&sent)), ErrorCode::UnknownID);
+
+        let sent = h.send_to(client(1), TID_BASE_PORT, &ack);
+        assert_eq!(sent[0].src, mock::local_ep(TID_BASE_PORT));
+        match reply(&sent) {
+            Repr::Data { block_num: 2, data } => assert_eq!(data.len(), 88),
+            other => panic!("unexpected reply {:?}", other),
+        }
+
+        // A preempting request takes over the transfer ID of its victim, which is told from it
+        h.context.priorities = vec![(client(3), 1)];
+        let sent = h.send(client(3), rrq("file", Options::default()));
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].src, mock::local_ep(TID_BASE_PORT));
+        assert_eq!(sent[0].dst, client(1));
+        assert_eq!(error_code(reply(&sent[..1])), ErrorCode::Undefined);
+        assert_eq!(sent[1].src, mock::local_ep(TID_BASE_PORT));
+        assert_eq!(sent[1].dst, client(3));
+        assert!(matches!(reply(&sent[1..]), Repr::Data { block_num: 1, .. }));
+    }
+
+    #[test]
+    fn test_listener_reserved_for_requests() {
+        let mut sockets = mock::socket_set(3);
+        let pool = (0..2).map(|_| (mock::udp_buffer(4, 2048), mock::udp_buffer(4, 2048)));
+        let server = Server::with_tid_pool(
+            &mut sockets,
+            mock::udp_buffer(4, 2048),
+            mock::udp_buffer(4, 2048),
+            pool,
+            Instant::from_millis(0),
+        );
+        let file = file_contents(600);
+        let mut h = Harness::with_server(TestContext::with_file("file", file), sockets, server);
+
+        let sent = h.send(client(1), wrq("upload", Options::default()));
+        assert_eq!(sent[0].src, mock::local_ep(TID_BASE_PORT));
+
+        // Data sent to the listening port is dropped
+        let data = encode(Repr::Data {
+            block_num: 1,
+            data: &[0; 512],
+        });
+        assert!(h.send_to(client(1), DEFAULT_PORT, &data).is_empty());
+
+        // A new client is served while the transfer is mid-stream
+        h.net
+            .inject(client(1), mock::local_ep(TID_BASE_PORT), &data);
+        h.net.inject(client(1), mock::local_ep(DEFAULT_PORT), &data);
+        let sent = h.send(client(2), rrq("file", Options::default()));
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().any(|d| d.dst == client(1)
+            && d.src == mock::local_ep(TID_BASE_PORT)
+            && d.payload == encode(Repr::Ack { block_num: 1 })));
+        assert!(sent
+            .iter()
+            .any(|d| d.dst == client(2) && d.src == mock::local_ep(TID_BASE_PORT + 1)));
+
+        // Requests from clients of ongoing transfers are still answered
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::AccessViolation);
+    }
+
+    #[test]
+    fn test_port() {
+        assert_eq!(parse_port("69"), 69);
+        assert_eq!(parse_port("65535"), 65535);
+
+        // Requests are received on the default port
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(100)));
+        assert_eq!(h.server.port(), DEFAULT_PORT);
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        assert_eq!(sent[0].src, mock::local_ep(DEFAULT_PORT));
+
+        // Unless overridden before binding
+        let mut sockets = mock::socket_set(1);
+        let mut server = Server::new(
+            &mut sockets,
+            mock::udp_buffer(4, 2048),
+            mock::udp_buffer(4, 2048),
+            Instant::from_millis(0),
+        );
+        server.set_port(6969);
+        let mut h = Harness::with_server(
+            TestContext::with_file("file", file_contents(100)),
+            sockets,
+            server,
+        );
+        let bytes = encode(rrq("file", Options::default()));
+        let sent = h.send_to(client(1), 6969, &bytes);
+        assert_eq!(sent[0].src, mock::local_ep(6969));
+    }
+
+    #[test]
+    fn test_send_error() {
+        let mut h = Harness::new(TestContext::default());
+        h.server
+            .send_error(
+                &mut h.sockets,
+                client(1),
+                ErrorCode::AccessViolation,
+                "Denied",
+            )
+            .unwrap();
+
+        let sent = h.poll();
+        assert_eq!(sent[0].src, mock::local_ep(DEFAULT_PORT));
+        assert_eq!(sent[0].dst, client(1));
+        assert_eq!(
+            reply(&sent),
+            Repr::Error {
+                code: ErrorCode::AccessViolation,
+                msg: "Denied"
+            }
+        );
+        assert_eq!(h.server.stats().errors_sent, 1);
+
+        // Not attached to any socket
+        let mut server = Server::detached(Instant::from_millis(0));
+        assert_eq!(
+            server.send_error(&mut h.sockets, client(1), ErrorCode::Undefined, ""),
+            Err(Error::Illegal)
+        );
+    }
+
+    #[test]
+    fn test_undersized_rx_buffer() {
+        let mut sockets = mock::socket_set(1);
+        let now = Instant::from_millis(0);
+        let mut server = Server::new(
+            &mut sockets,
+            mock::udp_buffer(4, 256),
+            mock::udp_buffer(4, 2048),
+            now,
+        );
+        let mut context = TestContext::default();
+        let mut pool = transfers::<TestHandle, 1>();
+        let mut transfers = Transfers::from(&mut pool[..]);
+
+        assert_eq!(
+            server.serve(&mut sockets, &mut context, &mut transfers, now),
+            Err(Error::Truncated)
+        );
+
+        // Fine with smaller blocks
+        server.set_mtu(Some(256));
+        assert_eq!(
+            server.serve(&mut sockets, &mut context, &mut transfers, now),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_serve_budget() {
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(100)));
+        let bytes = encode(rrq("file", Options::default()));
+
+        // All the queued requests are handled at once, the last one running out of slots
+        for host in 1..=3 {
+            h.net
+                .inject(client(host), mock::local_ep(DEFAULT_PORT), &bytes);
+        }
+        let sent = h.poll();
+        assert_eq!(sent.len(), 3);
+        assert!(h.transfers.iter().all(Option::is_some));
+        assert_eq!(sent[2].dst, client(3));
+        assert_eq!(error_code(reply(&sent[2..])), ErrorCode::AccessViolation);
+
+        // Unless the budget runs out first
+        h.server.reset(&mut h.context, &mut h.transfers);
+        h.server.set_serve_budget(2);
+        for host in 1..=3 {
+            h.net
+                .inject(client(host), mock::local_ep(DEFAULT_PORT), &bytes);
+        }
+        assert_eq!(h.poll().len(), 2);
+        assert_eq!(h.poll().len(), 1);
+    }
+
+    #[test]
+    fn test_serve_counted() {
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(100)));
+        let bytes = encode(rrq("file", Options::default()));
+
+        // Two requests handled by the same call, each answered with a DATA packet
+        for host in 1..=2 {
+            h.net
+                .inject(client(host), mock::local_ep(DEFAULT_PORT), &bytes);
+        }
+        h.net.poll(&mut h.sockets, h.now);
+        let activity = h
+            .server
+            .serve_counted(&mut h.sockets, &mut h.context, &mut h.transfers, h.now)
+            .unwrap();
+        assert_eq!(
+            activity,
+            Activity {
+                received: 2,
+                sent: 2
+            }
+        );
+        h.net.poll(&mut h.sockets, h.now);
+        assert_eq!(h.net.transmitted().len(), 2);
+
+        // Retransmissions count as sent
+        h.now += RETRY_TIMEOUT;
+        let activity = h
+            .server
+            .serve_counted(&mut h.sockets, &mut h.context, &mut h.transfers, h.now)
+            .unwrap();
+        assert_eq!(
+            activity,
+            Activity {
+                received: 0,
+                sent: 2
+            }
+        );
+
+        // Nothing to do
+        h.net.poll(&mut h.sockets, h.now);
+        let activity = h
+            .server
+            .serve_counted(&mut h.sockets, &mut h.context, &mut h.transfers, h.now)
+            .unwrap();
+        assert!(activity.is_idle());
+    }
+
+    #[test]
+    fn test_preemption() {
+        let file = file_contents(100);
+        let mut h = Harness::new(TestContext::with_file("file", file.clone()));
+        h.context.priorities = vec![(client(3), 2), (client(4), 1), (client(5), 1)];
+
+        h.send(client(1), wrq("upload", Options::default()));
+        h.send(client(2), rrq("file", Options::default()));
+
+        // With all the slots taken, a request of higher priority preempts the oldest
+        // of the transfers with the lowest priority
+        let sent = h.send(client(3), rrq("file", Options::default()));
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].dst, client(1));
+        assert_eq!(error_code(reply(&sent[..1])), ErrorCode::Undefined);
+        assert_eq!(sent[1].dst, client(3));
+        assert_eq!(
+            reply(&sent[1..]),
+            Repr::Data {
+                block_num: 1,
+                data: &file
+            }
+        );
+        assert_eq!(h.context.discarded, vec!["upload"]);
+
+        // Then the next lowest one
+        let sent = h.send(client(4), rrq("file", Options::default()));
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].dst, client(2));
+        assert!(h
+            .transfers
+            .iter()
+            .flatten()
+            .all(|xfer| xfer.ep != client(2)));
+
+        // Requests of equal or lower priority than all the transfers are rejected
+        let sent = h.send(client(5), rrq("file", Options::default()));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::AccessViolation);
+        let sent = h.send(client(6), rrq("file", Options::default()));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::AccessViolation);
+        assert_eq!(h.transfers.iter().flatten().count(), 2);
+    }
+
+    #[test]
+    fn test_failed_request_preempts_nothing() {
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(1000)));
+        h.context.priorities = vec![(client(3), 2)];
+        h.send(client(1), rrq("file", Options::default()));
+        h.send(client(2), wrq("upload", Options::default()));
+
+        // Requests that can't be served leave the running transfers alone
+        let sent = h.send(client(3), rrq("missing", Options::default()));
+        assert_eq!(sent.len(), 1);
+        assert_eq!(error_code(reply(&sent)), ErrorCode::FileNotFound);
+
+        h.context.rejected = Some(ErrorCode::AccessViolation);
+        let sent = h.send(client(3), rrq("file", Options::default()));
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dst, client(3));
+        h.context.rejected = None;
+
+        assert!(h.context.discarded.is_empty());
+        assert_eq!(
+            h.transfers
+                .iter()
+                .flatten()
+                .map(|xfer| xfer.ep)
+                .collect::<Vec<_>>(),
+            [client(1), client(2)]
+        );
+        let sent = h.send(client(1), Repr::Ack { block_num: 1 });
+        assert!(matches!(reply(&sent), Repr::Data { block_num: 2, .. }));
+    }
+
+    #[test]
+    fn test_write() {
+        let file = file_contents(1024);
+        let mut h = Harness::new(TestContext::default());
+
+        let sent = h.send(client(1), wrq("file", Options::default()));
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 0 });
+
+        for (i, chunk) in file.chunks(512).chain(Some(&[][..])).enumerate() {
+            let block_num = i as u16 + 1;
+            let sent = h.send(
+                client(1),
+                Repr::Data {
+                    block_num,
+                    data: chunk,
+                },
+            );
+            assert_eq!(reply(&sent), Repr::Ack { block_num });
+        }
+
+        assert_eq!(h.context.file("file"), Some(&file[..]));
+        assert_eq!(h.context.committed, vec!["file"]);
+        assert!(h.context.discarded.is_empty());
+    }
+
+    #[test]
+    fn test_aborted_write_discarded() {
+        let mut h = Harness::new(TestContext::default());
+
+        // Aborted by the client going silent
+        h.send(client(1), wrq("file", Options::default()));
+        h.send(
+            client(1),
+            Repr::Data {
+                block_num: 1,
+                data: &file_contents(512),
+            },
+        );
+        for _ in 0..=MAX_RETRIES {
+            h.now += RETRY_TIMEOUT;
+            h.poll();
+        }
+        assert!(h.server.is_idle(&h.transfers));
+        assert_eq!(h.context.discarded, vec!["file"]);
+
+        // Aborted by a reset
+        h.send(client(1), wrq("other", Options::default()));
+        h.server.reset(&mut h.context, &mut h.transfers);
+        assert_eq!(h.context.discarded, vec!["file", "other"]);
+        assert!(h.context.committed.is_empty());
+    }
+
+    #[test]
+    fn test_peer_unreachable() {
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(1024)));
+
+        h.send(client(1), wrq("upload", Options::default()));
+        h.send(client(2), rrq("file", Options::default()));
+
+        // Unrelated peers are ignored
+        let now = h.now;
+        let unreachable = |h: &mut Harness, ep| {
+            h.server
+                .peer_unreachable(&mut h.context, &mut h.transfers, ep, now)
+        };
+        assert!(!unreachable(&mut h, client(3)));
+        assert!(!h.server.is_idle(&h.transfers));
+
+        // Transfers are closed without waiting for retransmissions to run out
+        assert!(unreachable(&mut h, client(1)));
+        assert!(unreachable(&mut h, client(2)));
+        assert!(h.server.is_idle(&h.transfers));
+        assert_eq!(h.server.next_poll_at(), None);
+        assert_eq!(h.context.discarded, vec!["upload"]);
+        assert_eq!(h.server.stats().timeouts, 0);
+
+        h.now += RETRY_TIMEOUT;
+        assert!(h.poll().is_empty());
+    }
+
+    #[test]
+    fn test_idle_server() {
+        let file = file_contents(100);
+        let mut h = Harness::new(TestContext::with_file("file", file.clone()));
+
+        // Nothing to do: no periodic wakeup
+        assert!(h.server.is_idle(&h.transfers));
+        assert_eq!(h.server.next_poll_at(), None);
+        assert_eq!(h.server.next_poll(h.now), IDLE_POLL_INTERVAL);
+
+        h.send(client(1), rrq("file", Options::default()));
+        assert!(!h.server.is_idle(&h.transfers));
+        assert_eq!(
+            h.server.next_poll_at(),
+            Some(h.now + Duration::from_millis(50))
+        );
+
+        h.send(client(1), Repr::Ack { block_num: 1 });
+        assert!(h.server.is_idle(&h.transfers));
+        assert_eq!(h.server.next_poll_at(), None);
+    }
+
+    #[test]
+    fn test_retransmission() {
+        let file = file_contents(100);
+        let mut h = Harness::new(TestContext::with_file("file", file.clone()));
+
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        let data = reply(&sent);
+
+        // Nothing is resent before the timeout expires
+        h.now += Duration::from_millis(50);
+        assert!(h.poll().is_empty());
+
+        for _ in 0..MAX_RETRIES {
+            h.now += RETRY_TIMEOUT;
+            assert_eq!(reply(&h.poll()), data);
+        }
+
+        // Eventually, the transfer is dropped
+        h.now += RETRY_TIMEOUT;
+        assert!(h.poll().is_empty());
+        assert!(h.server.is_idle(&h.transfers));
+        assert_eq!(h.server.stats().timeouts, 1);
+    }
+
+    #[test]
+    fn test_peek_request() {
+        let bytes = encode(wrq("cfg/net", Options::default()));
+        assert_eq!(
+            peek_request(&bytes),
+            Some((OpCode::Write, "cfg/net", Mode::Octet))
+        );
+
+        // Options are ignored
+        let bytes = PacketBuilder::rrq("file", Mode::NetAscii)
+            .string("blksize")
+            .string("1024")
+            .build();
+        assert_eq!(
+            peek_request(&bytes),
+            Some((OpCode::Read, "file", Mode::NetAscii))
+        );
+
+        // Anything but a well-formed request
+        assert_eq!(peek_request(&[]), None);
+        assert_eq!(peek_request(&[0, 1]), None);
+        assert_eq!(peek_request(b"\x00\x01file"), None);
+        assert_eq!(peek_request(b"\x00\x01\xff\x00octet\x00"), None);
+        assert_eq!(peek_request(&[0, 4, 0, 1]), None);
+        assert_eq!(peek_request(&[0, 9, 0, 1]), None);
+    }
+
+    #[test]
+    fn test_block_size_constants() {
+        // These are part of the protocol, and size the buffers of every transfer
+        assert_eq!(DEFAULT_BLOCK_SIZE, 512);
+        assert_eq!(MIN_BLOCK_SIZE, 8);
+        assert_eq!(MAX_REPLY_LEN, DATA_HEADER_LEN + MAX_BLOCK_SIZE);
+
+        // Unless the MTU says otherwise, blocks can be as large as `MAX_BLOCK_SIZE`
+        let mut server = Server::detached(Instant::from_millis(0));
+        assert_eq!(server.negotiate_block_size(None), Ok(DEFAULT_BLOCK_SIZE));
+        assert_eq!(
+            server.negotiate_block_size(Some(u16::MAX)),
+            Ok(MAX_BLOCK_SIZE)
+        );
+        server.set_mtu(Some(DATA_HEADER_LEN + 100));
+        assert_eq!(server.negotiate_block_size(Some(u16::MAX)), Ok(100));
+    }
+
+    #[test]
+    fn test_required_buffer_sizes() {
+        // The sizes used by the example, for the default block size
+        assert_eq!(required_rx_size(DEFAULT_BLOCK_SIZE), 1032);
+        assert_eq!(required_rx_packets(), 2);
+        assert_eq!(required_tx_size(DEFAULT_BLOCK_SIZE, 1), 1032);
+        assert_eq!(required_tx_size(DEFAULT_BLOCK_SIZE, 0), 1032);
+        assert_eq!(required_tx_packets(1), 2);
+
+        assert_eq!(required_rx_size(1024), 2056);
+        assert_eq!(required_tx_size(1024, 4), 5140);
+        assert_eq!(required_tx_packets(4), 5);
+
+        // A server sized accordingly accepts full DATA packets
+        let mut sockets = mock::socket_set(1);
+        let server = Server::new(
+            &mut sockets,
+            mock::udp_buffer(required_rx_packets(), required_rx_size(DEFAULT_BLOCK_SIZE)),
+            mock::udp_buffer(
+                required_tx_packets(1),
+                required_tx_size(DEFAULT_BLOCK_SIZE, 1),
+            ),
+            Instant::from_millis(0),
+        );
+        let mut h = Harness::with_server(TestContext::default(), sockets, server);
+        let sent = h.send(client(1), wrq("file", Options::default()));
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 0 });
+
+        let block = file_contents(DEFAULT_BLOCK_SIZE);
+        let data = Repr::Data {
+            block_num: 1,
+            data: &block,
+        };
+        assert_eq!(reply(&h.send(client(1), data)), Repr::Ack { block_num: 1 });
+    }
+
+    #[test]
+    fn test_clock() {
+        std::thread_local!(static NOW: Cell<Instant> = Cell::new(Instant::from_millis(0)));
+
+        fn fake_clock() -> Instant {
+            NOW.with(Cell::get)
+        }
+
+        fn serve(h: &mut Harness) -> Vec<Datagram> {
+            h.net.poll(&mut h.sockets, fake_clock());
+            h.server
+                .serve_now(&mut h.sockets, &mut h.context, &mut h.transfers)
+                .unwrap();
+            h.net.poll(&mut h.sockets, fake_clock());
+            h.net.transmitted()
+        }
+
+        let file = file_contents(100);
+        let mut h = Harness::new(TestContext::with_file("file", file));
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        let data = reply(&sent);
+
+        // Without a clock, the current time is unknown
+        let res = h
+            .server
+            .serve_now(&mut h.sockets, &mut h.context, &mut h.transfers);
+        assert_eq!(res, Err(Error::Illegal));
+
+        // Timeouts only expire as the fake clock advances
+        h.server.set_clock(Some(fake_clock));
+        NOW.with(|now| now.set(Instant::from_millis(50)));
+        assert!(serve(&mut h).is_empty());
+
+        NOW.with(|now| now.set(Instant::from_millis(RETRY_TIMEOUT.total_millis() as i64)));
+        assert_eq!(reply(&serve(&mut h)), data);
+    }
+
+    #[test]
+    fn test_retransmission_under_traffic() {
+        let file = file_contents(100);
+        let mut h = Harness::new(TestContext::with_file("file", file.clone()));
+
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        let data = reply(&sent);
+
+        // Another client keeps the server busy, with a packet on every poll
+        let busy = |h: &mut Harness| {
+            let sent = h.send(client(2), rrq("missing", Options::default()));
+            let (to_busy, to_stalled): (Vec<_>, Vec<_>) =
+                sent.into_iter().partition(|d| d.dst == client(2));
+            assert_eq!(error_code(reply(&to_busy)), ErrorCode::FileNotFound);
+            to_stalled
+        };
+
+        h.now += Duration::from_millis(50);
+        assert!(busy(&mut h).is_empty());
+
+        // The stalled transfer is still retransmitted on schedule
+        for _ in 0..MAX_RETRIES {
+            h.now += RETRY_TIMEOUT;
+            assert_eq!(reply(&busy(&mut h)), data);
+        }
+
+        h.now += RETRY_TIMEOUT;
+        assert!(busy(&mut h).is_empty());
+        assert!(h.server.is_idle(&h.transfers));
+        assert_eq!(h.server.stats().timeouts, 1);
+    }
+
+    #[test]
+    fn test_server_stats() {
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(100)));
+
+        h.send(client(1), rrq("file", Options::default()));
+        h.send(client(1), Repr::Ack { block_num: 1 });
+
+        let sent = h.send_bytes(client(2), &PacketBuilder::opcode(9).build());
+        assert_eq!(error_code(reply(&sent)), ErrorCode::IllegalOperation);
+
+        assert_eq!(
+            *h.server.stats(),
+            ServerStats {
+                reads: 1,
+                errors_sent: 1,
+                malformed: 1,
+                ..ServerStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_failed_setup_releases_handle() {
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(100)));
+        h.context.unreadable.push("file".to_string());
+
+        // The first read fails: the client is told, and the handle is closed
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::AccessViolation);
+        assert!(h.transfers.iter().all(Option::is_none));
+        assert_eq!((h.context.opened, h.context.closed), (1, 1));
+        assert_eq!(h.server.stats().errors_sent, 1);
+
+        // The first packet can't be sent
+        struct Full;
+        impl Transmit for Full {
+            fn send(&mut self, _: usize, _: IpEndpoint) -> net::Result<&mut [u8]> {
+                Err(Error::Exhausted)
+            }
+        }
+        let mut context = TestContext::with_file("file", file_contents(100));
+        let mut pool = transfers::<TestHandle, 1>();
+        let mut transfers = Transfers::from(&mut pool[..]);
+        let mut server = Server::detached(Instant::from_millis(0));
+        let bytes = encode(rrq("file", Options::default()));
+        let res = server.process_datagram(
+            &mut context,
+            &mut transfers,
+            &bytes,
+            client(1),
+            Instant::from_millis(0),
+            &mut Full,
+        );
+        assert_eq!(res, Err(Error::Exhausted));
+        assert!(transfers.iter().all(Option::is_none));
+        assert_eq!((context.opened, context.closed), (1, 1));
+
+        // Writes that never started are discarded, not closed as if complete
+        let bytes = encode(wrq("new", Options::default()));
+        let res = server.process_datagram(
+            &mut context,
+            &mut transfers,
+            &bytes,
+            client(1),
+            Instant::from_millis(0),
+            &mut Full,
+        );
+        assert_eq!(res, Err(Error::Exhausted));
+        assert!(transfers.iter().all(Option::is_none));
+        assert_eq!(context.discarded, ["new"]);
+        assert!(context.committed.is_empty());
+        assert_eq!((context.opened, context.closed), (2, 1));
+    }
+
+    #[test]
+    #[cfg(feature = "read-ahead")]
+    fn test_read_ahead() {
+        let file = file_contents(1200);
+        let mut h = Harness::new(TestContext::with_file("file", file.clone()));
+        let read = |h: &Harness| h.transfers[0].as_ref().unwrap().handle.pos;
+
+        h.send(client(1), rrq("file", Options::default()));
+        assert_eq!(read(&h), 512);
+        h.send(client(1), Repr::Ack { block_num: 1 });
+        h.server.close_all(&mut h.context, &mut h.transfers);
+
+        // The next block is read as soon as a block is sent, before its ACK
+        h.server.set_read_ahead(true);
+        assert!(h.server.read_ahead());
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        assert_eq!(
+            reply(&sent),
+            Repr::Data {
+                block_num: 1,
+                data: &file[..512]
+            }
+        );
+        assert_eq!(read(&h), 1024);
+
+        // Retransmissions still carry the block sent
+        h.now += RETRY_TIMEOUT;
+        let sent = h.poll();
+        assert_eq!(
+            reply(&sent),
+            Repr::Data {
+                block_num: 1,
+                data: &file[..512]
+            }
+        );
+        let sent = h.send(client(1), Repr::Ack { block_num: 0 });
+        assert_eq!(
+            reply(&sent),
+            Repr::Data {
+                block_num: 1,
+                data: &file[..512]
+            }
+        );
+        assert_eq!(read(&h), 1024);
+
+        let sent = h.send(client(1), Repr::Ack { block_num: 1 });
+        assert_eq!(
+            reply(&sent),
+            Repr::Data {
+                block_num: 2,
+                data: &file[512..1024]
+            }
+        );
+        assert_eq!(read(&h), 1200);
+
+        // Nothing is read past the final block
+        let sent = h.send(client(1), Repr::Ack { block_num: 2 });
+        assert_eq!(
+            reply(&sent),
+            Repr::Data {
+                block_num: 3,
+                data: &file[1024..]
+            }
+        );
+        assert_eq!(read(&h), 1200);
+        h.send(client(1), Repr::Ack { block_num: 3 });
+        assert!(h.server.is_idle(&h.transfers));
+    }
+
+    #[test]
+    fn test_busy_retry_after() {
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(1000)));
+        h.send(client(1), rrq("file", Options::default()));
+        h.send(client(2), rrq("file", Options::default()));
+
+        // Plain rejection by default
+        let sent = h.send(client(3), rrq("file", Options::default()));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::AccessViolation);
+
+        h.server
+            .set_busy_retry_after(Some(Duration::from_millis(4500)));
+        assert_eq!(
+            h.server.busy_retry_after(),
+            Some(Duration::from_millis(4500))
+        );
+        let sent = h.send(client(3), rrq("file", Options::default()));
+        assert_eq!(
+            reply(&sent),
+            Repr::Error {
+                code: ErrorCode::Undefined,
+                msg: "busy; retry-after=5"
+            }
+        );
+        assert_eq!(
+            parse_retry_after("busy; retry-after=5"),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(h.server.stats().busy, 2);
+        assert!(h.transfers.iter().all(Option::is_some));
+
+        let mut buf = [0; BUSY_MESSAGE_LEN];
+        assert_eq!(
+            busy_message(&mut buf, Duration::from_millis(0)),
+            "busy; retry-after=1"
+        );
+        let msg = busy_message(&mut buf, Duration::from_millis(u64::MAX));
+        assert_eq!(msg, "busy; retry-after=18446744073709552");
+        assert_eq!(parse_retry_after(msg), None);
+
+        for msg in [
+            "busy; retry-after=",
+            "busy; retry-after=+5",
+            "File not found",
+        ]
+        .iter()
+        {
+            assert_eq!(parse_retry_after(msg), None);
+        }
+    }
+
+    #[test]
+    fn test_pause() {
+        let file = file_contents(1000);
+        let mut h = Harness::new(TestContext::with_file("file", file.clone()));
+        h.send(client(1), rrq("file", Options::default()));
+
+        h.server.pause();
+        assert!(h.server.is_paused());
+        assert!(h.server.describe(&h.transfers).paused);
+        let sent = h.send(client(2), rrq("file", Options::default()));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::AccessViolation);
+        let sent = h.send(client(2), wrq("new", Options::default()));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::AccessViolation);
+        assert_eq!(h.server.stats().busy, 2);
+        assert!(h.transfers[1].is_none());
+
+        // The transfer underway is carried out to completion
+        let sent = h.send(client(1), Repr::Ack { block_num: 1 });
+        assert_eq!(
+            reply(&sent),
+            Repr::Data {
+                block_num: 2,
+                data: &file[512..]
+            }
+        );
+        h.send(client(1), Repr::Ack { block_num: 2 });
+        assert!(h.transfers.iter().all(Option::is_none));
+
+        h.server.resume();
+        assert!(!h.server.is_paused());
+        let sent = h.send(client(2), rrq("file", Options::default()));
+        assert!(matches!(reply(&sent), Repr::Data { block_num: 1, .. }));
+    }
+
+    #[test]
+    fn test_strict_parse_mode() {
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(100)));
+        let bytes = PacketBuilder::rrq("file", Mode::Octet).build();
+        let unterminated = &bytes[..bytes.len() - 1];
+
+        h.server.set_parse_mode(ParseMode::Strict);
+        let sent = h.send_bytes(client(1), unterminated);
+        assert_eq!(error_code(reply(&sent)), ErrorCode::IllegalOperation);
+        assert_eq!(h.server.stats().malformed, 1);
+
+        h.server.set_parse_mode(ParseMode::Lenient);
+        let sent = h.send_bytes(client(1), unterminated);
+        assert!(matches!(reply(&sent), Repr::Data { block_num: 1, .. }));
+    }
+
+    #[test]
+    fn test_mode_case() {
+        let file = file_contents(100);
+        let mut h = Harness::new(TestContext::with_file("file", file));
+        let request = |mode: &str| PacketBuilder::opcode(1).string("file").string(mode).build();
+
+        // Mode strings are case-insensitive by default
+        for (host, mode) in ["octet", "OCTET", "Octet", "oCtEt"].iter().enumerate() {
+            let host = host as u8 + 1;
+            let sent = h.send_bytes(client(host), &request(mode));
+            assert!(
+                matches!(reply(&sent), Repr::Data { block_num: 1, .. }),
+                "{}",
+                mode
+            );
+            h.send(client(host), Repr::Ack { block_num: 1 });
+        }
+
+        // ...unless they have to be spelled in lowercase
+        h.server.set_mode_case_sensitive(true);
+        assert!(h.server.mode_case_sensitive());
+        for mode in ["OCTET", "Octet"].iter() {
+            let sent = h.send_bytes(client(1), &request(mode));
+            assert_eq!(error_code(reply(&sent)), ErrorCode::IllegalOperation);
+        }
+        let sent = h.send_bytes(client(1), &request("octet"));
+        assert!(matches!(reply(&sent), Repr::Data { block_num: 1, .. }));
+        h.send(client(1), Repr::Ack { block_num: 1 });
+
+        // Which also rules out the abbreviations tolerated by lenient parsing
+        let sent = h.send_bytes(client(1), &request("oct"));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::IllegalOperation);
+    }
+
+    #[test]
+    fn test_octet_aliases() {
+        let file = file_contents(100);
+        let mut h = Harness::new(TestContext::with_file("file", file.clone()));
+        let request = |mode: &str| PacketBuilder::opcode(1).string("file").string(mode).build();
+
+        // Aliases are rejected by default
+        assert!(!h.server.octet_aliases());
+        for mode in ["binary", "image"].iter() {
+            let sent = h.send_bytes(client(1), &request(mode));
+            assert_eq!(error_code(reply(&sent)), ErrorCode::IllegalOperation);
+        }
+
+        h.server.set_octet_aliases(true);
+        for (host, mode) in ["binary", "image", "BINARY"].iter().enumerate() {
+            let host = host as u8 + 1;
+            let sent = h.send_bytes(client(host), &request(mode));
+            assert_eq!(
+                reply(&sent),
+                Repr::Data {
+                    block_num: 1,
+                    data: &file
+                },
+                "{}",
+                mode
+            );
+            h.send(client(host), Repr::Ack { block_num: 1 });
+        }
+
+        // Spelling rules still apply
+        h.server.set_mode_case_sensitive(true);
+        let sent = h.send_bytes(client(1), &request("BINARY"));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::IllegalOperation);
+        let sent = h.send_bytes(client(1), &request("image"));
+        assert!(matches!(reply(&sent), Repr::Data { block_num: 1, .. }));
+        h.send(client(1), Repr::Ack { block_num: 1 });
+
+        h.server.set_parse_mode(ParseMode::Strict);
+        let sent = h.send_bytes(client(1), &request("binary"));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::IllegalOperation);
+    }
+
+    #[test]
+    fn test_forced_retransmit() {
+        let file = file_contents(600);
+        let mut h = Harness::new(TestContext::with_file("file", file.clone()));
+        let retransmit = |h: &mut Harness, idx: usize| {
+            let xfer = h.transfers[idx].as_mut().unwrap();
+            h.server.retransmit(&mut h.sockets, xfer)
+        };
+
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        assert!(matches!(reply(&sent), Repr::Data { block_num: 1, .. }));
+
+        // The last block is sent again right away
+        retransmit(&mut h, 0).unwrap();
+        assert_eq!(h.transfers[0].as_ref().unwrap().retries, 1);
+        h.net.poll(&mut h.sockets, h.now);
+        assert_eq!(
+            reply(&h.net.transmitted()),
+            Repr::Data {
+                block_num: 1,
+                data: &file[..512]
+            }
+        );
+
+        // Within the limits of the retry counter
+        for _ in 1..MAX_RETRIES {
+            retransmit(&mut h, 0).unwrap();
+            h.net.poll(&mut h.sockets, h.now);
+            assert_eq!(h.net.transmitted().len(), 1);
+        }
+        assert_eq!(h.transfers[0].as_ref().unwrap().retries, MAX_RETRIES);
+        assert_eq!(retransmit(&mut h, 0), Err(Error::Exhausted));
+        h.net.poll(&mut h.sockets, h.now);
+        assert!(h.net.transmitted().is_empty());
+
+        // Write transfers send their last ACK again
+        let sent = h.send(client(2), wrq("upload", Options::default()));
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 0 });
+        h.send(
+            client(2),
+            Repr::Data {
+                block_num: 1,
+                data: &[0; 512],
+            },
+        );
+        retransmit(&mut h, 1).unwrap();
+        h.net.poll(&mut h.sockets, h.now);
+        let sent = h.net.transmitted();
+        assert_eq!(sent[0].dst, client(2));
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 1 });
+    }
+
+    #[test]
+    fn test_forced_retransmit_tid_pool() {
+        let mut sockets = mock::socket_set(2);
+        let pool = Some((mock::udp_buffer(4, 2048), mock::udp_buffer(4, 2048)));
+        let server = Server::with_tid_pool(
+            &mut sockets,
+            mock::udp_buffer(4, 2048),
+            mock::udp_buffer(4, 2048),
+            pool,
+            Instant::from_millis(0),
+        );
+        let file = TestContext::with_file("file", file_contents(600));
+        let mut h = Harness::with_server(file, sockets, server);
+        h.send(client(1), rrq("file", Options::default()));
+
+        // The block is sent again from the transfer ID, not from the listening port
+        let xfer = h.transfers[0].as_mut().unwrap();
+        h.server.retransmit(&mut h.sockets, xfer).unwrap();
+        h.net.poll(&mut h.sockets, h.now);
+        let sent = h.net.transmitted();
+        assert_eq!(sent[0].src, mock::local_ep(TID_BASE_PORT));
+        assert_eq!(sent[0].dst, client(1));
+        assert!(matches!(reply(&sent), Repr::Data { block_num: 1, .. }));
+
+        let detached = Server::detached(h.now);
+        let xfer = h.transfers[0].as_mut().unwrap();
+        assert_eq!(
+            detached.retransmit(&mut h.sockets, xfer),
+            Err(Error::Illegal)
+        );
+    }
+    #[test]
+    fn test_endpoint_filter() {
+        let file = file_contents(100);
+        let mut h = Harness::new(TestContext::with_file("file", file.clone()));
+        h.server
+            .set_endpoint_filter(Some(|ep| ep.addr != client(2).addr));
+        assert!(h.server.endpoint_filter().is_some());
+
+        // Denied peers get no reply at all
+        assert!(h
+            .send(client(2), rrq("file", Options::default()))
+            .is_empty());
+        assert!(h.transfers.iter().all(Option::is_none));
+        assert!(h.send(client(2), Repr::Ack { block_num: 1 }).is_empty());
+
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        assert_eq!(
+            reply(&sent),
+            Repr::Data {
+                block_num: 1,
+                data: &file
+            }
+        );
+
+        // The same goes for detached servers
+        let mut server = Server::detached(Instant::from_millis(0));
+        server.set_endpoint_filter(Some(|ep| ep.addr != client(2).addr));
+        let mut context = TestContext::with_file("file", file);
+        let mut pool = transfers::<TestHandle, 1>();
+        let mut transfers = Transfers::from(&mut pool[..]);
+        let mut tx = mock::Outbox::default();
+        let bytes = encode(rrq("file", Options::default()));
+        for host in [2, 1].iter() {
+            server
+                .process_datagram(
+                    &mut context,
+                    &mut transfers,
+                    &bytes,
+                    client(*host),
+                    Instant::from_millis(0),
+                    &mut tx,
+                )
+                .unwrap();
+        }
+        assert_eq!(tx.sent.len(), 1);
+        assert_eq!(tx.sent[0].0, client(1));
+        server.close_all(&mut context, &mut transfers);
+    }
+
+    #[test]
+    fn test_describe() {
+        let mut h = Harness::new(TestContext::default());
+        let config = h.server.describe(&h.transfers);
+        assert_eq!(config.port, DEFAULT_PORT);
+        assert!(config.attached);
+        assert_eq!(config.tid_pool_size, 0);
+        assert_eq!(config.transfer_capacity, 2);
+        assert_eq!(config.retry_timeout, RETRY_TIMEOUT);
+        assert_eq!(config.max_retries, MAX_RETRIES);
+        assert_eq!(config.max_block_size, MAX_BLOCK_SIZE);
+        assert_eq!(config.parse_mode, ParseMode::Lenient);
+        assert!(config.overwrite);
+
+        h.server.set_mtu(Some(300));
+        h.server.set_max_write_bytes(Some(4096));
+        h.server.set_parse_mode(ParseMode::Strict);
+        h.server.set_overwrite(false);
+        h.server.set_octet_aliases(true);
+        h.server.set_serve_budget(3);
+        let config = h.server.describe(&h.transfers);
+        assert_eq!(config.mtu, Some(300));
+        assert_eq!(config.max_block_size, 300 - DATA_HEADER_LEN);
+        assert_eq!(config.max_write_bytes, Some(4096));
+        assert_eq!(config.parse_mode, ParseMode::Strict);
+        assert!(!config.overwrite);
+        assert!(config.octet_aliases);
+        assert_eq!(config.serve_budget, 3);
+
+        let server = Server::detached(Instant::from_millis(0));
+        let config = server.describe(&transfers::<TestHandle, 4>());
+        assert!(!config.attached);
+        assert_eq!(config.transfer_capacity, 4);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(1000)));
+
+        h.send(client(1), rrq("file", Options::default()));
+        h.send(client(2), wrq("other", Options::default()));
+        assert!(h.transfers.iter().all(Option::is_some));
+
+        h.server.reset(&mut h.context, &mut h.transfers);
+        assert!(h.server.is_idle(&h.transfers));
+        assert_eq!(h.server.next_poll_at(), None);
+
+        // Slots can be reused right away
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        assert_eq!(
+            reply(&sent),
+            Repr::Data {
+                block_num: 1,
+                data: &file_contents(512)
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "log", debug_assertions))]
+    fn test_leaked_transfer_warning() {
+        mock::logger::install();
+        let now = Instant::from_millis(0);
+        let mut context = TestContext::with_file("file", file_contents(1000));
+        let mut server = Server::detached(now);
+        let mut tx = mock::Outbox::default();
+        let bytes = encode(rrq("file", Options::default()));
+
+        let leaked = |lines: Vec<String>| lines.iter().any(|l| l.contains("handle is leaked"));
+
+        // Transfers closed before being dropped don't warn
+        let mut pool = transfers::<TestHandle, 1>();
+        let mut xfers = Transfers::from(&mut pool[..]);
+        server
+            .process_datagram(&mut context, &mut xfers, &bytes, client(1), now, &mut tx)
+            .unwrap();
+        server.close_all(&mut context, &mut xfers);
+        assert!(server.is_idle(&xfers));
+        drop(pool);
+        assert!(!leaked(mock::logger::take()));
+
+        // Active transfers do
+        let mut pool = transfers::<TestHandle, 1>();
+        let mut xfers = Transfers::from(&mut pool[..]);
+        server
+            .process_datagram(&mut context, &mut xfers, &bytes, client(1), now, &mut tx)
+            .unwrap();
+        drop(pool);
+        assert!(leaked(mock::logger::take()));
+    }
+
+    #[test]
+    fn test_write_block_numbers() {
+        let file = file_contents(1024);
+        let mut h = Harness::new(TestContext::default());
+
+        let sent = h.send(client(1), wrq("file", Options::default()));
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 0 });
+
+        // Every block but the last is sent twice, as if the first ACK had been lost
+        for (i, chunk) in file.chunks(512).chain(Some(&[][..])).enumerate() {
+            let block_num = i as u16 + 1;
+            for _ in 0..if chunk.is_empty() { 1 } else { 2 } {
+                let sent = h.send(
+                    client(1),
+                    Repr::Data {
+                        block_num,
+                        data: chunk,
+                    },
+                );
+                assert_eq!(reply(&sent), Repr::Ack { block_num });
+            }
+        }
+
+        assert_eq!(h.context.blocks, vec![1, 2, 3]);
+        assert_eq!(h.context.file("file"), Some(&file[..]));
+    }
+
+    #[test]
+    fn test_write_block_rollover() {
+        let mut h = Harness::new(TestContext::default());
+
+        let sent = h.send(client(1), wrq("file", Options::default()));
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 0 });
+
+        // A duplicate of the initial ACK'd block is answered with the same ACK
+        let sent = h.send(
+            client(1),
+            Repr::Data {
+                block_num: 0,
+                data: &[0; 512],
+            },
+        );
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 0 });
+
+        // Pretend 65535 blocks went through, so that block 0 is expected next
+        h.transfers
+            .iter_mut()
+            .flatten()
+            .for_each(|x| x.block_num = 0);
+
+        let sent = h.send(
+            client(1),
+            Repr::Data {
+                block_num: 65535,
+                data: &[0; 512],
+            },
+        );
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 65535 });
+
+        let sent = h.send(
+            client(1),
+            Repr::Data {
+                block_num: 0,
+                data: &[1; 512],
+            },
+        );
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 0 });
+
+        let sent = h.send(
+            client(1),
+            Repr::Data {
+                block_num: 1,
+                data: &[],
+            },
+        );
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 1 });
+        assert_eq!(h.context.file("file"), Some(&[1; 512][..]));
+    }
+
+    #[test]
+    fn test_deferred_write() {
+        let file = file_contents(700);
+        let mut h = Harness::new(TestContext::default());
+
+        h.send(client(1), wrq("file", Options::default()));
+        h.context.busy.set(true);
+
+        // The block is kept aside, and not acknowledged until written
+        let first = Repr::Data {
+            block_num: 1,
+            data: &file[..512],
+        };
+        assert!(h.send(client(1), first).is_empty());
+        assert!(h.poll().is_empty());
+        assert!(h.send(client(1), first).is_empty());
+
+        h.context.busy.set(false);
+        assert_eq!(reply(&h.poll()), Repr::Ack { block_num: 1 });
+
+        let last = Repr::Data {
+            block_num: 2,
+            data: &file[512..],
+        };
+        assert_eq!(reply(&h.send(client(1), last)), Repr::Ack { block_num: 2 });
+        assert!(h.server.is_idle(&h.transfers));
+        assert_eq!(h.context.blocks, vec![1, 2]);
+        assert_eq!(h.context.file("file"), Some(&file[..]));
+    }
+
+    #[test]
+    fn test_flush_interval() {
+        let file = file_contents(7 * 512 + 100);
+        let mut h = Harness::new(TestContext::default());
+        h.server.set_flush_interval(Some(3));
+
+        h.send(client(1), wrq("file", Options::default()));
+        for (i, chunk) in file.chunks(512).enumerate() {
+            let block_num = i as u16 + 1;
+            let sent = h.send(
+                client(1),
+                Repr::Data {
+                    block_num,
+                    data: chunk,
+                },
+            );
+            assert_eq!(reply(&sent), Repr::Ack { block_num });
+        }
+
+        assert_eq!(h.context.flushes, vec![3, 6]);
+        assert_eq!(h.context.file("file"), Some(&file[..]));
+
+        // Disabled by default
+        h.server.set_flush_interval(Some(0));
+        assert_eq!(h.server.flush_interval(), None);
+    }
+
+    #[test]
+    fn test_blksize_negotiated_downwards() {
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(600)));
+
+        let sent = h.send(client(1), rrq("file", blksize(1024)));
+        assert_eq!(
+            reply(&sent),
+            Repr::OptionAck {
+                options: blksize(512)
+            }
+        );
+    }
+
+    #[test]
+    fn test_negotiated_options() {
+        let file = file_contents(1100);
+        let mut h = Harness::new(TestContext::with_file("file", file));
+
+        let options = Options {
+            block_size: Some(256),
+            transfer_size: Some(0),
+            ..Options::default()
+        };
+        h.send(client(1), rrq("file", options));
+        h.send(client(2), wrq("other", Options::default()));
+
+        let xfer = h.transfers[0].as_ref().unwrap();
+        assert_eq!(xfer.endpoint(), client(1));
+        assert!(!xfer.is_write());
+        assert_eq!(xfer.block_size(), 256);
+        assert_eq!(xfer.transfer_size(), Some(1100));
+
+        let xfer = h.transfers[1].as_ref().unwrap();
+        assert_eq!(xfer.endpoint(), client(2));
+        assert!(xfer.is_write());
+        assert_eq!(xfer.block_size(), DEFAULT_BLOCK_SIZE);
+        assert_eq!(xfer.transfer_size(), None);
+    }
+
+    #[test]
+    fn test_mtu_clamps_block_size() {
+        let file = file_contents(1000);
+        let mut h = Harness::new(TestContext::with_file("file", file.clone()));
+        h.server.set_mtu(Some(300));
+
+        let sent = h.send(client(1), rrq("file", blksize(512)));
+        assert_eq!(
+            reply(&sent),
+            Repr::OptionAck {
+                options: blksize(296)
+            }
+        );
+
+        let mut received = Vec::new();
+        let mut sent = h.send(client(1), Repr::Ack { block_num: 0 });
+
+        for block in 1..=4 {
+            assert!(sent.iter().all(|d| d.payload.len() <= 300));
+            match reply(&sent) {
+                Repr::Data { block_num, data } => {
+                    assert_eq!(block_num, block);
+                    assert!(data.len() <= 296);
+                    received.extend_from_slice(data);
+                }
+                other => panic!("unexpected reply {:?}", other),
+            }
+            sent = h.send(client(1), Repr::Ack { block_num: block });
+        }
+
+        assert_eq!(received, file);
+    }
+
+    #[test]
+    fn test_mtu_requires_blksize() {
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(1000)));
+        h.server.set_mtu(Some(300));
+
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::IllegalOperation);
+        assert!(h.transfers.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn test_write_limit_exceeded() {
+        let mut h = Harness::new(TestContext::default());
+        h.server.set_max_write_bytes(Some(700));
+
+        let sent = h.send(client(1), wrq("file", Options::default()));
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 0 });
+
+        let block = file_contents(512);
+        let sent = h.send(
+            client(1),
+            Repr::Data {
+                block_num: 1,
+                data: &block,
+            },
+        );
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 1 });
+
+        let sent = h.send(
+            client(1),
+            Repr::Data {
+                block_num: 2,
+                data: &block,
+            },
+        );
+        assert_eq!(error_code(reply(&sent)), ErrorCode::DiskFull);
+
+        // The transfer is closed, and the offending block never reaches the file
+        assert!(h.transfers.iter().all(Option::is_none));
+        assert_eq!(h.context.file("file"), Some(&block[..]));
+    }
+
+    #[test]
+    fn test_block_limit() {
+        let file = file_contents(2 * 512 + 100);
+        let mut h = Harness::new(TestContext::with_file("file", file));
+        h.server.set_max_blocks(Some(4));
+        assert_eq!(h.server.max_blocks(), Some(4));
+
+        // A client re-acknowledging the first block over and over
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        assert_eq!(reply(&sent).buffer_len(), 4 + 512);
+        for _ in 0..4 {
+            let sent = h.send(client(1), Repr::Ack { block_num: 0 });
+            assert_eq!(reply(&sent).buffer_len(), 4 + 512);
+        }
+
+        let sent = h.send(client(1), Repr::Ack { block_num: 0 });
+        assert_eq!(error_code(reply(&sent)), ErrorCode::Undefined);
+        assert!(h.server.is_idle(&h.transfers));
+
+        // Well-behaved transfers fit within the limit
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        assert_eq!(reply(&sent).buffer_len(), 4 + 512);
+        for block_num in 1..=2 {
+            let sent = h.send(client(1), Repr::Ack { block_num });
+            assert!(matches!(reply(&sent), Repr::Data { .. }));
+        }
+        assert!(h.send(client(1), Repr::Ack { block_num: 3 }).is_empty());
+        assert!(h.server.is_idle(&h.transfers));
+    }
+
+    #[test]
+    fn test_write_limit_tsize() {
+        let mut h = Harness::new(TestContext::default());
+        h.server.set_max_write_bytes(Some(700));
+
+        let sent = h.send(client(1), wrq("file", tsize(701)));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::DiskFull);
+        assert!(h.transfers.iter().all(Option::is_none));
+        assert_eq!(h.context.file("file"), None);
+
+        let sent = h.send(client(1), wrq("file", tsize(700)));
+        assert_eq!(
+            reply(&sent),
+            Repr::OptionAck {
+                options: tsize(700)
+            }
+        );
+    }
+
+    #[test]
+    fn test_write_with_oack() {
+        let file = file_contents(400);
+        let mut h = Harness::new(TestContext::default());
+
+        let sent = h.send(client(1), wrq("file", blksize(256)));
+        assert_eq!(
+            reply(&sent),
+            Repr::OptionAck {
+                options: blksize(256)
+            }
+        );
+
+        for (i, chunk) in file.chunks(256).enumerate() {
+            let block_num = i as u16 + 1;
+            let sent = h.send(
+                client(1),
+                Repr::Data {
+                    block_num,
+                    data: chunk,
+                },
+            );
+            assert_eq!(reply(&sent), Repr::Ack { block_num });
+        }
+
+        assert_eq!(h.context.file("file"), Some(&file[..]));
+    }
+
+    #[test]
+    fn test_resumed_write() {
+        let file = file_contents(1000);
+        let mut h = Harness::new(TestContext::with_file("file", file[..600].to_vec()));
+
+        let resume = Options {
+            offset: Some(512),
+            ..Options::default()
+        };
+        let sent = h.send(client(1), wrq("file", resume));
+        assert_eq!(reply(&sent), Repr::OptionAck { options: resume });
+        assert_eq!(h.context.resumed, vec![("file".to_string(), 512)]);
+
+        let sent = h.send(
+            client(1),
+            Repr::Data {
+                block_num: 1,
+                data: &file[512..],
+            },
+        );
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 1 });
+        assert_eq!(h.context.file("file"), Some(&file[..]));
+    }
+
+    #[test]
+    fn test_resumed_write_unsupported() {
+        let mut h = Harness::new(XorContext::default());
+
+        // The offset is not acknowledged, so the client has to start over
+        let resume = Options {
+            offset: Some(512),
+            ..Options::default()
+        };
+        let sent = h.send(client(1), wrq("file", resume));
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 0 });
+    }
+
+    #[test]
+    fn test_premature_data_before_oack() {
+        let mut h = Harness::new(TestContext::default());
+
+        let sent = h.send(client(1), wrq("file", blksize(256)));
+        assert_eq!(
+            reply(&sent),
+            Repr::OptionAck {
+                options: blksize(256)
+            }
+        );
+
+        // In a write transfer, the OACK is acknowledged by DATA #1: anything else
+        // tears the transfer down instead of being mistaken for a later block
+        let sent = h.send(
+            client(1),
+            Repr::Data {
+                block_num: 2,
+                data: &[0; 256],
+            },
+        );
+        assert_eq!(error_code(reply(&sent)), ErrorCode::IllegalOperation);
+        assert!(h.transfers.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn test_premature_ack_before_oack() {
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(600)));
+
+        let sent = h.send(client(1), rrq("file", blksize(256)));
+        assert_eq!(
+            reply(&sent),
+            Repr::OptionAck {
+                options: blksize(256)
+            }
+        );
+
+        // In a read transfer, the OACK is acknowledged by ACK #0 only
+        let sent = h.send(client(1), Repr::Ack { block_num: 1 });
+        assert_eq!(error_code(reply(&sent)), ErrorCode::IllegalOperation);
+        assert!(h.transfers.iter().all(Option::is_none));
+
+        // Same goes for a DATA packet, even if numbered #1
+        h.send(client(1), rrq("file", blksize(256)));
+        let sent = h.send(
+            client(1),
+            Repr::Data {
+                block_num: 1,
+                data: &[0; 256],
+            },
+        );
+        assert_eq!(error_code(reply(&sent)), ErrorCode::IllegalOperation);
+        assert!(h.transfers.iter().all(Option::is_none));
+    }
+
+    /// A trivial "codec" flipping every bit of the file contents.
+    struct Xor;
+
+    impl Transform<TestHandle> for Xor {
+        fn read(&mut self, inner: &mut TestHandle, buf: &mut [u8]) -> Result<usize, ()> {
+            let n = inner.read(buf)?;
+            buf[..n].iter_mut().for_each(|b| *b ^= 0xff);
+            Ok(n)
+        }
+
+        fn write(&mut self, inner: &mut TestHandle, buf: &[u8]) -> Result<usize, ()> {
+            let data: Vec<u8> = buf.iter().map(|b| b ^ 0xff).collect();
+            inner.write(&data)
+        }
+    }
+
+    /// Runs all the files of a `TestContext` through `Xor`.
+    #[derive(Default)]
+    struct XorContext(TestContext);
+
+    impl Context for XorContext {
+        type Handle = Transformed<TestHandle, Xor>;
+
+        fn open(&mut self, filename: &str, write_mode: bool) -> Result<Self::Handle, ()> {
+            self.0
+                .open(filename, write_mode)
+                .map(|handle| Transformed::new(handle, Xor))
+        }
+
+        fn close(&mut self, handle: Self::Handle) {
+            self.0.close(handle.into_parts().0);
+        }
+    }
+
+    #[test]
+    fn test_transformed_handle() {
+        let file = file_contents(600);
+        let encoded: Vec<u8> = file.iter().map(|b| b ^ 0xff).collect();
+        let mut h = Harness::new(XorContext(TestContext::with_file("file", encoded.clone())));
+
+        // Reading decodes the stored contents
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        assert_eq!(
+            reply(&sent),
+            Repr::Data {
+                block_num: 1,
+                data: &file[..512]
+            }
+        );
+        let sent = h.send(client(1), Repr::Ack { block_num: 1 });
+        assert_eq!(
+            reply(&sent),
+            Repr::Data {
+                block_num: 2,
+                data: &file[512..]
+            }
+        );
+        h.send(client(1), Repr::Ack { block_num: 2 });
+
+        // Writing encodes them back
+        h.send(client(1), wrq("copy", Options::default()));
+        for (i, chunk) in file.chunks(512).enumerate() {
+            let block_num = i as u16 + 1;
+            let sent = h.send(
+                client(1),
+                Repr::Data {
+                    block_num,
+                    data: chunk,
+                },
+            );
+            assert_eq!(reply(&sent), Repr::Ack { block_num });
+        }
+        assert_eq!(h.context.0.file("copy"), Some(&encoded[..]));
+    }
+
+    /// Serves the files of a `TestContext` from memory, along with an index of them.
+    struct IndexContext(TestContext);
+
+    impl Context for IndexContext {
+        type Handle = GeneratedHandle<Vec<u8>>;
+
+        fn list(&self, entry: &mut dyn FnMut(&str)) {
+            entry("/index");
+            self.0.files.iter().for_each(|(name, _)| entry(name));
+        }
+
+        fn open(&mut self, filename: &str, write_mode: bool) -> Result<Self::Handle, ()> {
+            if write_mode {
+                return Err(());
+            }
+            if filename == "/index" {
+                let mut buf = vec![0; 32];
+                let len = write_index(&*self, &mut buf).ok_or(())?;
+                return Ok(GeneratedHandle::with_len(buf, len));
+            }
+            self.0
+                .file(filename)
+                .map(|data| GeneratedHandle::new(data.to_vec()))
+                .ok_or(())
+        }
+
+        fn close(&mut self, _: Self::Handle) {}
+    }
+
+    #[test]
+    fn test_generated_index() {
+        let mut context = TestContext::with_file("boot.bin", file_contents(10));
+        context
+            .files
+            .push(("config".to_string(), b"debug=1".to_vec()));
+        let mut h = Harness::new(IndexContext(context));
+
+        let sent = h.send(
+            client(1),
+            rrq(
+                "/index",
+                Options {
+                    transfer_size: Some(0),
+                    ..Options::default()
+                },
+            ),
+        );
+        assert_eq!(
+            reply(&sent),
+            Repr::OptionAck {
+                options: Options {
+                    transfer_size: Some(23),
+                    ..Options::default()
+                }
+            }
+        );
+        let sent = h.send(client(1), Repr::Ack { block_num: 0 });
+        assert_eq!(
+            reply(&sent),
+            Repr::Data {
+                block_num: 1,
+                data: b"/index\nboot.bin\nconfig\n"
+            }
+        );
+        h.send(client(1), Repr::Ack { block_num: 1 });
+        assert!(h.server.is_idle(&h.transfers));
+
+        // Generated files are read-only
+        let sent = h.send(client(2), wrq("/index", Options::default()));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::FileNotFound);
+
+        // The index must fit its buffer
+        h.context
+            .0
+            .files
+            .push(("a-rather-long-name".to_string(), vec![]));
+        let mut buf = [0; 32];
+        assert_eq!(write_index(&h.context, &mut buf), None);
+        let mut handle = GeneratedHandle::with_len([1, 2, 3], 5);
+        assert_eq!(handle.size(), Some(3));
+        assert_eq!(handle.read(&mut buf), Ok(3));
+        assert_eq!(handle.read(&mut buf), Ok(0));
+        assert_eq!(handle.write(&buf), Err(()));
+        assert_eq!(handle.into_inner(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_routing_context() {
+        let firmware = file_contents(100);
+        let routes = vec![
+            ("fw/", TestContext::with_file("x", firmware.clone())),
+            ("cfg/", TestContext::default()),
+        ];
+        let routes = Box::leak(routes.into_boxed_slice());
+        let mut h = Harness::new(RoutingContext::new(&mut routes[..]));
+
+        // Reads are served by the context of the matching prefix
+        let sent = h.send(client(1), rrq("fw/x", Options::default()));
+        assert_eq!(
+            reply(&sent),
+            Repr::Data {
+                block_num: 1,
+                data: &firmware
+            }
+        );
+        h.send(client(1), Repr::Ack { block_num: 1 });
+
+        // So are writes, which are closed through the same context
+        h.send(client(2), wrq("cfg/y", Options::default()));
+        let sent = h.send(
+            client(2),
+            Repr::Data {
+                block_num: 1,
+                data: b"mtu=1500",
+            },
+        );
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 1 });
+        assert_eq!(h.context.routes()[1].1.file("y"), Some(&b"mtu=1500"[..]));
+        assert_eq!(h.context.routes()[0].1.file("y"), None);
+
+        // Other prefixes don't reach any context
+        let sent = h.send(client(3), rrq("log/z", Options::default()));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::FileNotFound);
+        let sent = h.send(client(3), rrq("x", Options::default()));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::FileNotFound);
+
+        // Listings get the prefix back
+        let mut buf = [0; 32];
+        let len = write_index(&h.context, &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"fw/x\ncfg/y\n");
+
+        // Keeping the prefix hands the full filename over
+        h.context.set_strip_prefix(false);
+        h.context.routes_mut()[0].1.files[0].0 = "fw/x".to_string();
+        let sent = h.send(client(4), rrq("fw/x", Options::default()));
+        assert_eq!(
+            reply(&sent),
+            Repr::Data {
+                block_num: 1,
+                data: &firmware
+            }
+        );
+
+        // Only files reachable through their route are listed
+        h.context.routes_mut()[1].1.files[0].0 = "fw/y".to_string();
+        let len = write_index(&h.context, &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"fw/x\n");
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn test_stats() {
+        let file = file_contents(1000);
+        let mut h = Harness::new(TestContext::with_file("file", file.clone()));
+
+        let request = rrq("file", blksize(300));
+        let request_len = request.buffer_len();
+        let sent = h.send(client(1), request);
+        assert_eq!(reply(&sent).buffer_len(), 14);
+
+        let mut largest = 0;
+        for block_num in 0..=4 {
+            let sent = h.send(client(1), Repr::Ack { block_num });
+            largest = sent
+                .iter()
+                .map(|d| d.payload.len())
+                .fold(largest, usize::max);
+
+            let stats = h
+                .transfers
+                .iter()
+                .flatten()
+                .next()
+                .map(Transfer::packet_stats);
+            if let Some(stats) = stats {
+                assert_eq!(stats.max_sent, largest);
+            }
+        }
+
+        assert_eq!(largest, DATA_HEADER_LEN + 300);
+        assert!(h.transfers.iter().all(Option::is_none));
+        assert_eq!(
+            h.server.packet_stats(),
+            PacketStats {
+                max_sent: largest,
+                // The request is the largest packet sent by the client
+                max_received: request_len,
+            }
+        );
+    }
+
+    #[test]
+    fn test_rejected_by_context() {
+        let mut context = TestContext::with_file("file", file_contents(100));
+        context.rejected = Some(ErrorCode::AccessViolation);
+        let mut h = Harness::new(context);
+
+        let sent = h.send(client(1), rrq("file", Options::default()));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::AccessViolation);
+
+        let sent = h.send(client(2), wrq("other", Options::default()));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::AccessViolation);
+
+        assert_eq!(h.context.opened, 0);
+        assert!(h.transfers.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn test_no_overwrite() {
+        let file = file_contents(100);
+        let mut h = Harness::new(TestContext::with_file("file", file.clone()));
+        h.server.set_overwrite(false);
+
+        let sent = h.send(client(1), wrq("file", Options::default()));
+        assert_eq!(error_code(reply(&sent)), ErrorCode::FileExists);
+        assert_eq!(h.context.opened, 0);
+        assert_eq!(h.context.file("file"), Some(&file[..]));
+
+        // New files can still be written
+        let sent = h.send(client(1), wrq("other", Options::default()));
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 0 });
+
+        // Overwriting is allowed by default
+        h.server.set_overwrite(true);
+        let sent = h.send(client(2), wrq("file", Options::default()));
+        assert_eq!(reply(&sent), Repr::Ack { block_num: 0 });
+    }
+
+    #[test]
+    #[cfg(feature = "trace-bytes")]
+    fn test_trace_bytes() {
+        mock::logger::install();
+
+        let mut h = Harness::new(TestContext::with_file("file", file_contents(10)));
+        h.send(client(1), rrq("file", Options::default()));
+
+        // RRQ for "file", answered with DATA #1
+        let lines = mock::logger::take();
+        assert!(lines
+            .iter()
+            .any(|l| l.starts_with("tftp: recv: [") && l.contains("] 00 01 66 69 6c 65 00")));
+        assert!(lines
+            .iter()
+            .any(|l| l.starts_with("tftp: send: [14] 00 03 00 01")));
+    }
 }
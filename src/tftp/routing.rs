@@ -0,0 +1,204 @@
+//! Dispatching requests to several contexts, based on the prefix of their filename.
+
+use super::{Context, ErrorCode, Handle};
+use crate::net::wire::IpEndpoint;
+use managed::ManagedSlice;
+
+/// Longest filename listed through a [`RoutingContext`], once its prefix is added back.
+///
+/// RFC 2347 limits request packets to 512 bytes, so longer names can't be requested anyway.
+///
+/// [`RoutingContext`]: struct.RoutingContext.html
+const MAX_LISTED_NAME_LEN: usize = 512;
+
+/// A [`Context`] routing each request to one of several contexts, based on the prefix
+/// of the requested filename.
+///
+/// This lets a single [`Server`] expose several logical namespaces, such as `fw/` backed by
+/// a flash partition and `cfg/` backed by RAM. Routes are matched in order, and the first one
+/// whose prefix starts the filename handles the request. Requests matching no route are
+/// answered with a `FileNotFound` error.
+///
+/// All the routed contexts share the same type: backends of different kinds can be combined
+/// by wrapping them into an `enum` implementing [`Context`].
+///
+/// By default, the prefix is stripped from the filename before it is handed over to the
+/// routed context, so that `fw/image.bin` is opened as `image.bin`. See
+/// [`set_strip_prefix()`]. Files listed by the routed contexts get their prefix back, and
+/// files that can't be requested through their route, e.g. because an earlier route shadows
+/// them, are left out of the listing.
+///
+/// [`Context`]: trait.Context.html
+/// [`Server`]: struct.Server.html
+/// [`set_strip_prefix()`]: #method.set_strip_prefix
+pub struct RoutingContext<'a, C> {
+    routes: ManagedSlice<'a, (&'a str, C)>,
+    strip_prefix: bool,
+}
+
+impl<'a, C: Context> RoutingContext<'a, C> {
+    /// Creates a context dispatching requests over a table of `(prefix, context)` routes.
+    pub fn new<R>(routes: R) -> Self
+    where
+        R: Into<ManagedSlice<'a, (&'a str, C)>>,
+    {
+        RoutingContext {
+            routes: routes.into(),
+            strip_prefix: true,
+        }
+    }
+
+    /// Sets whether the prefix of a route is removed from the filename passed to its context.
+    ///
+    /// Defaults to `true`. When `false`, the routed contexts see the filename as requested.
+    pub fn set_strip_prefix(&mut self, strip: bool) {
+        self.strip_prefix = strip;
+    }
+
+    /// Returns whether the prefix of a route is removed from the filename passed to its context.
+    pub fn strip_prefix(&self) -> bool {
+        self.strip_prefix
+    }
+
+    /// Returns the table of routes, e.g. to inspect the routed contexts.
+    pub fn routes(&self) -> &[(&'a str, C)] {
+        &self.routes
+    }
+
+    /// Returns the table of routes as mutable.
+    pub fn routes_mut(&mut self) -> &mut [(&'a str, C)] {
+        &mut self.routes
+    }
+
+    /// Finds the route for `filename`, returning its index and the filename as seen
+    /// by its context.
+    fn route<'f>(&self, filename: &'f str) -> Option<(usize, &'f str)> {
+        self.routes
+            .iter()
+            .position(|(prefix, _)| filename.starts_with(prefix))
+            .map(|index| match self.strip_prefix {
+                true => (index, &filename[self.routes[index].0.len()..]),
+                false => (index, filename),
+            })
+    }
+}
+
+impl<'a, C: Context> Context for RoutingContext<'a, C> {
+    type Handle = Routed<C::Handle>;
+
+    fn accept(&self, filename: &str, write_mode: bool) -> Result<(), ErrorCode> {
+        let (index, filename) = self.route(filename).ok_or(ErrorCode::FileNotFound)?;
+        self.routes[index].1.accept(filename, write_mode)
+    }
+
+    fn priority(&self, ep: IpEndpoint, filename: &str) -> u8 {
+        match self.route(filename) {
+            Some((index, filename)) => self.routes[index].1.priority(ep, filename),
+            None => 0,
+        }
+    }
+
+    fn exists(&self, filename: &str) -> bool {
+        match self.route(filename) {
+            Some((index, filename)) => self.routes[index].1.exists(filename),
+            None => false,
+        }
+    }
+
+    fn list(&self, entry: &mut dyn FnMut(&str)) {
+        for (index, (prefix, context)) in self.routes.iter().enumerate() {
+            context.list(&mut |name| {
+                let mut buf = [0; MAX_LISTED_NAME_LEN];
+                let filename = match self.strip_prefix {
+                    true => match join(&mut buf, prefix, name) {
+                        Some(filename) => filename,
+                        None => return,
+                    },
+                    false => name,
+                };
+                if let Some((route, _)) = self.route(filename) {
+                    if route == index {
+                        entry(filename);
+                    }
+                }
+            });
+        }
+    }
+
+    fn open(&mut self, filename: &str, write_mode: bool) -> Result<Self::Handle, ()> {
+        let (route, filename) = self.route(filename).ok_or(())?;
+        let handle = self.routes[route].1.open(filename, write_mode)?;
+        Ok(Routed { route, handle })
+    }
+
+    fn open_append(&mut self, filename: &str, offset: u64) -> Result<Self::Handle, ()> {
+        let (route, filename) = self.route(filename).ok_or(())?;
+        let handle = self.routes[route].1.open_append(filename, offset)?;
+        Ok(Routed { route, handle })
+    }
+
+    fn close(&mut self, handle: Self::Handle) {
+        self.routes[handle.route].1.close(handle.handle);
+    }
+
+    fn commit(&mut self, handle: Self::Handle) {
+        self.routes[handle.route].1.commit(handle.handle);
+    }
+
+    fn discard(&mut self, handle: Self::Handle) {
+        self.routes[handle.route].1.discard(handle.handle);
+    }
+}
+
+/// Concatenates `prefix` and `name` into `buf`, unless the result doesn't fit.
+fn join<'b>(buf: &'b mut [u8], prefix: &str, name: &str) -> Option<&'b str> {
+    let len = prefix.len() + name.len();
+    if len > buf.len() {
+        return None;
+    }
+    buf[..prefix.len()].copy_from_slice(prefix.as_bytes());
+    buf[prefix.len()..len].copy_from_slice(name.as_bytes());
+    core::str::from_utf8(&buf[..len]).ok()
+}
+
+/// A [`Handle`] opened through a [`RoutingContext`], remembering the route it belongs to.
+///
+/// [`Handle`]: trait.Handle.html
+/// [`RoutingContext`]: struct.RoutingContext.html
+pub struct Routed<H> {
+    route: usize,
+    handle: H,
+}
+
+impl<H> Routed<H> {
+    /// Returns the index of the route the handle was opened through.
+    pub fn route(&self) -> usize {
+        self.route
+    }
+}
+
+impl<H: Handle> Handle for Routed<H> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        self.handle.read(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        self.handle.write(buf)
+    }
+
+    fn write_block(&mut self, block_num: u16, buf: &[u8]) -> Result<usize, ()> {
+        self.handle.write_block(block_num, buf)
+    }
+
+    fn size(&self) -> Option<u64> {
+        self.handle.size()
+    }
+
+    fn is_writable(&self) -> bool {
+        self.handle.is_writable()
+    }
+
+    fn flush(&mut self) -> Result<(), ()> {
+        self.handle.flush()
+    }
+}
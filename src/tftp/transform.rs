@@ -0,0 +1,77 @@
+//! Streaming transformations applied to the contents of a transfer.
+
+use super::Handle;
+
+/// A streaming transformation sitting between the wire and a [`Handle`].
+///
+/// A `Transform` can be used to decompress a file while it is being read,
+/// or to compress it while it is being written, without the server being aware of it.
+/// No codec is provided by this crate, but any streaming implementation can be plugged in.
+///
+/// The default implementations pass the data through unchanged.
+///
+/// [`Handle`]: trait.Handle.html
+pub trait Transform<H: Handle> {
+    /// Fills `buf` with transformed data pulled from `inner`, returning how many bytes were read.
+    ///
+    /// Returning less than `buf.len()` bytes marks the end of the file, just like
+    /// [`Handle::read()`] does.
+    ///
+    /// [`Handle::read()`]: trait.Handle.html#tymethod.read
+    fn read(&mut self, inner: &mut H, buf: &mut [u8]) -> Result<usize, ()> {
+        inner.read(buf)
+    }
+
+    /// Transforms `buf` and pushes the result into `inner`, returning how many bytes
+    /// of `buf` were consumed.
+    fn write(&mut self, inner: &mut H, buf: &[u8]) -> Result<usize, ()> {
+        inner.write(buf)
+    }
+}
+
+/// A [`Handle`] whose contents go through a [`Transform`].
+///
+/// [`Handle`]: trait.Handle.html
+/// [`Transform`]: trait.Transform.html
+pub struct Transformed<H, T> {
+    inner: H,
+    transform: T,
+}
+
+impl<H, T> Transformed<H, T>
+where
+    H: Handle,
+    T: Transform<H>,
+{
+    /// Wraps `inner` so that its contents are processed by `transform`.
+    pub fn new(inner: H, transform: T) -> Self {
+        Transformed { inner, transform }
+    }
+
+    /// Returns the wrapped handle and the transformation, e.g. to flush them on close.
+    pub fn into_parts(self) -> (H, T) {
+        (self.inner, self.transform)
+    }
+}
+
+impl<H, T> Handle for Transformed<H, T>
+where
+    H: Handle,
+    T: Transform<H>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        self.transform.read(&mut self.inner, buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        self.transform.write(&mut self.inner, buf)
+    }
+
+    fn is_writable(&self) -> bool {
+        self.inner.is_writable()
+    }
+
+    fn flush(&mut self) -> Result<(), ()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,85 @@
+//! Transmission of UDP datagrams, independently of the socket they go through.
+
+use crate::net::{socket::UdpSocket, wire::IpEndpoint, Result};
+
+/// A queue of outgoing UDP datagrams.
+///
+/// This is implemented for smoltcp's `UdpSocket`. Integrations that don't own a `SocketSet`,
+/// such as a bridge from another network stack, can implement it to drive the protocols
+/// of this crate with raw datagrams, through their `process_datagram()` and
+/// `process_timeouts()` methods.
+pub trait Transmit {
+    /// Enqueues a datagram of `size` bytes for `endpoint`, returning the payload to fill in.
+    ///
+    /// Returns `Err(Error::Exhausted)` if there is no room left for the datagram.
+    fn send(&mut self, size: usize, endpoint: IpEndpoint) -> Result<&mut [u8]>;
+
+    /// Returns `true` if a datagram can be enqueued.
+    ///
+    /// The default implementation always returns `true`.
+    fn can_send(&self) -> bool {
+        true
+    }
+}
+
+impl<'a, 'b> Transmit for UdpSocket<'a, 'b> {
+    fn send(&mut self, size: usize, endpoint: IpEndpoint) -> Result<&mut [u8]> {
+        UdpSocket::send(self, size, endpoint)
+    }
+
+    fn can_send(&self) -> bool {
+        UdpSocket::can_send(self)
+    }
+}
+
+/// A policy deciding whether datagrams from a peer are processed, e.g. an allowlist of hosts.
+///
+/// Filters return `true` for the endpoints allowed to reach a client or server. Datagrams from
+/// any other endpoint are silently dropped, before being parsed.
+pub type EndpointFilter = fn(IpEndpoint) -> bool;
+
+/// The work done by a single poll of a client or server.
+///
+/// A main loop can use it to decide whether to poll again right away, e.g. because
+/// the receive budget was spent before the sockets were drained, or to go to sleep.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Activity {
+    /// Number of datagrams received and processed.
+    pub received: usize,
+    /// Number of datagrams enqueued for transmission.
+    pub sent: usize,
+}
+
+impl Activity {
+    /// Returns `true` if no datagram was received or sent.
+    pub fn is_idle(&self) -> bool {
+        self.received == 0 && self.sent == 0
+    }
+}
+
+/// A `Transmit` wrapper counting the datagrams enqueued through it.
+#[cfg(feature = "tftp")]
+pub(crate) struct Counted<'t, T> {
+    inner: &'t mut T,
+    pub sent: usize,
+}
+
+#[cfg(feature = "tftp")]
+impl<'t, T: Transmit> Counted<'t, T> {
+    pub fn new(inner: &'t mut T) -> Self {
+        Counted { inner, sent: 0 }
+    }
+}
+
+#[cfg(feature = "tftp")]
+impl<'t, T: Transmit> Transmit for Counted<'t, T> {
+    fn send(&mut self, size: usize, endpoint: IpEndpoint) -> Result<&mut [u8]> {
+        let payload = self.inner.send(size, endpoint)?;
+        self.sent += 1;
+        Ok(payload)
+    }
+
+    fn can_send(&self) -> bool {
+        self.inner.can_send()
+    }
+}
@@ -0,0 +1,43 @@
+//! Hexadecimal rendering of byte slices, without allocating.
+
+use core::fmt;
+
+/// Writes `bytes` as lowercase hex into `w`, separating groups of `group` bytes with a space.
+///
+/// A `group` of zero writes all the bytes without any separator, e.g. `0001ab`,
+/// while a `group` of two writes `0001 ab`.
+pub(crate) fn write_hex<W: fmt::Write + ?Sized>(
+    w: &mut W,
+    bytes: &[u8],
+    group: usize,
+) -> fmt::Result {
+    for (i, byte) in bytes.iter().enumerate() {
+        if i > 0 && group > 0 && i % group == 0 {
+            w.write_char(' ')?;
+        }
+        write!(w, "{:02x}", byte)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::string::String;
+
+    fn hex(bytes: &[u8], group: usize) -> String {
+        let mut s = String::new();
+        write_hex(&mut s, bytes, group).unwrap();
+        s
+    }
+
+    #[test]
+    fn test_write_hex() {
+        assert_eq!(hex(&[], 1), "");
+        assert_eq!(hex(&[0x00, 0x01, 0xab], 0), "0001ab");
+        assert_eq!(hex(&[0x00, 0x01, 0xab], 1), "00 01 ab");
+        assert_eq!(hex(&[0x00, 0x01, 0xab], 2), "0001 ab");
+        assert_eq!(hex(&[0x0f, 0xf0, 0x12, 0x34], 2), "0ff0 1234");
+        assert_eq!(hex(&[0xde, 0xad], 8), "dead");
+    }
+}
@@ -0,0 +1,3 @@
+//! Internal helpers shared by the protocol implementations.
+
+pub(crate) mod hex;
@@ -2,10 +2,16 @@
 
 The `wire` module deals with the packet *representation*.
 Refer to the [module-level documentation] in `smoltcp` for additional details.
+
+Most users will not need to access this module directly, as the protocol implementations
+take care of building and parsing packets. It is nonetheless exposed for advanced use cases,
+such as implementing a custom server on top of the same packet definitions.
+
+[module-level documentation]: https://docs.rs/smoltcp/0.6.0/smoltcp/wire/index.html
 */
 
 #[cfg(feature = "sntp")]
-pub(crate) mod sntp;
+pub mod sntp;
 
 #[cfg(feature = "tftp")]
 pub(crate) mod tftp;
@@ -1,18 +1,63 @@
 //! Wire protocol definitions for the Simple Network Time Protocol v4 (SNTPv4).
 //!
 //! See https://tools.ietf.org/html/rfc4330 for the SNTPv4 specification.
+//!
+//! # Examples
+//!
+//! Building a client request and parsing it back:
+//!
+//! ```rust
+//! use smolapps::wire::sntp::{LeapIndicator, Packet, ProtocolMode, Repr, Stratum, Timestamp};
+//!
+//! let repr = Repr {
+//!     leap_indicator: LeapIndicator::NoWarning,
+//!     version: 4,
+//!     protocol_mode: ProtocolMode::Client,
+//!     stratum: Stratum::KissOfDeath,
+//!     poll_interval: 0,
+//!     precision: 0,
+//!     root_delay: 0,
+//!     root_dispersion: 0,
+//!     ref_identifier: [0, 0, 0, 0],
+//!     ref_timestamp: Timestamp::default(),
+//!     orig_timestamp: Timestamp::default(),
+//!     recv_timestamp: Timestamp::default(),
+//!     xmit_timestamp: Timestamp { sec: 0xe26c_3511, frac: 0x6a8d_f88f },
+//! };
+//!
+//! let mut bytes = [0; 48];
+//! let mut packet = Packet::new_unchecked(&mut bytes[..]);
+//! repr.emit(&mut packet).unwrap();
+//!
+//! let packet = Packet::new_checked(&bytes[..]).unwrap();
+//! assert_eq!(packet.protocol_mode(), ProtocolMode::Client);
+//! assert_eq!(Repr::parse(&packet).unwrap(), repr);
+//! ```
 
+use crate::datetime;
 use byteorder::{ByteOrder, NetworkEndian};
-use core::convert;
-use smoltcp::{Error, Result};
+use core::{cmp::Ordering, convert, fmt, str};
+use smoltcp::{wire::Ipv4Address, Error, Result};
+
+/// Number of seconds between 1970 and Feb 7, 2036 06:28:16 UTC (epoch 1).
+/// Used for NTP to Unix timestamp conversion.
+pub(crate) const DIFF_SEC_1970_2036: u32 = 2_085_978_496;
+
+/// Number of nanoseconds in a second.
+const NANOS_PER_SEC: i128 = 1_000_000_000;
 
 /// The SNTP leap indicator field.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum LeapIndicator {
+    /// No leap second warning.
     NoWarning,
+    /// The last minute of the day has 61 seconds.
     LastMinute61Sec,
+    /// The last minute of the day has 59 seconds.
     LastMinute59Sec,
+    /// Alarm condition (clock not synchronized).
     AlarmCondition,
+    /// Any other value.
     Unknown(u8),
 }
 
@@ -45,14 +90,23 @@ impl convert::From<LeapIndicator> for u8 {
 /// Only unicast mode is supported at the time.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ProtocolMode {
+    /// Reserved mode.
     Reserved,
+    /// Symmetric active mode.
     SymmetricActive,
+    /// Symmetric passive mode.
     SymmetricPassive,
+    /// Client mode.
     Client,
+    /// Server mode.
     Server,
+    /// Broadcast mode.
     Broadcast,
+    /// Reserved for NTP control messages.
     NtpControlMessage,
+    /// Reserved for private use.
     Private,
+    /// Any other value.
     Unknown(u8),
 }
 
@@ -91,9 +145,13 @@ impl convert::From<ProtocolMode> for u8 {
 /// The SNTP stratum.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Stratum {
+    /// Unspecified or unavailable, used for Kiss-o'-Death messages.
     KissOfDeath,
+    /// Primary reference (e.g. radio clock).
     Primary,
+    /// Secondary reference (via NTP or SNTP), values 2 to 15.
     Secondary(u8),
+    /// Reserved, values 16 to 255.
     Reserved(u8),
 }
 
@@ -118,14 +176,88 @@ impl Into<u8> for Stratum {
     }
 }
 
+/// The meaning of the reference identifier of an SNTP packet, which depends on its stratum.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RefId<'a> {
+    /// ASCII code identifying the reference source of a primary server (e.g. `"GPS"`),
+    /// or the kiss code of a Kiss-o'-Death message (e.g. `"DENY"`).
+    Code(&'a str),
+    /// Address of the upstream server of a secondary server.
+    ///
+    /// For IPv6 upstream servers, this is the first four octets of the MD5 hash of the address
+    /// instead, which cannot be told apart from an IPv4 address.
+    Addr(Ipv4Address),
+    /// An identifier that could not be interpreted, e.g. a non-ASCII code or a reserved stratum.
+    Unknown([u8; 4]),
+}
+
 /// An SNTP timestamp, represented as integer and fractional part.
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
 pub struct Timestamp {
-    pub(crate) sec: u32,
-    pub(crate) frac: u32,
+    /// Number of seconds since the beginning of the current NTP era.
+    pub sec: u32,
+    /// Fractional part of the second, in units of 2^-32 seconds.
+    pub frac: u32,
 }
 
 impl Timestamp {
+    /// Converts this timestamp to the number of nanoseconds since the Unix epoch.
+    ///
+    /// NTP timestamps are ambiguous as to which era they belong to. Timestamps are assumed
+    /// to fall between 1970 and 2106, ie. seconds values preceding the Unix epoch in era 0
+    /// are taken to belong to era 1 (starting Feb 7, 2036).
+    pub fn to_unix_nanos(self) -> i128 {
+        let secs = i128::from(self.sec.wrapping_add(DIFF_SEC_1970_2036));
+        let nanos = (i128::from(self.frac) * NANOS_PER_SEC) >> 32;
+        secs * NANOS_PER_SEC + nanos
+    }
+
+    /// Creates a timestamp from the number of nanoseconds since the Unix epoch.
+    ///
+    /// The integer part is taken modulo 2^32, so times outside the 1970-2106 range
+    /// are mapped to the corresponding NTP era, as per [`to_unix_nanos`].
+    /// The fractional part is rounded up, so that converting back yields the original value.
+    ///
+    /// [`to_unix_nanos`]: #method.to_unix_nanos
+    pub fn from_unix_nanos(nanos: i128) -> Timestamp {
+        let secs = nanos.div_euclid(NANOS_PER_SEC);
+        let rem = nanos.rem_euclid(NANOS_PER_SEC);
+
+        let sec = (secs.rem_euclid(1 << 32) as u32).wrapping_sub(DIFF_SEC_1970_2036);
+        let frac = (((rem << 32) + NANOS_PER_SEC - 1) / NANOS_PER_SEC) as u32;
+
+        Timestamp { sec, frac }
+    }
+
+    /// Returns the timestamp as a 64-bit fixed-point value, with the integer part
+    /// in the upper 32 bits.
+    pub fn to_bits(self) -> u64 {
+        (u64::from(self.sec) << 32) | u64::from(self.frac)
+    }
+
+    /// Compares two timestamps, taking the era rollover into account.
+    ///
+    /// As per RFC 5905, timestamps are compared through their 64-bit difference, so that
+    /// the result is correct as long as they are less than 68 years apart, even across eras.
+    /// This is deliberately not an `Ord` implementation, since the relation is not transitive.
+    pub fn cmp_wrapping(self, other: Timestamp) -> Ordering {
+        (self.to_bits().wrapping_sub(other.to_bits()) as i64).cmp(&0)
+    }
+
+    /// Returns whether this timestamp is later than `other`, as per [`cmp_wrapping`].
+    ///
+    /// [`cmp_wrapping`]: #method.cmp_wrapping
+    pub fn is_after(self, other: Timestamp) -> bool {
+        self.cmp_wrapping(other) == Ordering::Greater
+    }
+
+    /// Returns whether this timestamp is earlier than `other`, as per [`cmp_wrapping`].
+    ///
+    /// [`cmp_wrapping`]: #method.cmp_wrapping
+    pub fn is_before(self, other: Timestamp) -> bool {
+        self.cmp_wrapping(other) == Ordering::Less
+    }
+
     fn parse(buffer: &[u8]) -> Result<Timestamp> {
         let sec = NetworkEndian::read_u32(buffer.get(0..4).ok_or(Error::Truncated)?);
         let frac = NetworkEndian::read_u32(buffer.get(4..8).ok_or(Error::Truncated)?);
@@ -138,6 +270,29 @@ impl Timestamp {
     }
 }
 
+/// Formats the timestamp as an RFC 3339 date and time in UTC, e.g. `2020-05-17T22:11:29.416228804Z`,
+/// with the same era assumptions as `to_unix_nanos()`.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let nanos = self.to_unix_nanos();
+        let secs = (nanos / NANOS_PER_SEC) as i64;
+        let nsecs = (nanos % NANOS_PER_SEC) as u32;
+        datetime::format_rfc3339(f, secs, nsecs)
+    }
+}
+
+/// Converts the timestamp to civil time, with the same era assumptions as `to_unix_nanos()`.
+#[cfg(feature = "chrono")]
+impl From<Timestamp> for chrono::DateTime<chrono::Utc> {
+    fn from(ts: Timestamp) -> Self {
+        let nanos = ts.to_unix_nanos();
+        let secs = (nanos / NANOS_PER_SEC) as i64;
+        let nsecs = (nanos % NANOS_PER_SEC) as u32;
+        // Timestamps between 1970 and 2106 are always in range
+        chrono::DateTime::from_timestamp(secs, nsecs).unwrap()
+    }
+}
+
 /// A read/write wrapper around a Simple Network Time Protocol v4 packet buffer.
 #[derive(Debug, PartialEq)]
 pub struct Packet<T: AsRef<[u8]>> {
@@ -193,8 +348,6 @@ impl<T: AsRef<[u8]>> Packet<T> {
 
     /// Ensure that no accessor method will panic if called.
     /// Returns `Err(Error::Truncated)` if the buffer is too short.
-    ///
-    /// [set_header_len]: #method.set_header_len
     pub fn check_len(&self) -> Result<()> {
         let len = self.buffer.as_ref().len();
         if len < field::TRANSMIT_TIMESTAMP.end {
@@ -204,6 +357,11 @@ impl<T: AsRef<[u8]>> Packet<T> {
         }
     }
 
+    /// Consumes the packet, returning the underlying buffer.
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+
     /// Returns the leap indicator of this packet.
     pub fn leap_indicator(&self) -> LeapIndicator {
         let data = self.buffer.as_ref();
@@ -286,14 +444,16 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
         let data = self.buffer.as_mut();
         let li: u8 = li.into();
         data[field::LI_VN_MODE] &= !field::LI_MASK;
-        data[field::LI_VN_MODE] |= li << field::LI_SHIFT;
+        data[field::LI_VN_MODE] |= (li << field::LI_SHIFT) & field::LI_MASK;
     }
 
     /// Sets the version number for this packet.
+    ///
+    /// Only the three least significant bits of `vn` are stored.
     pub fn set_version(&mut self, vn: u8) {
         let data = self.buffer.as_mut();
         data[field::LI_VN_MODE] &= !field::VN_MASK;
-        data[field::LI_VN_MODE] |= vn << field::VN_SHIFT;
+        data[field::LI_VN_MODE] |= (vn << field::VN_SHIFT) & field::VN_MASK;
     }
 
     /// Sets the protocol mode for this packet.
@@ -301,7 +461,7 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
         let data = self.buffer.as_mut();
         let mode: u8 = mode.into();
         data[field::LI_VN_MODE] &= !field::MODE_MASK;
-        data[field::LI_VN_MODE] |= mode << field::MODE_SHIFT;
+        data[field::LI_VN_MODE] |= (mode << field::MODE_SHIFT) & field::MODE_MASK;
     }
 
     /// Sets the stratum for this packet.
@@ -440,6 +600,28 @@ pub struct Repr {
 }
 
 impl Repr {
+    /// Interprets the reference identifier according to the stratum.
+    ///
+    /// Codes shorter than four characters are padded with NULL bytes, which are stripped.
+    pub fn ref_id(&self) -> RefId<'_> {
+        match self.stratum {
+            Stratum::KissOfDeath | Stratum::Primary => {
+                let len = self
+                    .ref_identifier
+                    .iter()
+                    .position(|b| *b == 0)
+                    .unwrap_or(self.ref_identifier.len());
+                let code = &self.ref_identifier[..len];
+                match str::from_utf8(code) {
+                    Ok(code) if code.bytes().all(|b| b.is_ascii_graphic()) => RefId::Code(code),
+                    _ => RefId::Unknown(self.ref_identifier),
+                }
+            }
+            Stratum::Secondary(_) => RefId::Addr(Ipv4Address(self.ref_identifier)),
+            Stratum::Reserved(_) => RefId::Unknown(self.ref_identifier),
+        }
+    }
+
     /// Return the length of a packet that will be emitted
     /// from this high-level representation.
     pub fn buffer_len(&self) -> usize {
@@ -495,7 +677,7 @@ impl Repr {
 mod test {
     use super::*;
 
-    use std::vec;
+    use std::{string::ToString, vec};
 
     static PACKET_BYTES: [u8; 48] = [
         0x24, 0x02, 0x00, 0xe6, 0x00, 0x00, 0x01, 0x20, 0x00, 0x00, 0x00, 0x6f, 0x50, 0x42, 0xe0,
@@ -615,6 +797,106 @@ mod test {
         assert_eq!(repr, packet_repr());
     }
 
+    #[test]
+    fn test_unix_nanos() {
+        let ts = Timestamp {
+            sec: 0xe26c_3511,
+            frac: 0x6a8d_f88f,
+        };
+        assert_eq!(ts.to_unix_nanos(), 1_589_753_489_416_228_804);
+
+        // Half a second past the Unix epoch
+        let ts = Timestamp {
+            sec: 2_208_988_800,
+            frac: 0x8000_0000,
+        };
+        assert_eq!(ts.to_unix_nanos(), 500_000_000);
+        assert_eq!(Timestamp::from_unix_nanos(500_000_000), ts);
+
+        // Start of era 1
+        let ts = Timestamp { sec: 0, frac: 0 };
+        assert_eq!(ts.to_unix_nanos(), 2_085_978_496 * 1_000_000_000);
+        assert_eq!(
+            Timestamp::from_unix_nanos(2_085_978_496 * 1_000_000_000),
+            ts
+        );
+
+        // Nanosecond round-trip, before and after the era rollover
+        for nanos in [
+            1,
+            999_999_999,
+            1_589_753_489_416_228_804,
+            2_085_978_495_999_999_999,
+            2_085_978_496_000_000_001,
+            4_294_967_295_999_999_999,
+        ]
+        .iter()
+        {
+            assert_eq!(Timestamp::from_unix_nanos(*nanos).to_unix_nanos(), *nanos);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let ts = Timestamp {
+            sec: 0xe26c_3511,
+            frac: 0x6a8d_f88f,
+        };
+        assert_eq!(ts.to_string(), "2020-05-17T22:11:29.416228804Z");
+
+        // Start of era 1
+        let ts = Timestamp { sec: 0, frac: 0 };
+        assert_eq!(ts.to_string(), "2036-02-07T06:28:16Z");
+    }
+
+    #[test]
+    fn test_cmp_wrapping() {
+        let early = Timestamp { sec: 10, frac: 0 };
+        let late = Timestamp {
+            sec: 10,
+            frac: 0x8000_0000,
+        };
+
+        // Same era: seconds first, then fraction
+        assert_eq!(early.cmp_wrapping(late), Ordering::Less);
+        assert_eq!(late.cmp_wrapping(early), Ordering::Greater);
+        assert_eq!(early.cmp_wrapping(early), Ordering::Equal);
+        assert!(Timestamp { sec: 11, frac: 0 }.is_after(late));
+        assert!(!early.is_after(early) && !early.is_before(early));
+
+        // Cross era: the end of era 0 precedes the start of era 1
+        let end_of_era = Timestamp {
+            sec: 0xffff_fff0,
+            frac: 0xffff_ffff,
+        };
+        assert!(end_of_era.is_before(early));
+        assert!(early.is_after(end_of_era));
+        assert_eq!(end_of_era.to_bits(), 0xffff_fff0_ffff_ffff);
+    }
+
+    #[test]
+    #[cfg(feature = "chrono")]
+    fn test_chrono() {
+        use chrono::{DateTime, NaiveDate, Utc};
+
+        let ts = Timestamp {
+            sec: 0xe26c_3511,
+            frac: 0x6a8d_f88f,
+        };
+        let dt: DateTime<Utc> = ts.into();
+        let expected = NaiveDate::from_ymd_opt(2020, 5, 17)
+            .and_then(|d| d.and_hms_nano_opt(22, 11, 29, 416_228_804))
+            .unwrap();
+        assert_eq!(dt.naive_utc(), expected);
+
+        // Start of era 1
+        let dt: DateTime<Utc> = Timestamp { sec: 0, frac: 0 }.into();
+        let expected = NaiveDate::from_ymd_opt(2036, 2, 7)
+            .and_then(|d| d.and_hms_opt(6, 28, 16))
+            .unwrap();
+        assert_eq!(dt.naive_utc(), expected);
+    }
+
     #[test]
     fn test_emit() {
         let mut bytes = vec![0xa5; 48];
@@ -622,4 +904,135 @@ mod test {
         packet_repr().emit(&mut packet).unwrap();
         assert_eq!(&packet.buffer[..], &PACKET_BYTES[..]);
     }
+
+    /// Emits `repr` and parses it back.
+    fn round_trip(repr: Repr) -> Repr {
+        let mut bytes = vec![0xa5; repr.buffer_len()];
+        repr.emit(&mut Packet::new_unchecked(&mut bytes)).unwrap();
+        Repr::parse(&Packet::new_checked(&bytes).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(round_trip(packet_repr()), packet_repr());
+
+        // Every combination of the bit fields sharing the first octet
+        for li in 0..=3 {
+            for version in 0..=7 {
+                for mode in 0..=7 {
+                    let repr = Repr {
+                        leap_indicator: LeapIndicator::from(li),
+                        version,
+                        protocol_mode: ProtocolMode::from(mode),
+                        ..packet_repr()
+                    };
+                    assert_eq!(round_trip(repr), repr);
+                }
+            }
+        }
+
+        for stratum in 0..=255 {
+            let repr = Repr {
+                stratum: Stratum::from(stratum),
+                ..packet_repr()
+            };
+            assert_eq!(round_trip(repr), repr);
+        }
+
+        // Signed and unsigned extremes of the other fields
+        let extremes = [
+            (0, i8::MIN, i32::MIN, 0),
+            (0xff, i8::MAX, i32::MAX, u32::MAX),
+            (0x80, -1, -1, 0x8000_0000),
+        ];
+        for &(poll_interval, precision, root_delay, root_dispersion) in extremes.iter() {
+            let repr = Repr {
+                poll_interval,
+                precision,
+                root_delay,
+                root_dispersion,
+                ref_identifier: root_dispersion.to_be_bytes(),
+                ..packet_repr()
+            };
+            assert_eq!(round_trip(repr), repr);
+        }
+
+        // Timestamps at the edges of an era, in each of the four slots
+        let timestamps = [
+            Timestamp { sec: 0, frac: 0 },
+            Timestamp { sec: 0, frac: 1 },
+            Timestamp {
+                sec: u32::MAX,
+                frac: u32::MAX,
+            },
+            Timestamp {
+                sec: 0x8000_0000,
+                frac: 0x8000_0000,
+            },
+        ];
+        for (i, ts) in timestamps.iter().enumerate() {
+            let repr = Repr {
+                ref_timestamp: *ts,
+                orig_timestamp: timestamps[(i + 1) % 4],
+                recv_timestamp: timestamps[(i + 2) % 4],
+                xmit_timestamp: timestamps[(i + 3) % 4],
+                ..packet_repr()
+            };
+            assert_eq!(round_trip(repr), repr);
+        }
+    }
+
+    #[test]
+    fn test_emit_out_of_range() {
+        // Values too wide for their bit field don't spill over into the neighbouring ones
+        let repr = Repr {
+            leap_indicator: LeapIndicator::Unknown(0xff),
+            version: 0xff,
+            protocol_mode: ProtocolMode::Unknown(0xff),
+            ..packet_repr()
+        };
+        let parsed = round_trip(repr);
+        assert_eq!(parsed.leap_indicator, LeapIndicator::AlarmCondition);
+        assert_eq!(parsed.version, 7);
+        assert_eq!(parsed.protocol_mode, ProtocolMode::Private);
+
+        let repr = Repr {
+            version: 8,
+            protocol_mode: ProtocolMode::Unknown(8),
+            ..packet_repr()
+        };
+        let parsed = round_trip(repr);
+        assert_eq!(parsed.leap_indicator, LeapIndicator::NoWarning);
+        assert_eq!(parsed.version, 0);
+        assert_eq!(parsed.protocol_mode, ProtocolMode::Reserved);
+    }
+
+    #[test]
+    fn test_ref_id() {
+        // Secondary servers report their upstream server
+        let repr = packet_repr();
+        assert_eq!(repr.ref_id(), RefId::Addr(Ipv4Address::new(80, 66, 224, 2)));
+
+        // Primary servers report their reference source
+        let repr = Repr {
+            stratum: Stratum::Primary,
+            ref_identifier: *b"GPS\0",
+            ..packet_repr()
+        };
+        assert_eq!(repr.ref_id(), RefId::Code("GPS"));
+
+        let repr = Repr {
+            stratum: Stratum::KissOfDeath,
+            ref_identifier: *b"RATE",
+            ..packet_repr()
+        };
+        assert_eq!(repr.ref_id(), RefId::Code("RATE"));
+
+        let repr = Repr {
+            stratum: Stratum::Primary,
+            ref_identifier: [0xff, 0x00, 0x00, 0x00],
+            ..packet_repr()
+        };
+        assert_eq!(repr.ref_id(), RefId::Unknown([0xff, 0x00, 0x00, 0x00]));
+    }
 }
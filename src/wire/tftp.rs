@@ -1,9 +1,9 @@
 //! Wire protocol definitions for the Trivial File Transfer Protocol (TFTP).
 //!
-//! See https://tools.ietf.org/html/rfc1350 for the TFTP specification.
-
-// TODO: remove me once the TFTP client has been implemented!
-#![allow(unused)]
+//! See https://tools.ietf.org/html/rfc1350 for the TFTP specification,
+//! and https://tools.ietf.org/html/rfc2347 for the option extension.
+//! Supported options are defined in https://tools.ietf.org/html/rfc2348
+//! and https://tools.ietf.org/html/rfc2349.
 
 use byteorder::{ByteOrder, NetworkEndian};
 use core::str;
@@ -11,39 +11,65 @@ use smoltcp::{Error, Result};
 
 enum_with_unknown! {
     /// One of the possible operations supported by TFTP.
-    pub enum OpCode(u16) {
+    pub doc enum OpCode(u16) {
+        /// Read request (RRQ).
         Read = 1,
+        /// Write request (WRQ).
         Write = 2,
+        /// Data block (DATA).
         Data = 3,
+        /// Acknowledgment of a data block (ACK).
         Ack = 4,
+        /// Error (ERROR).
         Error = 5,
+        /// Option acknowledgment (OACK), see [RFC 2347](https://tools.ietf.org/html/rfc2347).
+        OptionAck = 6,
     }
 }
 
 enum_with_unknown! {
     /// One of the possible error codes found in a TFTP error packet.
-    pub enum ErrorCode(u16) {
+    pub doc enum ErrorCode(u16) {
+        /// Not defined, see the error message (if any).
         Undefined = 0,
+        /// File not found.
         FileNotFound = 1,
+        /// Access violation.
         AccessViolation = 2,
+        /// Disk full or allocation exceeded.
         DiskFull = 3,
+        /// Illegal TFTP operation.
         IllegalOperation = 4,
+        /// Unknown transfer ID.
         UnknownID = 5,
+        /// File already exists.
         FileExists = 6,
+        /// No such user.
         NoSuchUser = 7,
+        /// Transfer terminated because of the options, see [RFC 2347](https://tools.ietf.org/html/rfc2347).
+        OptionNegotiation = 8,
     }
 }
 
 /// One of the possible operating modes supported by TFTP.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Mode {
+    /// Text, with line endings converted to CR LF.
     NetAscii,
+    /// Raw bytes.
     Octet,
+    /// Text sent to a user rather than a file, obsoleted by RFC 1350.
     Mail,
+    /// Any other mode.
     Unknown,
 }
 
 impl Mode {
+    /// Returns all the modes defined by the specification, i.e. all but `Unknown`.
+    pub fn all() -> &'static [Mode] {
+        &[Mode::NetAscii, Mode::Octet, Mode::Mail]
+    }
+
     /// Returns the string representation of this `Mode`.
     pub fn as_str(self) -> &'static str {
         match self {
@@ -53,8 +79,38 @@ impl Mode {
             Mode::Unknown => "",
         }
     }
+
+    /// Identifies a mode from its full name, ignoring case as RFC 1350 requires.
+    ///
+    /// Unlike `Mode::from(u8)`, which only looks at the first character, names other than
+    /// `netascii`, `octet` or `mail` yield `Mode::Unknown`.
+    pub fn from_name(name: &[u8]) -> Mode {
+        Mode::all()
+            .iter()
+            .find(|mode| name.eq_ignore_ascii_case(mode.as_str().as_bytes()))
+            .copied()
+            .unwrap_or(Mode::Unknown)
+    }
+
+    /// Identifies an obsolete name of octet mode, ignoring case, returning it in lowercase.
+    ///
+    /// Some legacy clients send `binary` or `image` instead of `octet`. These names are not
+    /// recognized by either parse mode, and yield `Mode::Unknown` when parsing packets:
+    /// this lets servers opt into accepting them.
+    pub fn octet_alias(name: &[u8]) -> Option<&'static str> {
+        OCTET_ALIASES
+            .iter()
+            .find(|alias| name.eq_ignore_ascii_case(alias.as_bytes()))
+            .copied()
+    }
 }
 
+/// Obsolete names of octet mode, as sent by legacy clients.
+const OCTET_ALIASES: &[&str] = &["binary", "image"];
+
+/// Identifies a mode from the first character of its name, in either case.
+///
+/// This is how mode strings are interpreted in `ParseMode::Lenient`.
 impl From<u8> for Mode {
     fn from(b: u8) -> Self {
         match b {
@@ -65,6 +121,205 @@ impl From<u8> for Mode {
         }
     }
 }
+
+/// How tolerant packet parsing is of deviations from the specification.
+///
+/// Some clients in the wild emit slightly malformed packets, which are accepted
+/// in `Lenient` mode, which is used by default. `Strict` mode rejects them instead.
+///
+/// Deviations tolerated in `Lenient` mode only:
+///
+/// * a request whose mode string is missing its NULL terminator, in which case the mode
+///   extends to the end of the packet;
+/// * a mode string other than `netascii`, `octet` or `mail`, which is identified
+///   from its first character alone;
+/// * a last option missing its NULL terminator, or missing its value altogether
+///   (in which case it is ignored);
+/// * an error message missing its NULL terminator, in which case the message
+///   extends to the end of the packet;
+/// * bytes following the NULL terminator of an error message, which are ignored.
+///
+/// The filename of a request must be NULL-terminated in both modes,
+/// since it could not be told apart from the mode string otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Accept the deviations listed above.
+    Lenient,
+    /// Reject any deviation from the specification.
+    Strict,
+}
+
+/// A field of a TFTP packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// Operation code, shared by all packets.
+    Opcode,
+    /// Filename of a request.
+    Filename,
+    /// Mode string of a request.
+    Mode,
+    /// Options of a request or option acknowledgment.
+    Options,
+    /// Block number of a DATA or ACK packet.
+    BlockNumber,
+    /// Error code of an error packet.
+    ErrorCode,
+    /// Error message of an error packet.
+    ErrorMessage,
+}
+
+/// A parsing error, naming the field of the packet at fault.
+///
+/// This is returned by the `_verbose` variants of the parsing functions, for debugging
+/// purposes: it converts to the `Error` that the regular ones return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// The packet ends before or within the field, see `Error::Truncated`.
+    Truncated(Field),
+    /// The field is invalid, see `Error::Malformed`.
+    Malformed(Field),
+}
+
+impl ParseError {
+    /// Returns the field at fault.
+    pub fn field(self) -> Field {
+        match self {
+            ParseError::Truncated(field) | ParseError::Malformed(field) => field,
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        match err {
+            ParseError::Truncated(_) => Error::Truncated,
+            ParseError::Malformed(_) => Error::Malformed,
+        }
+    }
+}
+
+/// Name of the block size option, see [RFC 2348](https://tools.ietf.org/html/rfc2348).
+const OPT_BLOCK_SIZE: &str = "blksize";
+
+/// Name of the transfer size option, see [RFC 2349](https://tools.ietf.org/html/rfc2349).
+const OPT_TRANSFER_SIZE: &str = "tsize";
+
+/// Name of the non-standard option used to resume an interrupted write.
+const OPT_OFFSET: &str = "offset";
+
+/// The set of options that can be appended to a request or an option acknowledgment.
+///
+/// Options are encoded as a sequence of NULL-terminated name/value pairs,
+/// where the value is a decimal number in ASCII representation.
+/// Unrecognized options and options with a malformed value are ignored when parsing.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Options {
+    /// Number of data bytes carried by each DATA packet.
+    pub block_size: Option<u16>,
+    /// Total size of the file being transferred, in bytes.
+    pub transfer_size: Option<u64>,
+    /// Number of bytes of the file already stored by the server, when resuming a write.
+    ///
+    /// This is not a standard option: the first DATA block carries the bytes following `offset`.
+    pub offset: Option<u64>,
+}
+
+impl Options {
+    /// Returns `true` if no option is set.
+    pub fn is_empty(&self) -> bool {
+        *self == Options::default()
+    }
+
+    /// Return the length of the encoded options.
+    pub fn buffer_len(&self) -> usize {
+        self.pairs()
+            .iter()
+            .filter_map(|(name, value)| value.map(|v| name.len() + 1 + decimal_len(v) + 1))
+            .sum()
+    }
+
+    /// Returns the name/value pairs of all options, in their encoding order.
+    fn pairs(&self) -> [(&'static str, Option<u64>); 3] {
+        [
+            (OPT_BLOCK_SIZE, self.block_size.map(u64::from)),
+            (OPT_TRANSFER_SIZE, self.transfer_size),
+            (OPT_OFFSET, self.offset),
+        ]
+    }
+
+    /// Parses a sequence of name/value pairs.
+    fn parse(buffer: &[u8]) -> Options {
+        let mut options = Options::default();
+        let mut fields = buffer.split(|b| *b == 0);
+
+        while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+            let value = match parse_decimal(value) {
+                Some(value) => value,
+                None => continue,
+            };
+            if name.eq_ignore_ascii_case(OPT_BLOCK_SIZE.as_bytes()) {
+                options.block_size = Some(value as u16).filter(|v| u64::from(*v) == value);
+            } else if name.eq_ignore_ascii_case(OPT_TRANSFER_SIZE.as_bytes()) {
+                options.transfer_size = Some(value);
+            } else if name.eq_ignore_ascii_case(OPT_OFFSET.as_bytes()) {
+                options.offset = Some(value);
+            }
+        }
+
+        options
+    }
+
+    /// Emits the options as a sequence of name/value pairs, returning the number of bytes written.
+    fn emit(&self, buffer: &mut [u8]) -> usize {
+        let mut offset = 0;
+        for (name, value) in self.pairs().iter() {
+            if let Some(v) = value {
+                offset += emit_option(&mut buffer[offset..], name, *v);
+            }
+        }
+        offset
+    }
+}
+
+/// Emits a single name/value pair, returning the number of bytes written.
+fn emit_option(buffer: &mut [u8], name: &str, value: u64) -> usize {
+    let name_end = name.len();
+    let value_end = name_end + 1 + decimal_len(value);
+
+    buffer[..name_end].copy_from_slice(name.as_bytes());
+    buffer[name_end] = 0;
+
+    let mut v = value;
+    for b in buffer[name_end + 1..value_end].iter_mut().rev() {
+        *b = b'0' + (v % 10) as u8;
+        v /= 10;
+    }
+    buffer[value_end] = 0;
+
+    value_end + 1
+}
+
+/// Returns the number of digits of the decimal representation of `value`.
+fn decimal_len(mut value: u64) -> usize {
+    let mut len = 1;
+    while value >= 10 {
+        value /= 10;
+        len += 1;
+    }
+    len
+}
+
+/// Parses an unsigned ASCII decimal number.
+fn parse_decimal(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |acc, b| match b {
+        b'0'..=b'9' => acc.checked_mul(10)?.checked_add(u64::from(b - b'0')),
+        _ => None,
+    })
+}
+
 /// A read/write wrapper around a Simple Network Time Protocol v4 packet buffer.
 #[derive(Debug, Eq, PartialEq)]
 pub struct Packet<T: AsRef<[u8]>> {
@@ -98,38 +353,97 @@ impl<T: AsRef<[u8]>> Packet<T> {
         Packet { buffer }
     }
 
-    /// Shorthand for a combination of [new_unchecked] and [check_len].
+    /// Shorthand for a combination of [new_unchecked] and [check_len_with], parsing leniently.
     ///
     /// [new_unchecked]: #method.new_unchecked
-    /// [check_len]: #method.check_len
+    /// [check_len_with]: #method.check_len_with
     pub fn new_checked(buffer: T) -> Result<Packet<T>> {
+        Self::new_checked_with(buffer, ParseMode::Lenient)
+    }
+
+    /// Same as [new_checked], using the given parsing mode.
+    ///
+    /// [new_checked]: #method.new_checked
+    pub fn new_checked_with(buffer: T, mode: ParseMode) -> Result<Packet<T>> {
         let packet = Self::new_unchecked(buffer);
-        packet.check_len()?;
+        packet.check_len_with(mode)?;
         Ok(packet)
     }
 
     /// Ensures that no accessor method will panic if called.
-    /// Returns `Err(Error::Truncated)` if the buffer is too short.
+    /// Returns `Err(Error::Truncated)` if the buffer is too short,
+    /// and `Err(Error::Malformed)` if a filename or error message is not valid UTF-8.
     ///
-    /// [set_header_len]: #method.set_header_len
-    pub fn check_len(&self) -> Result<()> {
+    /// Packets with an unknown opcode only need to carry the opcode itself to pass this check,
+    /// since their layout is not known. Rejecting them is left to [`Repr::parse`].
+    ///
+    /// In `Strict` mode, `Err(Error::Malformed)` is also returned for error messages
+    /// followed by extra bytes.
+    ///
+    /// [`Repr::parse`]: enum.Repr.html#method.parse
+    pub fn check_len_with(&self, mode: ParseMode) -> Result<()> {
+        self.check_len_verbose(mode).map_err(Error::from)
+    }
+
+    /// Same as [check_len_with], naming the field at fault in case of error.
+    ///
+    /// [check_len_with]: #method.check_len_with
+    pub fn check_len_verbose(&self, mode: ParseMode) -> core::result::Result<(), ParseError> {
+        use self::ParseError::*;
+
         let len = self.buffer.as_ref().len();
         if len < field::OPCODE.end {
-            Err(Error::Truncated)
-        } else {
-            let end = match self.opcode() {
-                OpCode::Read | OpCode::Write | OpCode::Error => self.find_last_null_byte()?,
-                OpCode::Data | OpCode::Ack => field::BLOCK.end,
-                OpCode::Unknown(_) => return Err(Error::Malformed),
-            };
-            if len < end {
-                Err(Error::Truncated)
-            } else {
-                Ok(())
+            return Err(Truncated(Field::Opcode));
+        }
+
+        match self.opcode() {
+            OpCode::Read | OpCode::Write => {
+                // The filename must be NULL-terminated, the mode only in strict mode
+                let filename_end = self
+                    .find_null_byte(field::OPCODE.end)
+                    .map_err(|_| Truncated(Field::Filename))?;
+                let filename = &self.buffer.as_ref()[field::OPCODE.end..filename_end - 1];
+                if str::from_utf8(filename).is_err() {
+                    return Err(Malformed(Field::Filename));
+                }
+                match self.find_null_byte(filename_end) {
+                    Err(_) if mode == ParseMode::Lenient && filename_end < len => Ok(()),
+                    res => res.map(|_| ()).map_err(|_| Truncated(Field::Mode)),
+                }
+            }
+            OpCode::Error => {
+                if len < field::ERROR_CODE.end {
+                    return Err(Truncated(Field::ErrorCode));
+                }
+                // Only look past the error code, whose high byte may be zero
+                let msg_end = self
+                    .find_null_byte(field::ERROR_STRING.start)
+                    .map_or(len, |end| end - 1);
+                let msg = &self.buffer.as_ref()[field::ERROR_STRING.start..msg_end];
+                if str::from_utf8(msg).is_err() {
+                    return Err(Malformed(Field::ErrorMessage));
+                }
+                match self.find_null_byte(field::ERROR_STRING.start) {
+                    Err(_) if mode == ParseMode::Lenient => Ok(()),
+                    Err(_) => Err(Truncated(Field::ErrorMessage)),
+                    Ok(msg_end) if mode == ParseMode::Strict && msg_end < len => {
+                        Err(Malformed(Field::ErrorMessage))
+                    }
+                    Ok(_) => Ok(()),
+                }
             }
+            OpCode::Data | OpCode::Ack if len < field::BLOCK.end => {
+                Err(Truncated(Field::BlockNumber))
+            }
+            _ => Ok(()),
         }
     }
 
+    /// Consumes the packet, returning the underlying buffer.
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+
     /// Returns the OpCode of this packet.
     pub fn opcode(&self) -> OpCode {
         NetworkEndian::read_u16(&self.buffer.as_ref()[field::OPCODE]).into()
@@ -156,14 +470,49 @@ impl<T: AsRef<[u8]>> Packet<T> {
         self.buffer.as_ref()[start].into()
     }
 
+    /// Returns the raw mode string of this packet, without its NULL terminator.
+    pub fn mode_str(&self) -> &[u8] {
+        let start = field::OPCODE.end + self.filename().len() + 1;
+        let end = self.options_start();
+        let data = self.buffer.as_ref();
+        match data[end - 1] {
+            0 => &data[start..end - 1],
+            _ => &data[start..end],
+        }
+    }
+
+    /// Returns `true` if the options of this packet are a sequence of complete,
+    /// NULL-terminated name/value pairs.
+    fn options_complete(&self) -> bool {
+        let options = &self.buffer.as_ref()[self.options_start()..];
+        let nulls = options.iter().filter(|b| **b == 0).count();
+        match options.last() {
+            Some(b) => *b == 0 && nulls % 2 == 0,
+            None => true,
+        }
+    }
+
+    /// Returns the options contained in this request or option acknowledgment packet.
+    pub fn options(&self) -> Options {
+        let data = self.buffer.as_ref();
+        let start = self.options_start();
+        if start < data.len() {
+            Options::parse(&data[start..])
+        } else {
+            Options::default()
+        }
+    }
+
     /// Returns the block number of this packet.
     pub fn block_number(&self) -> u16 {
         NetworkEndian::read_u16(&self.buffer.as_ref()[field::BLOCK])
     }
 
     /// Returns the data contained in this packet.
+    ///
+    /// Buffers too short to hold a block number have no data, and yield an empty slice.
     pub fn data(&self) -> &[u8] {
-        &self.buffer.as_ref()[field::DATA]
+        self.buffer.as_ref().get(field::DATA).unwrap_or(&[])
     }
 
     /// Returns the error code of this packet.
@@ -172,20 +521,42 @@ impl<T: AsRef<[u8]>> Packet<T> {
     }
 
     /// Returns the error message of this packet.
+    ///
+    /// A message missing its NULL terminator extends to the end of the packet.
     pub fn error_msg(&self) -> &str {
         let data = self.buffer.as_ref();
-        str::from_utf8(&data[field::ERROR_STRING.start..data.len() - 1]).unwrap()
+        let end = match self.find_null_byte(field::ERROR_STRING.start) {
+            Ok(end) => end - 1,
+            Err(_) => data.len(),
+        };
+        str::from_utf8(&data[field::ERROR_STRING.start..end]).unwrap()
     }
 
-    /// Returns the index immediately following the last NULL byte of this packet.
-    fn find_last_null_byte(&self) -> Result<usize> {
+    /// Returns the index immediately following the first NULL byte of this packet,
+    /// only considering the bytes from `start` onwards.
+    fn find_null_byte(&self, start: usize) -> Result<usize> {
         self.buffer
             .as_ref()
-            .iter()
-            .rposition(|b| *b == 0)
-            .map(|p| p + 1) // account for 0-based indexing
+            .get(start..)
+            .and_then(|data| data.iter().position(|b| *b == 0))
+            .map(|p| start + p + 1) // account for 0-based indexing
             .ok_or(Error::Truncated)
     }
+
+    /// Returns the index at which the options of this packet begin.
+    ///
+    /// For requests, options follow the mode string, which may extend to the end
+    /// of the packet if it is not NULL-terminated. The packet is assumed valid.
+    fn options_start(&self) -> usize {
+        match self.opcode() {
+            OpCode::Read | OpCode::Write => {
+                let mode_start = field::OPCODE.end + self.filename().len() + 1;
+                self.find_null_byte(mode_start)
+                    .unwrap_or_else(|_| self.buffer.as_ref().len())
+            }
+            _ => field::OPCODE.end,
+        }
+    }
 }
 
 impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
@@ -207,7 +578,15 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
         data[fn_start..mode_start - 1].copy_from_slice(fname.as_bytes());
         data[mode_start..mode_end].copy_from_slice(mode.as_bytes());
         data[mode_start - 1] = 0;
-        data[data.len() - 1] = 0;
+        data[mode_end] = 0;
+    }
+
+    /// Sets the options of this packet.
+    ///
+    /// For requests, the filename and mode must have been set beforehand.
+    pub fn set_options(&mut self, options: Options) {
+        let start = self.options_start();
+        options.emit(&mut self.buffer.as_mut()[start..]);
     }
 
     /// Sets the block number of this packet.
@@ -241,43 +620,125 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Repr<'a> {
     /// Read request (RRQ) packet.
-    ReadRequest { filename: &'a str, mode: Mode },
+    ReadRequest {
+        filename: &'a str,
+        mode: Mode,
+        options: Options,
+    },
     /// Write request (WRQ) packet.
-    WriteRequest { filename: &'a str, mode: Mode },
+    WriteRequest {
+        filename: &'a str,
+        mode: Mode,
+        options: Options,
+    },
     /// Data (DATA) packet.
     Data { block_num: u16, data: &'a [u8] },
     /// Acknowledgment (ACK) packet.
     Ack { block_num: u16 },
     /// Error (ERR) packet.
     Error { code: ErrorCode, msg: &'a str },
+    /// Option acknowledgment (OACK) packet.
+    OptionAck { options: Options },
 }
 
 impl<'a> Repr<'a> {
+    /// Returns the error packet to relay to the client when processing fails with `err`.
+    ///
+    /// Only parsing failures get a specific code; anything else is reported as `Undefined`.
+    /// Refusals decided by the server or its context, such as a missing file, carry their own
+    /// code and message instead.
+    pub fn error_from_net(err: Error) -> Repr<'a> {
+        let (code, msg) = match err {
+            Error::Truncated => (ErrorCode::IllegalOperation, "Packet truncated"),
+            Error::Malformed => (ErrorCode::IllegalOperation, "Malformed packet"),
+            Error::Unrecognized => (ErrorCode::IllegalOperation, "Unknown operation"),
+            _ => (ErrorCode::Undefined, "Internal error"),
+        };
+        Repr::Error { code, msg }
+    }
+
     /// Return the length of a packet that will be emitted from this high-level representation.
     pub fn buffer_len(&self) -> usize {
         match self {
-            Repr::ReadRequest { filename, mode } | Repr::WriteRequest { filename, mode } => {
-                2 + filename.len() + 1 + mode.as_str().len() + 1
+            Repr::ReadRequest {
+                filename,
+                mode,
+                options,
             }
+            | Repr::WriteRequest {
+                filename,
+                mode,
+                options,
+            } => 2 + filename.len() + 1 + mode.as_str().len() + 1 + options.buffer_len(),
             Repr::Data { data, .. } => 2 + 2 + data.len(),
             Repr::Error { msg, .. } => 2 + 2 + msg.len() + 1,
             Repr::Ack { .. } => 4,
+            Repr::OptionAck { options } => 2 + options.buffer_len(),
         }
     }
 
     /// Parse a TFTP packet and return its high-level representation.
+    ///
+    /// This is the only place where packets with an unknown opcode are rejected,
+    /// with `Err(Error::Malformed)`. The check happens before any other field is accessed.
     pub fn parse<T>(packet: &'a Packet<&T>) -> Result<Self>
     where
         T: AsRef<[u8]> + ?Sized,
     {
+        Self::parse_with(packet, ParseMode::Lenient)
+    }
+
+    /// Same as [parse], using the given parsing mode.
+    ///
+    /// In `Strict` mode, the packet length is checked again with the stricter rules,
+    /// and `Err(Error::Malformed)` is returned for unknown mode strings and incomplete options.
+    ///
+    /// [parse]: #method.parse
+    pub fn parse_with<T>(packet: &'a Packet<&T>, mode: ParseMode) -> Result<Self>
+    where
+        T: AsRef<[u8]> + ?Sized,
+    {
+        Self::parse_verbose(packet, mode).map_err(Error::from)
+    }
+
+    /// Same as [parse_with], naming the field at fault in case of error.
+    ///
+    /// [parse_with]: #method.parse_with
+    pub fn parse_verbose<T>(
+        packet: &'a Packet<&T>,
+        mode: ParseMode,
+    ) -> core::result::Result<Self, ParseError>
+    where
+        T: AsRef<[u8]> + ?Sized,
+    {
+        if mode == ParseMode::Strict {
+            if let OpCode::Unknown(_) = packet.opcode() {
+                return Err(ParseError::Malformed(Field::Opcode));
+            }
+            packet.check_len_verbose(mode)?;
+
+            if let OpCode::Read | OpCode::Write = packet.opcode() {
+                if Mode::from_name(packet.mode_str()) == Mode::Unknown {
+                    return Err(ParseError::Malformed(Field::Mode));
+                }
+            }
+            if let OpCode::Read | OpCode::Write | OpCode::OptionAck = packet.opcode() {
+                if !packet.options_complete() {
+                    return Err(ParseError::Malformed(Field::Options));
+                }
+            }
+        }
+
         Ok(match packet.opcode() {
             OpCode::Read => Repr::ReadRequest {
                 filename: packet.filename(),
                 mode: packet.mode(),
+                options: packet.options(),
             },
             OpCode::Write => Repr::WriteRequest {
                 filename: packet.filename(),
                 mode: packet.mode(),
+                options: packet.options(),
             },
             OpCode::Data => Repr::Data {
                 block_num: packet.block_number(),
@@ -290,7 +751,10 @@ impl<'a> Repr<'a> {
                 code: packet.error_code(),
                 msg: packet.error_msg(),
             },
-            OpCode::Unknown(_) => return Err(Error::Malformed),
+            OpCode::OptionAck => Repr::OptionAck {
+                options: packet.options(),
+            },
+            OpCode::Unknown(_) => return Err(ParseError::Malformed(Field::Opcode)),
         })
     }
 
@@ -300,13 +764,23 @@ impl<'a> Repr<'a> {
         T: AsRef<[u8]> + AsMut<[u8]> + ?Sized,
     {
         match *self {
-            Self::ReadRequest { filename, mode } => {
+            Self::ReadRequest {
+                filename,
+                mode,
+                options,
+            } => {
                 packet.set_opcode(OpCode::Read);
                 packet.set_filename_and_mode(filename, mode);
+                packet.set_options(options);
             }
-            Self::WriteRequest { filename, mode } => {
+            Self::WriteRequest {
+                filename,
+                mode,
+                options,
+            } => {
                 packet.set_opcode(OpCode::Write);
                 packet.set_filename_and_mode(filename, mode);
+                packet.set_options(options);
             }
             Self::Data { block_num, data } => {
                 packet.set_opcode(OpCode::Data);
@@ -322,16 +796,99 @@ impl<'a> Repr<'a> {
                 packet.set_error_code(code);
                 packet.set_error_msg(msg);
             }
+            Self::OptionAck { options } => {
+                packet.set_opcode(OpCode::OptionAck);
+                packet.set_options(options);
+            }
         };
         Ok(())
     }
 }
 
+/// Test helper building raw TFTP packets field by field.
+///
+/// Multi-byte fields are laid out by hand in network byte order, so that the output
+/// is independent of the `Packet` accessors and `Repr::emit` it is checked against.
+#[cfg(test)]
+pub(crate) struct PacketBuilder {
+    bytes: std::vec::Vec<u8>,
+}
+
+#[cfg(test)]
+impl PacketBuilder {
+    /// Starts a packet with an arbitrary opcode.
+    pub fn opcode(opcode: u16) -> PacketBuilder {
+        PacketBuilder {
+            bytes: opcode.to_be_bytes().to_vec(),
+        }
+    }
+
+    /// Starts a read request.
+    pub fn rrq(filename: &str, mode: Mode) -> PacketBuilder {
+        PacketBuilder::opcode(1)
+            .string(filename)
+            .string(mode.as_str())
+    }
+
+    /// Starts a write request.
+    pub fn wrq(filename: &str, mode: Mode) -> PacketBuilder {
+        PacketBuilder::opcode(2)
+            .string(filename)
+            .string(mode.as_str())
+    }
+
+    /// Builds a data packet.
+    pub fn data(block_num: u16, data: &[u8]) -> PacketBuilder {
+        PacketBuilder::opcode(3).u16(block_num).bytes(data)
+    }
+
+    /// Builds an acknowledgment packet.
+    pub fn ack(block_num: u16) -> PacketBuilder {
+        PacketBuilder::opcode(4).u16(block_num)
+    }
+
+    /// Builds an error packet.
+    pub fn error(code: u16, msg: &str) -> PacketBuilder {
+        PacketBuilder::opcode(5).u16(code).string(msg)
+    }
+
+    /// Starts an option acknowledgment packet.
+    pub fn oack() -> PacketBuilder {
+        PacketBuilder::opcode(6)
+    }
+
+    /// Appends an option name/value pair.
+    pub fn option(self, name: &str, value: &str) -> PacketBuilder {
+        self.string(name).string(value)
+    }
+
+    /// Appends a NUL-terminated string.
+    pub fn string(self, s: &str) -> PacketBuilder {
+        self.bytes(s.as_bytes()).bytes(&[0])
+    }
+
+    /// Appends a 16-bit field in network byte order.
+    pub fn u16(self, value: u16) -> PacketBuilder {
+        self.bytes(&[(value >> 8) as u8, value as u8])
+    }
+
+    /// Appends raw bytes.
+    pub fn bytes(mut self, bytes: &[u8]) -> PacketBuilder {
+        self.bytes.extend_from_slice(bytes);
+        self
+    }
+
+    /// Returns the packet contents.
+    pub fn build(self) -> std::vec::Vec<u8> {
+        self.bytes
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
-    use std::vec;
+    use std::{vec, vec::Vec};
 
     static RRQ_BYTES: [u8; 20] = [
         0x00, 0x01, 0x72, 0x66, 0x63, 0x31, 0x33, 0x35, 0x30, 0x2e, 0x74, 0x78, 0x74, 0x00, 0x6f,
@@ -385,6 +942,21 @@ mod test {
 
     static ERR_BYTES: [u8; 10] = [0x00, 0x05, 0x00, 0x06, 0x45, 0x72, 0x72, 0x6f, 0x72, 0x00];
 
+    static RRQ_BLKSIZE_BYTES: [u8; 32] = [
+        0x00, 0x01, 0x72, 0x66, 0x63, 0x31, 0x33, 0x35, 0x30, 0x2e, 0x74, 0x78, 0x74, 0x00, 0x6f,
+        0x63, 0x74, 0x65, 0x74, 0x00, 0x62, 0x6c, 0x6b, 0x73, 0x69, 0x7a, 0x65, 0x00, 0x31, 0x34,
+        0x32, 0x00,
+    ];
+
+    static OACK_BLKSIZE_BYTES: [u8; 14] = [
+        0x00, 0x06, 0x62, 0x6c, 0x6b, 0x73, 0x69, 0x7a, 0x65, 0x00, 0x32, 0x39, 0x36, 0x00,
+    ];
+
+    static OACK_BLKSIZE_TSIZE_BYTES: [u8; 27] = [
+        0x00, 0x06, 0x62, 0x6c, 0x6b, 0x73, 0x69, 0x7a, 0x65, 0x00, 0x31, 0x30, 0x32, 0x34, 0x00,
+        0x74, 0x73, 0x69, 0x7a, 0x65, 0x00, 0x37, 0x34, 0x32, 0x34, 0x30, 0x00,
+    ];
+
     #[test]
     fn test_deconstruct() {
         let packet = Packet::new_unchecked(&RRQ_BYTES[..]);
@@ -449,6 +1021,7 @@ mod test {
                 Repr::ReadRequest {
                     filename: "rfc1350.txt",
                     mode: Mode::Octet,
+                    options: Options::default(),
                 },
                 &RRQ_BYTES[..],
             ),
@@ -456,6 +1029,7 @@ mod test {
                 Repr::WriteRequest {
                     filename: "rfc1350.txt",
                     mode: Mode::Octet,
+                    options: Options::default(),
                 },
                 &WRQ_BYTES[..],
             ),
@@ -483,6 +1057,495 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_options() {
+        for (repr, bytes) in vec![
+            (
+                Repr::ReadRequest {
+                    filename: "rfc1350.txt",
+                    mode: Mode::Octet,
+                    options: Options {
+                        block_size: Some(142),
+                        ..Options::default()
+                    },
+                },
+                &RRQ_BLKSIZE_BYTES[..],
+            ),
+            (
+                Repr::OptionAck {
+                    options: Options {
+                        block_size: Some(296),
+                        ..Options::default()
+                    },
+                },
+                &OACK_BLKSIZE_BYTES[..],
+            ),
+            (
+                Repr::OptionAck {
+                    options: Options {
+                        block_size: Some(1024),
+                        transfer_size: Some(74_240),
+                        ..Options::default()
+                    },
+                },
+                &OACK_BLKSIZE_TSIZE_BYTES[..],
+            ),
+        ]
+        .into_iter()
+        {
+            assert_eq!(repr.buffer_len(), bytes.len());
+
+            let packet = Packet::new_checked(bytes).unwrap();
+            assert_eq!(Repr::parse(&packet).unwrap(), repr);
+
+            let mut buff = vec![0xa5; bytes.len()];
+            let mut packet = Packet::new_unchecked(&mut buff);
+            repr.emit(&mut packet).unwrap();
+            assert_eq!(&packet.buffer[..], bytes);
+        }
+    }
+
+    #[test]
+    fn test_options_ignored() {
+        // Unknown names, malformed and out-of-range values, options without a value
+        let bytes =
+            b"\x00\x01file\x00octet\x00foo\x001\x00blksize\x00abc\x00BLKSIZE\x0070000\x00blksize";
+        let packet = Packet::new_checked(&bytes[..]).unwrap();
+        assert_eq!(packet.options(), Options::default());
+
+        // Option names are case-insensitive
+        let bytes = b"\x00\x01file\x00octet\x00BlkSize\x001024\x00";
+        let packet = Packet::new_checked(&bytes[..]).unwrap();
+        assert_eq!(packet.options().block_size, Some(1024));
+    }
+
+    #[test]
+    fn test_builder() {
+        assert_eq!(
+            PacketBuilder::rrq("rfc1350.txt", Mode::Octet).build(),
+            &RRQ_BYTES[..]
+        );
+        assert_eq!(
+            PacketBuilder::wrq("rfc1350.txt", Mode::Octet).build(),
+            &WRQ_BYTES[..]
+        );
+        assert_eq!(
+            PacketBuilder::data(1, &DATA_BYTES[4..]).build(),
+            &DATA_BYTES[..]
+        );
+        assert_eq!(PacketBuilder::ack(9).build(), &ACK_BYTES[..]);
+        assert_eq!(PacketBuilder::error(6, "Error").build(), &ERR_BYTES[..]);
+        assert_eq!(
+            PacketBuilder::rrq("rfc1350.txt", Mode::Octet)
+                .option("blksize", "142")
+                .build(),
+            &RRQ_BLKSIZE_BYTES[..]
+        );
+        assert_eq!(
+            PacketBuilder::oack().option("blksize", "296").build(),
+            &OACK_BLKSIZE_BYTES[..]
+        );
+        assert_eq!(
+            PacketBuilder::oack()
+                .option("blksize", "1024")
+                .option("tsize", "74240")
+                .build(),
+            &OACK_BLKSIZE_TSIZE_BYTES[..]
+        );
+
+        // Fields wider than a byte must come out in network byte order
+        let options = Options {
+            block_size: Some(1432),
+            transfer_size: Some(0x0102_0304),
+            offset: Some(0xffff_ffff),
+        };
+        for (repr, bytes) in vec![
+            (
+                Repr::WriteRequest {
+                    filename: "file",
+                    mode: Mode::NetAscii,
+                    options,
+                },
+                PacketBuilder::wrq("file", Mode::NetAscii)
+                    .option("blksize", "1432")
+                    .option("tsize", "16909060")
+                    .option("offset", "4294967295")
+                    .build(),
+            ),
+            (
+                Repr::Data {
+                    block_num: 0x1234,
+                    data: &[0xde, 0xad],
+                },
+                PacketBuilder::data(0x1234, &[0xde, 0xad]).build(),
+            ),
+            (
+                Repr::Ack { block_num: 0xff00 },
+                PacketBuilder::ack(0xff00).build(),
+            ),
+            (
+                Repr::Error {
+                    code: ErrorCode::Unknown(0x0a0b),
+                    msg: "",
+                },
+                PacketBuilder::error(0x0a0b, "").build(),
+            ),
+            (
+                Repr::OptionAck { options },
+                PacketBuilder::oack()
+                    .option("blksize", "1432")
+                    .option("tsize", "16909060")
+                    .option("offset", "4294967295")
+                    .build(),
+            ),
+        ]
+        .into_iter()
+        {
+            let mut buff = vec![0xa5; repr.buffer_len()];
+            let mut packet = Packet::new_unchecked(&mut buff);
+            repr.emit(&mut packet).unwrap();
+            assert_eq!(&packet.buffer[..], &bytes[..]);
+        }
+    }
+
+    #[test]
+    fn test_error_from_net() {
+        for (err, code, msg) in vec![
+            (
+                Error::Truncated,
+                ErrorCode::IllegalOperation,
+                "Packet truncated",
+            ),
+            (
+                Error::Malformed,
+                ErrorCode::IllegalOperation,
+                "Malformed packet",
+            ),
+            // Running out of buffers says nothing about the disk
+            (Error::Exhausted, ErrorCode::Undefined, "Internal error"),
+            (Error::Illegal, ErrorCode::Undefined, "Internal error"),
+        ]
+        .into_iter()
+        {
+            let repr = Repr::error_from_net(err);
+            assert_eq!(repr, Repr::Error { code, msg });
+
+            let mut bytes = vec![0xa5; repr.buffer_len()];
+            repr.emit(&mut Packet::new_unchecked(&mut bytes)).unwrap();
+            assert_eq!(bytes, PacketBuilder::error(code.into(), msg).build());
+        }
+    }
+
+    #[test]
+    fn test_all_variants() {
+        assert_eq!(Mode::all(), &[Mode::NetAscii, Mode::Octet, Mode::Mail]);
+        for mode in Mode::all() {
+            assert_eq!(Mode::from(mode.as_str().as_bytes()[0]), *mode);
+            assert_eq!(Mode::from_name(mode.as_str().as_bytes()), *mode);
+        }
+
+        let opcodes: Vec<_> = (0..=7).map(OpCode::from).collect();
+        assert_eq!(OpCode::all(), &opcodes[1..7]);
+
+        let codes: Vec<_> = (0..=9).map(ErrorCode::from).collect();
+        assert_eq!(ErrorCode::all(), &codes[..9]);
+        assert_eq!(codes[9], ErrorCode::Unknown(9));
+    }
+
+    #[test]
+    fn test_unknown_opcode() {
+        for opcode in [0x0000, 0x0007, 0xffff].iter() {
+            let mut bytes = [0; 4];
+            NetworkEndian::write_u16(&mut bytes[..2], *opcode);
+
+            let packet = Packet::new_checked(&bytes[..2]).unwrap();
+            assert_eq!(packet.opcode(), OpCode::Unknown(*opcode));
+            assert_eq!(Repr::parse(&packet), Err(Error::Malformed));
+
+            let packet = Packet::new_checked(&bytes[..]).unwrap();
+            assert_eq!(Repr::parse(&packet), Err(Error::Malformed));
+        }
+    }
+
+    #[test]
+    fn test_truncated_request() {
+        // The zero in the opcode must not be mistaken for a terminator
+        let packet = Packet::new_unchecked(&RRQ_BYTES[..2]);
+        assert_eq!(
+            packet.check_len_with(ParseMode::Lenient),
+            Err(Error::Truncated)
+        );
+
+        // Same goes for the high byte of the error code
+        let packet = Packet::new_unchecked(&ERR_BYTES[..4]);
+        assert_eq!(
+            packet.check_len_with(ParseMode::Strict),
+            Err(Error::Truncated)
+        );
+        assert_eq!(packet.error_msg(), "");
+    }
+
+    #[test]
+    fn test_data_length() {
+        let bytes = PacketBuilder::data(7, &[0xaa]).build();
+
+        // A DATA packet without a full block number is truncated
+        let packet = Packet::new_unchecked(&bytes[..3]);
+        assert_eq!(
+            packet.check_len_with(ParseMode::Lenient),
+            Err(Error::Truncated)
+        );
+        assert_eq!(
+            Packet::new_checked(&bytes[..3]).err(),
+            Some(Error::Truncated)
+        );
+
+        // ...while an empty block is a valid final block
+        let packet = Packet::new_checked(&bytes[..4]).unwrap();
+        assert_eq!(
+            Repr::parse(&packet),
+            Ok(Repr::Data {
+                block_num: 7,
+                data: &[]
+            })
+        );
+
+        let packet = Packet::new_checked(&bytes[..]).unwrap();
+        assert_eq!(
+            Repr::parse(&packet),
+            Ok(Repr::Data {
+                block_num: 7,
+                data: &[0xaa]
+            })
+        );
+    }
+
+    #[test]
+    fn test_mode_from_name() {
+        for name in ["octet", "OCTET", "Octet", "oCtEt"].iter() {
+            assert_eq!(Mode::from_name(name.as_bytes()), Mode::Octet);
+        }
+        assert_eq!(Mode::from_name(b"NetASCII"), Mode::NetAscii);
+        assert_eq!(Mode::from_name(b"MAIL"), Mode::Mail);
+
+        // The whole name must match
+        for name in ["", "o", "oct", "octets", "octet\0", "binary"].iter() {
+            assert_eq!(Mode::from_name(name.as_bytes()), Mode::Unknown, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_octet_alias() {
+        assert_eq!(Mode::octet_alias(b"binary"), Some("binary"));
+        assert_eq!(Mode::octet_alias(b"IMAGE"), Some("image"));
+        for name in ["", "octet", "bin", "images"].iter() {
+            assert_eq!(Mode::octet_alias(name.as_bytes()), None, "{}", name);
+        }
+
+        // Aliases are parsed as unknown modes, in lenient parsing too
+        for mode in ["binary", "image"].iter() {
+            let bytes = PacketBuilder::opcode(1).string("file").string(mode).build();
+            let packet = Packet::new_checked(&bytes[..]).unwrap();
+            assert_eq!(packet.mode(), Mode::Unknown);
+            assert_eq!(packet.mode_str(), mode.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_data_short_buffer() {
+        let bytes = PacketBuilder::data(7, &[0xaa]).build();
+
+        // Unchecked packets too short for a payload don't panic
+        for len in 0..=4 {
+            assert_eq!(Packet::new_unchecked(&bytes[..len]).data(), &[]);
+        }
+        assert_eq!(Packet::new_unchecked(&bytes[..]).data(), &[0xaa]);
+    }
+
+    #[test]
+    fn test_parse_mode_missing_nul() {
+        // The mode string runs to the end of the packet
+        let bytes = PacketBuilder::rrq("file", Mode::Octet).build();
+        let bytes = &bytes[..bytes.len() - 1];
+
+        let packet = Packet::new_checked_with(bytes, ParseMode::Lenient).unwrap();
+        assert_eq!(
+            Repr::parse_with(&packet, ParseMode::Lenient),
+            Ok(Repr::ReadRequest {
+                filename: "file",
+                mode: Mode::Octet,
+                options: Options::default(),
+            })
+        );
+        assert_eq!(
+            Repr::parse_with(&packet, ParseMode::Strict),
+            Err(Error::Truncated)
+        );
+        assert_eq!(
+            Packet::new_checked_with(bytes, ParseMode::Strict),
+            Err(Error::Truncated)
+        );
+
+        // Same goes for the last option value
+        let bytes = PacketBuilder::rrq("file", Mode::Octet)
+            .option("blksize", "1024")
+            .build();
+        let packet = Packet::new_checked(&bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(packet.options().block_size, Some(1024));
+        assert_eq!(
+            Repr::parse_with(&packet, ParseMode::Strict),
+            Err(Error::Malformed)
+        );
+
+        // And for the error message
+        let bytes = &ERR_BYTES[..ERR_BYTES.len() - 1];
+        let packet = Packet::new_checked(bytes).unwrap();
+        assert_eq!(
+            Repr::parse(&packet),
+            Ok(Repr::Error {
+                code: ErrorCode::FileExists,
+                msg: "Error",
+            })
+        );
+        assert_eq!(
+            Packet::new_checked_with(bytes, ParseMode::Strict),
+            Err(Error::Truncated)
+        );
+
+        // The terminator is always emitted
+        let repr = Repr::parse(&packet).unwrap();
+        let mut buf = vec![0; repr.buffer_len()];
+        repr.emit(&mut Packet::new_unchecked(&mut buf)).unwrap();
+        assert_eq!(buf, &ERR_BYTES[..]);
+    }
+
+    #[test]
+    fn test_parse_mode_deviations() {
+        // Padding after the error message
+        let bytes = PacketBuilder::error(1, "File not found")
+            .bytes(&[0, 0])
+            .build();
+        let packet = Packet::new_checked(&bytes[..]).unwrap();
+        assert_eq!(packet.error_msg(), "File not found");
+        assert_eq!(
+            Packet::new_checked_with(&bytes[..], ParseMode::Strict),
+            Err(Error::Malformed)
+        );
+
+        // Mode string only recognized from its first character
+        let bytes = PacketBuilder::opcode(1)
+            .string("file")
+            .string("oct")
+            .build();
+        let packet = Packet::new_checked(&bytes[..]).unwrap();
+        assert_eq!(packet.mode(), Mode::Octet);
+        assert_eq!(
+            Repr::parse_with(&packet, ParseMode::Strict),
+            Err(Error::Malformed)
+        );
+
+        // Option name without value
+        let bytes = PacketBuilder::rrq("file", Mode::NetAscii)
+            .string("blksize")
+            .build();
+        let packet = Packet::new_checked(&bytes[..]).unwrap();
+        assert!(Repr::parse(&packet).is_ok());
+        assert_eq!(
+            Repr::parse_with(&packet, ParseMode::Strict),
+            Err(Error::Malformed)
+        );
+
+        // Well-formed packets are accepted in both modes
+        let bytes = PacketBuilder::wrq("file", Mode::Octet)
+            .option("tsize", "0")
+            .build();
+        let packet = Packet::new_checked_with(&bytes[..], ParseMode::Strict).unwrap();
+        assert_eq!(
+            Repr::parse_with(&packet, ParseMode::Strict),
+            Repr::parse(&packet)
+        );
+    }
+
+    #[test]
+    fn test_parse_verbose() {
+        use self::ParseError::*;
+
+        let check = |bytes: &[u8], mode| Packet::new_unchecked(bytes).check_len_verbose(mode);
+        let parse = |bytes: &[u8], mode| {
+            let packet = Packet::new_unchecked(bytes);
+            Repr::parse_verbose(&packet, mode).map(|_| ())
+        };
+
+        assert_eq!(
+            check(&[0], ParseMode::Lenient),
+            Err(Truncated(Field::Opcode))
+        );
+        assert_eq!(
+            check(&RRQ_BYTES[..6], ParseMode::Lenient),
+            Err(Truncated(Field::Filename))
+        );
+        assert_eq!(
+            check(&RRQ_BYTES[..RRQ_BYTES.len() - 1], ParseMode::Strict),
+            Err(Truncated(Field::Mode))
+        );
+        assert_eq!(
+            check(&[0, 5, 0], ParseMode::Lenient),
+            Err(Truncated(Field::ErrorCode))
+        );
+        assert_eq!(
+            check(&ERR_BYTES[..6], ParseMode::Strict),
+            Err(Truncated(Field::ErrorMessage))
+        );
+        assert_eq!(
+            check(&[0, 4, 0], ParseMode::Lenient),
+            Err(Truncated(Field::BlockNumber))
+        );
+
+        // Strings that are not valid UTF-8
+        assert_eq!(
+            check(&[0, 1, 0xff, 0, b'o', 0], ParseMode::Lenient),
+            Err(Malformed(Field::Filename))
+        );
+        assert_eq!(
+            check(&[0, 5, 0, 1, 0xc3, 0], ParseMode::Lenient),
+            Err(Malformed(Field::ErrorMessage))
+        );
+        assert_eq!(
+            check(&[0, 5, 0, 1, 0xc3], ParseMode::Lenient),
+            Err(Malformed(Field::ErrorMessage))
+        );
+
+        let bytes = PacketBuilder::rrq("file", Mode::Octet)
+            .string("blksize")
+            .build();
+        assert_eq!(parse(&bytes, ParseMode::Lenient), Ok(()));
+        assert_eq!(
+            parse(&bytes, ParseMode::Strict),
+            Err(Malformed(Field::Options))
+        );
+
+        let bytes = PacketBuilder::opcode(1)
+            .string("file")
+            .string("oct")
+            .build();
+        assert_eq!(
+            parse(&bytes, ParseMode::Strict),
+            Err(Malformed(Field::Mode))
+        );
+        assert_eq!(
+            parse(&[0, 9], ParseMode::Lenient),
+            Err(Malformed(Field::Opcode))
+        );
+
+        // The regular functions report the same error, without the field
+        let packet = Packet::new_unchecked(&ERR_BYTES[..6]);
+        assert_eq!(
+            packet.check_len_with(ParseMode::Strict),
+            Err(Error::Truncated)
+        );
+        assert_eq!(Truncated(Field::ErrorMessage).field(), Field::ErrorMessage);
+    }
+
     #[test]
     fn test_emit() {
         for (repr, bytes) in vec![
@@ -490,6 +1553,7 @@ mod test {
                 Repr::ReadRequest {
                     filename: "rfc1350.txt",
                     mode: Mode::Octet,
+                    options: Options::default(),
                 },
                 &RRQ_BYTES[..],
             ),
@@ -497,6 +1561,7 @@ mod test {
                 Repr::WriteRequest {
                     filename: "rfc1350.txt",
                     mode: Mode::Octet,
+                    options: Options::default(),
                 },
                 &WRQ_BYTES[..],
             ),
@@ -0,0 +1,214 @@
+/*! End-to-end test of the TFTP server, on top of two smoltcp interfaces.
+
+The interfaces are wired together by an in-process link. The server runs on one of them,
+while the other hosts a minimal client reading a file over multiple blocks.
+
+This test needs the standard library, so it only runs when explicitly requested with:
+
+```no_rust
+cargo test --test loopback --features std
+```
+*/
+
+use smolapps::{
+    net,
+    net::iface::{EthernetInterface, EthernetInterfaceBuilder, NeighborCache},
+    net::phy::{self, Device, DeviceCapabilities},
+    net::socket::{SocketSet, UdpPacketMetadata, UdpSocket, UdpSocketBuffer},
+    net::time::{Duration, Instant},
+    net::wire::{EthernetAddress, IpAddress, IpCidr, IpEndpoint},
+    tftp::{Context, Handle, Server, DEFAULT_PORT},
+};
+use std::{cell::RefCell, collections::BTreeMap, collections::VecDeque, rc::Rc};
+
+type Queue = Rc<RefCell<VecDeque<Vec<u8>>>>;
+
+/// One end of a point-to-point Ethernet link.
+struct Link {
+    rx: Queue,
+    tx: Queue,
+}
+
+impl Link {
+    /// Creates the two ends of a link.
+    fn pair() -> (Link, Link) {
+        let (a, b) = (Queue::default(), Queue::default());
+        (
+            Link {
+                rx: a.clone(),
+                tx: b.clone(),
+            },
+            Link { rx: b, tx: a },
+        )
+    }
+}
+
+impl<'a> Device<'a> for Link {
+    type RxToken = RxToken;
+    type TxToken = TxToken;
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let buffer = self.rx.borrow_mut().pop_front()?;
+        Some((
+            RxToken { buffer },
+            TxToken {
+                queue: self.tx.clone(),
+            },
+        ))
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        Some(TxToken {
+            queue: self.tx.clone(),
+        })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = 1514;
+        caps
+    }
+}
+
+struct RxToken {
+    buffer: Vec<u8>,
+}
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> net::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> net::Result<R>,
+    {
+        f(&mut self.buffer)
+    }
+}
+
+struct TxToken {
+    queue: Queue,
+}
+
+impl phy::TxToken for TxToken {
+    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> net::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> net::Result<R>,
+    {
+        let mut buffer = vec![0; len];
+        let result = f(&mut buffer);
+        self.queue.borrow_mut().push_back(buffer);
+        result
+    }
+}
+
+/// Serves a single in-memory file, named `data.bin`.
+struct Files(Vec<u8>);
+
+impl Context for Files {
+    type Handle = File;
+
+    fn open(&mut self, filename: &str, write_mode: bool) -> Result<File, ()> {
+        if filename == "data.bin" && !write_mode {
+            Ok(File(self.0.clone(), 0))
+        } else {
+            Err(())
+        }
+    }
+
+    fn close(&mut self, _: File) {}
+}
+
+struct File(Vec<u8>, usize);
+
+impl Handle for File {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        let len = buf.len().min(self.0.len() - self.1);
+        buf[..len].copy_from_slice(&self.0[self.1..self.1 + len]);
+        self.1 += len;
+        Ok(len)
+    }
+
+    fn write(&mut self, _: &[u8]) -> Result<usize, ()> {
+        Err(())
+    }
+}
+
+fn iface(link: Link, host: u8) -> EthernetInterface<'static, 'static, 'static, Link> {
+    EthernetInterfaceBuilder::new(link)
+        .ethernet_addr(EthernetAddress([0x02, 0x00, 0x00, 0x00, 0x00, host]))
+        .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+        .ip_addrs(vec![IpCidr::new(IpAddress::v4(192, 168, 69, host), 24)])
+        .finalize()
+}
+
+fn udp_buffer() -> UdpSocketBuffer<'static, 'static> {
+    UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 2048])
+}
+
+#[test]
+fn test_read_over_loopback() {
+    let contents: Vec<u8> = (0..3 * 512 + 100).map(|i| (i % 251) as u8).collect();
+    let mut files = Files(contents.clone());
+
+    let (server_link, client_link) = Link::pair();
+    let mut server_iface = iface(server_link, 1);
+    let mut client_iface = iface(client_link, 2);
+
+    let mut server_sockets = SocketSet::new(vec![]);
+    let mut client_sockets = SocketSet::new(vec![]);
+
+    let mut now = Instant::from_millis(0);
+    let mut server = Server::new(&mut server_sockets, udp_buffer(), udp_buffer(), now);
+    let mut transfers = vec![].into();
+
+    let mut socket = UdpSocket::new(udp_buffer(), udp_buffer());
+    socket.bind(4242).unwrap();
+    let client = client_sockets.add(socket);
+
+    let server_ep = IpEndpoint::new(IpAddress::v4(192, 168, 69, 1), DEFAULT_PORT);
+    let mut rrq = vec![0, 1];
+    rrq.extend_from_slice(b"data.bin\0octet\0");
+    client_sockets
+        .get::<UdpSocket>(client)
+        .send_slice(&rrq, server_ep)
+        .unwrap();
+
+    let mut received = Vec::new();
+    let mut done = false;
+
+    while !done {
+        assert!(now < Instant::from_secs(10), "transfer did not complete");
+
+        client_iface.poll(&mut client_sockets, now).ok();
+        server_iface.poll(&mut server_sockets, now).ok();
+        server
+            .serve(&mut server_sockets, &mut files, &mut transfers, now)
+            .unwrap();
+        server_iface.poll(&mut server_sockets, now).ok();
+
+        let mut socket = client_sockets.get::<UdpSocket>(client);
+        while let Ok((packet, ep)) = socket.recv() {
+            assert_eq!(&packet[..2], &[0, 3], "expected a DATA packet");
+            let block = u16::from_be_bytes([packet[2], packet[3]]);
+            let data = &packet[4..];
+
+            if usize::from(block) == received.len() / 512 + 1 {
+                received.extend_from_slice(data);
+                done = data.len() < 512;
+            }
+
+            let ack = [0, 4, packet[2], packet[3]];
+            socket.send_slice(&ack, ep).unwrap();
+        }
+
+        now += Duration::from_millis(10);
+    }
+
+    // Deliver the final ACK, so that the server can wrap up the transfer
+    client_iface.poll(&mut client_sockets, now).ok();
+    server_iface.poll(&mut server_sockets, now).ok();
+    server
+        .serve(&mut server_sockets, &mut files, &mut transfers, now)
+        .unwrap();
+
+    assert_eq!(received, contents);
+    assert!(server.is_idle(&transfers));
+}